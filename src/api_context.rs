@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+use lambda_http::request::RequestContext;
+use lambda_http::{Request, RequestExt};
+use serde_json::Value;
+
+use crate::{CustomEvent, LambdaError};
+
+/// What API Gateway tells a proxy integration about the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiGatewayContext {
+    pub apigw_request_id: String,
+    pub source_ip: String,
+    pub user_agent: Option<String>,
+    // Cognito user pool claims; only string-valued ones are kept
+    pub claims: BTreeMap<String, String>,
+}
+
+/// `None` for anything but a REST API (v1) proxy request.
+pub fn extract_api_context(req: &Request) -> Option<ApiGatewayContext> {
+    let context = match req.request_context() {
+        RequestContext::ApiGateway(context) => context,
+        _ => return None,
+    };
+    let claims = match context.authorizer.get("claims") {
+        Some(Value::Object(claims)) => claims
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+            .collect(),
+        _ => BTreeMap::new(),
+    };
+    Some(ApiGatewayContext {
+        apigw_request_id: context.request_id,
+        source_ip: context.identity.source_ip,
+        user_agent: context.identity.user_agent,
+        claims,
+    })
+}
+
+/// A proxy request carries its own `requestContext` and `httpMethod`, which a
+/// `CustomEvent` never has.
+pub fn is_proxy_request(event: &Value) -> bool {
+    event.get("requestContext").is_some() && event.get("httpMethod").is_some()
+}
+
+/// The `CustomEvent` in a proxy request's body, with the context it came with.
+pub fn parse_proxy_request(
+    event: Value,
+) -> Result<(CustomEvent, Option<ApiGatewayContext>), LambdaError> {
+    let request = lambda_http::request::from_str(&event.to_string())
+        .map_err(|err| LambdaError::MalformedEvent(err.to_string()))?;
+    let api_context = extract_api_context(&request);
+    let request: ApiGatewayProxyRequest = serde_json::from_value(event)
+        .map_err(|err| LambdaError::MalformedEvent(err.to_string()))?;
+    Ok((CustomEvent::try_from(request)?, api_context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_proxy_request() -> Value {
+        serde_json::json!({
+            "resource": "/",
+            "path": "/",
+            "httpMethod": "POST",
+            "headers": { "Content-Type": "application/json" },
+            "multiValueHeaders": { "Content-Type": ["application/json"] },
+            "queryStringParameters": null,
+            "multiValueQueryStringParameters": null,
+            "pathParameters": null,
+            "stageVariables": null,
+            "requestContext": {
+                "accountId": "123456789012",
+                "resourceId": "us4z18",
+                "stage": "dev",
+                "requestId": "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9",
+                "identity": {
+                    "sourceIp": "192.168.100.1",
+                    "userAgent": "curl/7.64.1"
+                },
+                "authorizer": {
+                    "claims": { "sub": "user-1", "email": "me@example.com", "exp": 1 }
+                },
+                "resourcePath": "/",
+                "httpMethod": "POST",
+                "apiId": "wt6mne2s9k"
+            },
+            "body": r#"{"textBody":"Firstname"}"#,
+            "isBase64Encoded": false
+        })
+    }
+
+    #[test]
+    fn can_extract_api_context() {
+        let request = lambda_http::request::from_str(&get_proxy_request().to_string())
+            .expect("expected Ok(_) value");
+        let context = extract_api_context(&request).expect("expected Some(_) value");
+        assert_eq!(
+            context.apigw_request_id,
+            "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9"
+        );
+        assert_eq!(context.source_ip, "192.168.100.1");
+        assert_eq!(context.user_agent, Some("curl/7.64.1".to_string()));
+        let claims: Vec<(&str, &str)> = context
+            .claims
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(claims, vec![("email", "me@example.com"), ("sub", "user-1")]);
+    }
+
+    #[test]
+    fn can_parse_proxy_request() {
+        let event = get_proxy_request();
+        assert!(is_proxy_request(&event));
+        assert!(!is_proxy_request(
+            &serde_json::json!({ "textBody": "Firstname" })
+        ));
+        let (event, context) = parse_proxy_request(event).expect("expected Ok(_) value");
+        assert_eq!(event.text_body, Some("Firstname".to_string()));
+        assert_eq!(
+            context.map(|context| context.source_ip),
+            Some("192.168.100.1".to_string())
+        );
+    }
+}
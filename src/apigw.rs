@@ -0,0 +1,180 @@
+use std::convert::TryFrom;
+
+use aws_lambda_events::event::apigw::ApiGatewayProxyRequest;
+
+use crate::multipart::{is_multipart, parse_multipart, UploadPart};
+use crate::{parse_custom_event_streaming, CustomEvent, LambdaError};
+
+/// For a Lambda proxy integration, where the `CustomEvent` JSON arrives as the
+/// request body. The deployed `lambda` integration maps the body itself, see serverless.yml.
+/// A `multipart/form-data` body is read as a form upload instead.
+impl TryFrom<ApiGatewayProxyRequest> for CustomEvent {
+    type Error = LambdaError;
+
+    fn try_from(request: ApiGatewayProxyRequest) -> Result<CustomEvent, LambdaError> {
+        let content_type = request
+            .headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = request.body.unwrap_or_default();
+        let body = if request.is_base64_encoded.unwrap_or(false) {
+            base64::decode(&body).map_err(|err| LambdaError::MalformedEvent(err.to_string()))?
+        } else {
+            body.into_bytes()
+        };
+        match content_type {
+            Some(content_type) if is_multipart(&content_type) => {
+                get_multipart_event(parse_multipart(&body, &content_type)?)
+            }
+            _ => parse_custom_event_streaming(body.as_slice()),
+        }
+    }
+}
+
+// the `text` part is the body and `filename` names it, falling back to the
+// file name of the `text` part; any other field is rejected like in JSON
+fn get_multipart_event(parts: Vec<UploadPart>) -> Result<CustomEvent, LambdaError> {
+    let mut text_body = None;
+    let mut filename = None;
+    let mut file_name = None;
+    for part in parts {
+        match part.name.as_str() {
+            "text" => {
+                file_name = part.filename;
+                text_body = Some(get_field_text("text", part.data)?);
+            }
+            "filename" => filename = Some(get_field_text("filename", part.data)?),
+            name => {
+                return Err(LambdaError::UnknownFields(format!(
+                    "unknown field `{}`, expected `text` or `filename`",
+                    name
+                )))
+            }
+        }
+    }
+    Ok(CustomEvent {
+        text_body,
+        filename: filename.or(file_name),
+        ..Default::default()
+    })
+}
+
+fn get_field_text(name: &str, data: Vec<u8>) -> Result<String, LambdaError> {
+    String::from_utf8(data)
+        .map_err(|_| LambdaError::MalformedEvent(format!("{}: not valid UTF-8", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // trimmed from the aws_lambda_events example-apigw-request.json fixture
+    fn get_request(body: &str, is_base64_encoded: bool) -> ApiGatewayProxyRequest {
+        get_request_with_content_type(body, is_base64_encoded, "application/json")
+    }
+
+    fn get_request_with_content_type(
+        body: &str,
+        is_base64_encoded: bool,
+        content_type: &str,
+    ) -> ApiGatewayProxyRequest {
+        serde_json::from_value(serde_json::json!({
+            "resource": "/",
+            "path": "/",
+            "httpMethod": "POST",
+            "headers": { "Content-Type": content_type },
+            "multiValueHeaders": { "Content-Type": [content_type] },
+            "queryStringParameters": null,
+            "multiValueQueryStringParameters": null,
+            "pathParameters": null,
+            "stageVariables": null,
+            "requestContext": {
+                "accountId": "123456789012",
+                "resourceId": "us4z18",
+                "stage": "dev",
+                "requestId": "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9",
+                "identity": {
+                    "sourceIp": "192.168.100.1",
+                    "userAgent": "curl/7.64.1"
+                },
+                "resourcePath": "/",
+                "httpMethod": "POST",
+                "apiId": "wt6mne2s9k"
+            },
+            "body": body,
+            "isBase64Encoded": is_base64_encoded
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn can_convert_proxy_request() {
+        let request = get_request(r#"{"key":"a.txt","textBody":"Firstname"}"#, false);
+        let event = CustomEvent::try_from(request).expect("expected Ok(_) value");
+        assert_eq!(event.key, Some("a.txt".to_string()));
+        assert_eq!(event.text_body, Some("Firstname".to_string()));
+    }
+
+    #[test]
+    fn can_convert_base64_proxy_request() {
+        let body = base64::encode(r#"{"textBody":"名前"}"#);
+        let event = CustomEvent::try_from(get_request(&body, true)).expect("expected Ok(_) value");
+        assert_eq!(event.text_body, Some("名前".to_string()));
+    }
+
+    #[test]
+    fn can_reject_invalid_proxy_request_body() {
+        let result = CustomEvent::try_from(get_request(r#"{"textBody":"#, false));
+        assert!(matches!(result, Err(LambdaError::MalformedEvent(_))));
+        let result = CustomEvent::try_from(get_request("not base64!", true));
+        assert!(matches!(result, Err(LambdaError::MalformedEvent(_))));
+    }
+
+    const MULTIPART_CONTENT_TYPE: &str = "multipart/form-data; boundary=X-BOUNDARY";
+
+    #[test]
+    fn can_convert_multipart_proxy_request() {
+        let body = base64::encode(
+            "--X-BOUNDARY\r\n\
+             Content-Disposition: form-data; name=\"text\"; filename=\"upload.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Firstname\r\n\
+             --X-BOUNDARY\r\n\
+             Content-Disposition: form-data; name=\"filename\"\r\n\r\n\
+             notes.txt\r\n\
+             --X-BOUNDARY--\r\n",
+        );
+        let request = get_request_with_content_type(&body, true, MULTIPART_CONTENT_TYPE);
+        let event = CustomEvent::try_from(request).expect("expected Ok(_) value");
+        assert_eq!(event.text_body, Some("Firstname".to_string()));
+        assert_eq!(event.filename, Some("notes.txt".to_string()));
+
+        let body = "--X-BOUNDARY\r\n\
+                    Content-Disposition: form-data; name=\"text\"; filename=\"upload.txt\"\r\n\r\n\
+                    Firstname\r\n\
+                    --X-BOUNDARY--\r\n";
+        let request = get_request_with_content_type(body, false, MULTIPART_CONTENT_TYPE);
+        let event = CustomEvent::try_from(request).expect("expected Ok(_) value");
+        assert_eq!(event.filename, Some("upload.txt".to_string()));
+    }
+
+    #[test]
+    fn can_reject_malformed_multipart_proxy_request() {
+        let body = "--OTHER-BOUNDARY\r\n\
+                    Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+                    Firstname\r\n\
+                    --OTHER-BOUNDARY--\r\n";
+        let request = get_request_with_content_type(body, false, MULTIPART_CONTENT_TYPE);
+        let result = CustomEvent::try_from(request);
+        assert!(matches!(result, Err(LambdaError::MalformedEvent(_))));
+
+        let body = "--X-BOUNDARY\r\n\
+                    Content-Disposition: form-data; name=\"key\"\r\n\r\n\
+                    a.txt\r\n\
+                    --X-BOUNDARY--\r\n";
+        let request = get_request_with_content_type(body, false, MULTIPART_CONTENT_TYPE);
+        let result = CustomEvent::try_from(request);
+        assert!(matches!(result, Err(LambdaError::UnknownFields(_))));
+    }
+}
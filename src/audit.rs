@@ -0,0 +1,159 @@
+use lambda::Context;
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+
+use crate::api_context::ApiGatewayContext;
+use crate::config::Config;
+use crate::{CustomEvent, CustomOutput, OP_PUT};
+
+// the newest CloudTrail record version at the time of writing
+const EVENT_VERSION: &str = "1.08";
+const EVENT_SOURCE: &str = "lambda.amazonaws.com";
+
+/// One audit entry in the shape of a CloudTrail record, so it can be shipped
+/// to the same tooling as the account's CloudTrail logs.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudTrailRecord {
+    pub event_version: String,
+    pub event_source: String,
+    pub event_name: String,
+    #[serde(rename = "requestID")]
+    pub request_id: String,
+    pub user_identity: UserIdentity,
+    #[serde(rename = "sourceIPAddress", skip_serializing_if = "Option::is_none")]
+    pub source_ip_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    pub request_parameters: Value,
+    pub response_elements: Value,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserIdentity {
+    #[serde(rename = "type")]
+    pub identity_type: String,
+    pub principal_id: String,
+    pub invoked_by: String,
+}
+
+/// Text bodies are left out of both the parameters and the response elements,
+/// the audit log records who did what, not the content.
+/// The caller's address and user agent are only known through API Gateway.
+pub fn build_cloudtrail_record(
+    event: &CustomEvent,
+    output: &CustomOutput,
+    c: &Context,
+    api_context: Option<&ApiGatewayContext>,
+    config: &Config,
+) -> CloudTrailRecord {
+    // Cognito callers are the only ones the runtime tells us anything about,
+    // behind a user pool authorizer the claims name the user instead
+    let principal_id = match &c.identity {
+        Some(identity) => Some(identity.identity_id.clone()),
+        None => api_context.and_then(|api_context| api_context.claims.get("sub").cloned()),
+    };
+    let user_identity = match principal_id {
+        Some(principal_id) => UserIdentity {
+            identity_type: "WebIdentityUser".to_string(),
+            principal_id,
+            invoked_by: c.invoked_function_arn.clone(),
+        },
+        None => UserIdentity {
+            identity_type: "AWSAccount".to_string(),
+            principal_id: String::new(),
+            invoked_by: c.invoked_function_arn.clone(),
+        },
+    };
+    CloudTrailRecord {
+        event_version: EVENT_VERSION.to_string(),
+        event_source: EVENT_SOURCE.to_string(),
+        event_name: event.op.as_deref().unwrap_or(OP_PUT).to_string(),
+        request_id: c.request_id.clone(),
+        user_identity,
+        source_ip_address: api_context.map(|api_context| api_context.source_ip.clone()),
+        user_agent: api_context.and_then(|api_context| api_context.user_agent.clone()),
+        request_parameters: json!({
+            "bucketName": config.bucket_name,
+            "key": event.key,
+            "destinationKey": event.destination_key,
+            "prefix": event.prefix,
+        }),
+        response_elements: json!({
+            "message": output.message,
+            "key": output.key,
+            "etag": output.etag,
+            "warning": output.warning,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_build_cloudtrail_record() {
+        let event = CustomEvent {
+            key: Some("test.txt".to_string()),
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let output = CustomOutput {
+            message: "Succeeded.".to_string(),
+            key: Some("test.txt".to_string()),
+            etag: Some("\"etag\"".to_string()),
+            ..Default::default()
+        };
+        let c = Context {
+            request_id: "request-1".to_string(),
+            invoked_function_arn: "arn:aws:lambda:ap-northeast-1:123456789012:function:hello"
+                .to_string(),
+            ..Default::default()
+        };
+        let config = Config {
+            bucket_name: "test-bucket".to_string(),
+            ..Default::default()
+        };
+        let record = build_cloudtrail_record(&event, &output, &c, None, &config);
+        let record = serde_json::to_value(record).unwrap();
+        assert_eq!(record["eventVersion"], "1.08");
+        assert_eq!(record["eventSource"], "lambda.amazonaws.com");
+        assert_eq!(record["eventName"], "put");
+        assert_eq!(record["requestID"], "request-1");
+        assert_eq!(record["userIdentity"]["type"], "AWSAccount");
+        assert_eq!(
+            record["userIdentity"]["invokedBy"],
+            "arn:aws:lambda:ap-northeast-1:123456789012:function:hello"
+        );
+        assert_eq!(record["requestParameters"]["bucketName"], "test-bucket");
+        assert_eq!(record["requestParameters"]["key"], "test.txt");
+        assert_eq!(record["responseElements"]["etag"], "\"etag\"");
+        assert!(!record.to_string().contains("Firstname"));
+        assert_eq!(record.get("sourceIPAddress"), None);
+    }
+
+    #[test]
+    fn can_build_cloudtrail_record_from_api_context() {
+        let mut claims = std::collections::BTreeMap::new();
+        claims.insert("sub".to_string(), "user-1".to_string());
+        let api_context = ApiGatewayContext {
+            apigw_request_id: "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9".to_string(),
+            source_ip: "192.168.100.1".to_string(),
+            user_agent: Some("curl/7.64.1".to_string()),
+            claims,
+        };
+        let record = build_cloudtrail_record(
+            &CustomEvent::default(),
+            &CustomOutput::default(),
+            &Context::default(),
+            Some(&api_context),
+            &Config::default(),
+        );
+        assert_eq!(record.source_ip_address, Some("192.168.100.1".to_string()));
+        assert_eq!(record.user_agent, Some("curl/7.64.1".to_string()));
+        assert_eq!(record.user_identity.identity_type, "WebIdentityUser");
+        assert_eq!(record.user_identity.principal_id, "user-1");
+    }
+}
@@ -0,0 +1,484 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lambda::Context;
+
+use crate::api_context::ApiGatewayContext;
+use crate::config::Config;
+#[cfg(feature = "hmac-auth")]
+use crate::internal_signature::get_signing_payload;
+use crate::internal_signature::verify_internal_event;
+use crate::{CustomEvent, LambdaError};
+
+/// Who an invocation was authorized as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Principal {
+    /// No auth check is enabled in this build or configuration.
+    Anonymous,
+    ApiKey,
+    /// The event carried a valid HMAC signature.
+    Signed,
+    CognitoIdentity(String),
+    /// A fan-out child invocation sent by this function.
+    Internal,
+}
+
+/// A `Context` that has been through `authorize`. `hello` takes this instead
+/// of a bare `Context`, so a new entry point can't forget the auth checks.
+#[derive(Debug)]
+pub struct AuthorizedContext {
+    inner: Context,
+    principal: Principal,
+    api_context: Option<ApiGatewayContext>,
+}
+
+impl AuthorizedContext {
+    pub fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    /// Set for invocations through an API Gateway proxy integration.
+    pub fn api_context(&self) -> Option<&ApiGatewayContext> {
+        self.api_context.as_ref()
+    }
+
+    pub fn with_api_context(mut self, api_context: Option<ApiGatewayContext>) -> AuthorizedContext {
+        self.api_context = api_context;
+        self
+    }
+
+    pub fn into_inner(self) -> Context {
+        self.inner
+    }
+
+    /// Skips `authorize`, for handler tests that are not about auth.
+    #[cfg(test)]
+    pub fn anonymous(inner: Context) -> AuthorizedContext {
+        AuthorizedContext {
+            inner,
+            principal: Principal::Anonymous,
+            api_context: None,
+        }
+    }
+}
+
+/// Runs every check compiled in through the `api-key-auth`, `hmac-auth` and
+/// `cognito-auth` features; all of them must pass. The API key and signature
+/// checks only apply once their secret is configured.
+/// An event carrying an internal signature is authorized by that signature
+/// alone: it must verify against `INTERNAL_SIGNING_KEY` and be within
+/// `SIGNATURE_MAX_AGE_SECS` of `signedAt`, whatever the features, and the
+/// caller's credentials are not forwarded to fan-out children.
+/// Otherwise the principal comes from the most specific check that passed.
+pub fn authorize(
+    event: &CustomEvent,
+    ctx: Context,
+    config: &Config,
+) -> Result<AuthorizedContext, LambdaError> {
+    if let Some(principal) = check_internal_signature(event, config)? {
+        return Ok(AuthorizedContext {
+            inner: ctx,
+            principal,
+            api_context: None,
+        });
+    }
+    let checks = [
+        check_api_key(event, config)?,
+        check_signature(event, config)?,
+        check_cognito_identity(&ctx)?,
+    ];
+    let principal = checks
+        .iter()
+        .flatten()
+        .last()
+        .cloned()
+        .unwrap_or(Principal::Anonymous);
+    Ok(AuthorizedContext {
+        inner: ctx,
+        principal,
+        api_context: None,
+    })
+}
+
+fn check_api_key(event: &CustomEvent, config: &Config) -> Result<Option<Principal>, LambdaError> {
+    let expected = match &config.api_key {
+        Some(api_key) if cfg!(feature = "api-key-auth") => api_key,
+        _ => return Ok(None),
+    };
+    match &event.api_key {
+        Some(api_key) if constant_time_eq(api_key.as_bytes(), expected.as_bytes()) => {
+            Ok(Some(Principal::ApiKey))
+        }
+        _ => Err(LambdaError::Unauthorized),
+    }
+}
+
+// the signature is the hex HMAC-SHA256 of `get_signing_payload`, which covers
+// `signedAt`, so a captured signature stops working once it is out of the window
+#[cfg(feature = "hmac-auth")]
+fn check_signature(event: &CustomEvent, config: &Config) -> Result<Option<Principal>, LambdaError> {
+    use hmac::{Hmac, Mac, NewMac};
+    use sha2::Sha256;
+
+    let secret = match &config.hmac_secret {
+        Some(secret) => secret,
+        None => return Ok(None),
+    };
+    if !is_fresh(event.signed_at, get_unix_now()) {
+        return Err(LambdaError::Unauthorized);
+    }
+    let signature = event
+        .signature
+        .as_deref()
+        .and_then(|signature| hex::decode(signature).ok())
+        .ok_or(LambdaError::Unauthorized)?;
+    let mut mac =
+        Hmac::<Sha256>::new_varkey(secret.as_bytes()).map_err(|_| LambdaError::Unauthorized)?;
+    mac.update(&get_signing_payload(event));
+    match mac.verify(&signature) {
+        Ok(()) => Ok(Some(Principal::Signed)),
+        Err(_) => Err(LambdaError::Unauthorized),
+    }
+}
+
+#[cfg(not(feature = "hmac-auth"))]
+fn check_signature(
+    _event: &CustomEvent,
+    _config: &Config,
+) -> Result<Option<Principal>, LambdaError> {
+    Ok(None)
+}
+
+// either way, so a client clock somewhat ahead of ours is still accepted
+fn is_fresh(signed_at: Option<u64>, now: u64) -> bool {
+    match signed_at {
+        Some(signed_at) => signed_at.max(now) - signed_at.min(now) <= crate::SIGNATURE_MAX_AGE_SECS,
+        None => false,
+    }
+}
+
+/// Unix seconds, as `signedAt` is written.
+pub fn get_unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+// without a key to check against, a signed event can't be trusted either;
+// `signedAt` is signed too, so a captured child event can't be replayed later
+fn check_internal_signature(
+    event: &CustomEvent,
+    config: &Config,
+) -> Result<Option<Principal>, LambdaError> {
+    let signature = match &event.internal_signature {
+        Some(signature) => signature,
+        None => return Ok(None),
+    };
+    if !is_fresh(event.signed_at, get_unix_now()) {
+        return Err(LambdaError::Unauthorized);
+    }
+    match &config.internal_signing_key {
+        Some(key) if verify_internal_event(event, signature, key.as_bytes()) => {
+            Ok(Some(Principal::Internal))
+        }
+        _ => Err(LambdaError::Unauthorized),
+    }
+}
+
+// Lambda fills in the identity for callers that came through a Cognito identity pool
+fn check_cognito_identity(c: &Context) -> Result<Option<Principal>, LambdaError> {
+    if !cfg!(feature = "cognito-auth") {
+        return Ok(None);
+    }
+    match &c.identity {
+        Some(identity) if !identity.identity_id.is_empty() => Ok(Some(Principal::CognitoIdentity(
+            identity.identity_id.clone(),
+        ))),
+        _ => Err(LambdaError::Unauthorized),
+    }
+}
+
+// so a wrong key takes as long to reject no matter where it differs
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_compare_in_constant_time() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+
+    #[cfg(not(any(
+        feature = "api-key-auth",
+        feature = "hmac-auth",
+        feature = "cognito-auth"
+    )))]
+    #[test]
+    fn can_authorize_anonymously_without_auth_features() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let authorized = authorize(&CustomEvent::default(), Context::default(), &config)
+            .expect("expected Ok(_) value");
+        assert_eq!(authorized.principal(), &Principal::Anonymous);
+    }
+
+    #[test]
+    fn can_authorize_internal_signature() {
+        use crate::internal_signature::sign_internal_event;
+
+        let config = Config {
+            internal_signing_key: Some("internal-secret".to_string()),
+            ..Default::default()
+        };
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            internal_request_id: Some("parent-request".to_string()),
+            signed_at: Some(get_unix_now()),
+            ..Default::default()
+        };
+        let event = CustomEvent {
+            internal_signature: Some(sign_internal_event(&event, b"internal-secret")),
+            ..event
+        };
+        let authorized =
+            authorize(&event, Context::default(), &config).expect("expected Ok(_) value");
+        assert_eq!(authorized.principal(), &Principal::Internal);
+        // children carry no API key or caller signature, the internal one is enough
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            hmac_secret: Some("secret".to_string()),
+            ..config
+        };
+        let authorized =
+            authorize(&event, Context::default(), &config).expect("expected Ok(_) value");
+        assert_eq!(authorized.principal(), &Principal::Internal);
+
+        let forged = CustomEvent {
+            text_body: Some("Lastname".to_string()),
+            ..event
+        };
+        assert_eq!(
+            authorize(&forged, Context::default(), &config).unwrap_err(),
+            LambdaError::Unauthorized
+        );
+        assert_eq!(
+            authorize(&forged, Context::default(), &Config::default()).unwrap_err(),
+            LambdaError::Unauthorized
+        );
+    }
+
+    #[test]
+    fn can_reject_stale_internal_signature() {
+        use crate::internal_signature::sign_internal_event;
+
+        let config = Config {
+            internal_signing_key: Some("internal-secret".to_string()),
+            ..Default::default()
+        };
+        let now = get_unix_now();
+        for &signed_at in &[None, Some(now - 3600), Some(now + 3600)] {
+            let event = CustomEvent {
+                text_body: Some("Firstname".to_string()),
+                internal_request_id: Some("parent-request".to_string()),
+                signed_at,
+                ..Default::default()
+            };
+            // validly signed, but outside the window
+            let event = CustomEvent {
+                internal_signature: Some(sign_internal_event(&event, b"internal-secret")),
+                ..event
+            };
+            assert_eq!(
+                authorize(&event, Context::default(), &config).unwrap_err(),
+                LambdaError::Unauthorized
+            );
+        }
+    }
+
+    #[cfg(feature = "api-key-auth")]
+    #[test]
+    fn can_authorize_api_key() {
+        let config = Config {
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let event = CustomEvent {
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let authorized =
+            authorize(&event, Context::default(), &config).expect("expected Ok(_) value");
+        assert_eq!(authorized.principal(), &Principal::ApiKey);
+
+        let event = CustomEvent {
+            api_key: Some("wrong".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            authorize(&event, Context::default(), &config).unwrap_err(),
+            LambdaError::Unauthorized
+        );
+        assert_eq!(
+            authorize(&CustomEvent::default(), Context::default(), &config).unwrap_err(),
+            LambdaError::Unauthorized
+        );
+    }
+
+    #[cfg(feature = "hmac-auth")]
+    fn sign(event: &CustomEvent) -> CustomEvent {
+        use hmac::{Hmac, Mac, NewMac};
+        use sha2::Sha256;
+
+        let mut mac = Hmac::<Sha256>::new_varkey(b"secret").unwrap();
+        mac.update(&get_signing_payload(event));
+        CustomEvent {
+            signature: Some(hex::encode(mac.finalize().into_bytes())),
+            ..event.clone()
+        }
+    }
+
+    #[cfg(feature = "hmac-auth")]
+    fn get_signed_event() -> CustomEvent {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        sign(&CustomEvent {
+            op: Some("put".to_string()),
+            key: Some("test.txt".to_string()),
+            text_body: Some("Firstname".to_string()),
+            signed_at: Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            ),
+            ..Default::default()
+        })
+    }
+
+    #[cfg(feature = "hmac-auth")]
+    #[test]
+    fn can_authorize_hmac_signature() {
+        let config = Config {
+            hmac_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let event = get_signed_event();
+        let authorized =
+            authorize(&event, Context::default(), &config).expect("expected Ok(_) value");
+        assert_eq!(authorized.principal(), &Principal::Signed);
+
+        // a signature for different content must not pass
+        let event = CustomEvent {
+            text_body: Some("Lastname".to_string()),
+            ..event
+        };
+        assert_eq!(
+            authorize(&event, Context::default(), &config).unwrap_err(),
+            LambdaError::Unauthorized
+        );
+        let event = CustomEvent {
+            signature: Some("not-hex".to_string()),
+            ..event
+        };
+        assert_eq!(
+            authorize(&event, Context::default(), &config).unwrap_err(),
+            LambdaError::Unauthorized
+        );
+    }
+
+    #[cfg(feature = "hmac-auth")]
+    #[test]
+    fn can_reject_hmac_signature_for_changed_field() {
+        use crate::BatchItem;
+
+        let config = Config {
+            hmac_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let changed = [
+            CustomEvent {
+                items: Some(vec![BatchItem {
+                    key: Some("other.txt".to_string()),
+                    text_body: Some("Lastname".to_string()),
+                }]),
+                ..get_signed_event()
+            },
+            CustomEvent {
+                content_type: Some("text/html".to_string()),
+                ..get_signed_event()
+            },
+            CustomEvent {
+                destination_key: Some("other.txt".to_string()),
+                ..get_signed_event()
+            },
+        ];
+        for event in &changed {
+            assert_eq!(
+                authorize(event, Context::default(), &config).unwrap_err(),
+                LambdaError::Unauthorized
+            );
+        }
+    }
+
+    #[cfg(feature = "hmac-auth")]
+    #[test]
+    fn can_reject_stale_hmac_signature() {
+        let config = Config {
+            hmac_secret: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let fresh = get_signed_event();
+        let signed_at = fresh.signed_at.unwrap();
+        for &signed_at in &[None, Some(signed_at - 3600), Some(signed_at + 3600)] {
+            let event = sign(&CustomEvent {
+                signed_at,
+                ..fresh.clone()
+            });
+            assert_eq!(
+                authorize(&event, Context::default(), &config).unwrap_err(),
+                LambdaError::Unauthorized
+            );
+        }
+        assert!(is_fresh(Some(1000), 1000 + crate::SIGNATURE_MAX_AGE_SECS));
+        assert!(!is_fresh(Some(1000), 1001 + crate::SIGNATURE_MAX_AGE_SECS));
+        assert!(is_fresh(Some(1000 + crate::SIGNATURE_MAX_AGE_SECS), 1000));
+    }
+
+    #[cfg(feature = "cognito-auth")]
+    #[test]
+    fn can_authorize_cognito_identity() {
+        let c = Context {
+            identity: Some(
+                serde_json::from_value(serde_json::json!({
+                    "identityId": "ap-northeast-1:identity",
+                    "identityPoolId": "ap-northeast-1:pool",
+                }))
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let authorized = authorize(&CustomEvent::default(), c, &Config::default())
+            .expect("expected Ok(_) value");
+        assert_eq!(
+            authorized.principal(),
+            &Principal::CognitoIdentity("ap-northeast-1:identity".to_string())
+        );
+        assert_eq!(
+            authorize(
+                &CustomEvent::default(),
+                Context::default(),
+                &Config::default()
+            )
+            .unwrap_err(),
+            LambdaError::Unauthorized
+        );
+    }
+}
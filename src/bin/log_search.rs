@@ -0,0 +1,236 @@
+//! Finds the invocation that wrote an S3 object by searching the function's
+//! logs with CloudWatch Logs Insights.
+//!
+//! `cargo run --bin log-search -- --s3-key <key> --start-time <epoch seconds> --log-group <name>`
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use rusoto_core::Region;
+use rusoto_logs::{
+    CloudWatchLogs, CloudWatchLogsClient, GetQueryResultsRequest, ResultField, StartQueryRequest,
+};
+use tokio::time::delay_for;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Insights queries time out after 15 minutes on the AWS side
+const MAX_POLLS: usize = 900;
+const USAGE: &str =
+    "usage: log-search --s3-key <key> --start-time <epoch seconds> --log-group <name>";
+
+#[derive(Debug, PartialEq)]
+struct SearchArgs {
+    s3_key: String,
+    start_time: i64,
+    log_group: String,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<SearchArgs> {
+    let mut s3_key = None;
+    let mut start_time = None;
+    let mut log_group = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--s3-key" => s3_key = Some(value),
+            "--start-time" => {
+                start_time = Some(
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("--start-time must be epoch seconds"))?,
+                )
+            }
+            "--log-group" => log_group = Some(value),
+            _ => bail!("unknown argument {}\n{}", arg, USAGE),
+        }
+    }
+    match (s3_key, start_time, log_group) {
+        (Some(s3_key), Some(start_time), Some(log_group)) => Ok(SearchArgs {
+            s3_key,
+            start_time,
+            log_group,
+        }),
+        _ => bail!(USAGE),
+    }
+}
+
+/// Matches the `s3_key="..."` field put_text logs after a successful write.
+fn build_query(s3_key: &str) -> String {
+    format!(
+        "fields @timestamp, @message | filter @message like /s3_key=\"{}\"/",
+        escape_regex(s3_key)
+    )
+}
+
+// keys are free-form, keep dots and slashes in them from acting as regex syntax
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\/.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn format_row(row: &[ResultField]) -> Option<String> {
+    let field = |name: &str| {
+        row.iter()
+            .find(|field| field.field.as_deref() == Some(name))
+            .and_then(|field| field.value.clone())
+    };
+    let message = field("@message")?;
+    let message = message.trim_end();
+    Some(match field("@timestamp") {
+        Some(timestamp) => format!("{} {}", timestamp, message),
+        None => message.to_string(),
+    })
+}
+
+/// Starts the query and polls it until it finishes, returning one line per match.
+async fn search(
+    logs: &dyn CloudWatchLogs,
+    args: &SearchArgs,
+    end_time: i64,
+    poll_interval: Duration,
+) -> Result<Vec<String>> {
+    let query_id = logs
+        .start_query(StartQueryRequest {
+            log_group_name: Some(args.log_group.clone()),
+            query_string: build_query(&args.s3_key),
+            start_time: args.start_time,
+            end_time,
+            ..Default::default()
+        })
+        .await?
+        .query_id
+        .ok_or_else(|| anyhow!("StartQuery returned no query id"))?;
+    for _ in 0..MAX_POLLS {
+        let output = logs
+            .get_query_results(GetQueryResultsRequest {
+                query_id: query_id.clone(),
+            })
+            .await?;
+        match output.status.as_deref() {
+            Some("Complete") => {
+                return Ok(output
+                    .results
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|row| format_row(row))
+                    .collect())
+            }
+            Some("Scheduled") | Some("Running") => delay_for(poll_interval).await,
+            status => bail!("query {} ended with status {:?}", query_id, status),
+        }
+    }
+    bail!("query {} did not finish", query_id)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = parse_args(std::env::args().skip(1))?;
+    let end_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let logs = CloudWatchLogsClient::new(Region::default());
+    for line in search(&logs, &args, end_time, POLL_INTERVAL).await? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_mock::{
+        MockCredentialsProvider, MockRequestDispatcher, MultipleMockRequestDispatcher,
+    };
+
+    fn get_args() -> SearchArgs {
+        SearchArgs {
+            s3_key: "logs/a.txt".to_string(),
+            start_time: 1605500000,
+            log_group: "/aws/lambda/hello".to_string(),
+        }
+    }
+
+    #[test]
+    fn can_parse_args() {
+        let args = parse_args(
+            vec![
+                "--s3-key",
+                "logs/a.txt",
+                "--start-time",
+                "1605500000",
+                "--log-group",
+                "/aws/lambda/hello",
+            ]
+            .into_iter()
+            .map(String::from),
+        )
+        .expect("expected Ok(_) value");
+        assert_eq!(args, get_args());
+        assert!(parse_args(vec!["--s3-key".to_string()]).is_err());
+        assert!(parse_args(vec!["--s3-key".to_string(), "a.txt".to_string()]).is_err());
+    }
+
+    #[test]
+    fn can_build_query_with_escaped_key() {
+        assert_eq!(
+            build_query("logs/a.txt"),
+            r#"fields @timestamp, @message | filter @message like /s3_key="logs\/a\.txt"/"#
+        );
+    }
+
+    #[tokio::test]
+    async fn can_search_until_query_completes() {
+        let logs = CloudWatchLogsClient::new_with(
+            MultipleMockRequestDispatcher::new(vec![
+                MockRequestDispatcher::default()
+                    .with_body(r#"{"queryId":"q-1"}"#)
+                    .with_request_checker(|request| {
+                        let body = String::from_utf8(request.payload.clone().unwrap_or_default())
+                            .unwrap_or_default();
+                        assert!(body.contains(r#""startTime":1605500000"#));
+                        assert!(body.contains(r#""endTime":1605503600"#));
+                        assert!(body.contains("/aws/lambda/hello"));
+                    }),
+                MockRequestDispatcher::default().with_body(r#"{"status":"Running","results":[]}"#),
+                MockRequestDispatcher::default().with_body(
+                    r#"{"status":"Complete","results":[[
+                        {"field":"@timestamp","value":"2020-11-16 04:13:20.000"},
+                        {"field":"@message","value":"INFO Wrote s3_key=\"logs/a.txt\" in request c6af9ac6\n"}
+                    ]]}"#,
+                ),
+            ]),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let lines = search(&logs, &get_args(), 1605503600, Duration::from_millis(1))
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(
+            lines,
+            vec![r#"2020-11-16 04:13:20.000 INFO Wrote s3_key="logs/a.txt" in request c6af9ac6"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_report_failed_query() {
+        let logs = CloudWatchLogsClient::new_with(
+            MultipleMockRequestDispatcher::new(vec![
+                MockRequestDispatcher::default().with_body(r#"{"queryId":"q-1"}"#),
+                MockRequestDispatcher::default().with_body(r#"{"status":"Failed"}"#),
+            ]),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let err = search(&logs, &get_args(), 1605503600, Duration::from_millis(1))
+            .await
+            .expect_err("expected Err(_) value");
+        assert!(err.to_string().contains("Failed"));
+    }
+}
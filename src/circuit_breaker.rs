@@ -0,0 +1,79 @@
+use std::time::Instant;
+
+use lambda::Context;
+
+use crate::{get_deadline, LambdaError};
+
+pub const DEFAULT_THRESHOLD_MS: u64 = 1000;
+
+/// Opens when the invocation has less than `threshold_ms` left, so nothing is
+/// started that Lambda would cut off halfway.
+pub struct CircuitBreaker {
+    threshold_ms: u64,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold_ms: u64) -> CircuitBreaker {
+        CircuitBreaker { threshold_ms }
+    }
+
+    /// A context without a deadline never opens the circuit.
+    pub fn check(&self, c: &Context) -> Result<(), LambdaError> {
+        let deadline = match get_deadline(c) {
+            Some(deadline) => deadline,
+            None => return Ok(()),
+        };
+        let remaining_ms = deadline
+            .checked_duration_since(Instant::now())
+            .map(|remaining| remaining.as_millis() as u64)
+            .unwrap_or(0);
+        if remaining_ms < self.threshold_ms {
+            return Err(LambdaError::InsufficientTime { remaining_ms });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn get_context_with_remaining(remaining_ms: u64) -> Context {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        Context {
+            deadline: now_ms + remaining_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn can_stay_closed_with_enough_time() {
+        let breaker = CircuitBreaker::new(DEFAULT_THRESHOLD_MS);
+        assert_eq!(breaker.check(&get_context_with_remaining(10_000)), Ok(()));
+        assert_eq!(breaker.check(&Context::default()), Ok(()));
+    }
+
+    #[test]
+    fn can_open_below_threshold() {
+        let breaker = CircuitBreaker::new(DEFAULT_THRESHOLD_MS);
+        for remaining_ms in &[0, 1, 500, 900] {
+            let result = breaker.check(&get_context_with_remaining(*remaining_ms));
+            match result {
+                Err(LambdaError::InsufficientTime { remaining_ms: left }) => {
+                    assert!(left <= *remaining_ms)
+                }
+                result => panic!("unexpected result for {}ms: {:?}", remaining_ms, result),
+            }
+        }
+    }
+
+    #[test]
+    fn can_disable_with_zero_threshold() {
+        let breaker = CircuitBreaker::new(0);
+        assert_eq!(breaker.check(&get_context_with_remaining(0)), Ok(()));
+    }
+}
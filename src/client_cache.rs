@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use rusoto_core::Region;
+use rusoto_s3::S3Client;
+
+// distinct region/endpoint configurations are few, this only guards against unbounded growth
+const MAX_CACHED_CLIENTS: usize = 16;
+
+pub static CLIENT_CACHE: Lazy<ClientCache> = Lazy::new(|| ClientCache::new(MAX_CACHED_CLIENTS));
+
+/// Keeps one `S3Client` per (region, endpoint) so warm invocations reuse their
+/// connection pool instead of building a new client every time.
+/// rusoto_s3 always addresses buckets path-style, so it's not part of the key.
+pub struct ClientCache {
+    clients: Mutex<HashMap<(String, String), S3Client>>,
+    capacity: usize,
+}
+
+impl ClientCache {
+    pub fn new(capacity: usize) -> ClientCache {
+        ClientCache {
+            clients: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// Returns the cached client for `region`, calling `build` only on a miss.
+    /// Once the cache is full new configurations still get a client, it just isn't kept.
+    pub fn get_or_build<F>(&self, region: &Region, build: F) -> S3Client
+    where
+        F: FnOnce() -> S3Client,
+    {
+        let cache_key = get_cache_key(region);
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&cache_key) {
+            return client.clone();
+        }
+        let client = build();
+        if clients.len() < self.capacity {
+            clients.insert(cache_key, client.clone());
+        }
+        client
+    }
+}
+
+fn get_cache_key(region: &Region) -> (String, String) {
+    match region {
+        Region::Custom { name, endpoint } => (name.clone(), endpoint.clone()),
+        _ => (region.name().to_owned(), String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_mock::{MockCredentialsProvider, MockRequestDispatcher};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn build_client(region: &Region, builds: &Cell<usize>) -> S3Client {
+        builds.set(builds.get() + 1);
+        S3Client::new_with(
+            MockRequestDispatcher::default(),
+            MockCredentialsProvider,
+            region.clone(),
+        )
+    }
+
+    #[test]
+    fn can_reuse_client_per_region_config() {
+        let cache = ClientCache::new(MAX_CACHED_CLIENTS);
+        let builds = Cell::new(0);
+        let local = Region::Custom {
+            name: "ap-northeast-1".to_owned(),
+            endpoint: "http://host.docker.internal:8000".to_owned(),
+        };
+        let cloud = Region::ApNortheast1;
+        for _ in 0..2 {
+            cache.get_or_build(&local, || build_client(&local, &builds));
+            cache.get_or_build(&cloud, || build_client(&cloud, &builds));
+        }
+        assert_eq!(builds.get(), 2);
+    }
+
+    #[test]
+    fn can_share_cache_across_threads() {
+        let cache = Arc::new(ClientCache::new(MAX_CACHED_CLIENTS));
+        let builds = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                let builds = builds.clone();
+                thread::spawn(move || {
+                    let region = Region::ApNortheast1;
+                    cache.get_or_build(&region, || {
+                        builds.fetch_add(1, Ordering::SeqCst);
+                        S3Client::new_with(
+                            MockRequestDispatcher::default(),
+                            MockCredentialsProvider,
+                            region.clone(),
+                        )
+                    });
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // the lock is held while building, so racing threads never build twice
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn can_build_uncached_client_when_full() {
+        let cache = ClientCache::new(1);
+        let builds = Cell::new(0);
+        let tokyo = Region::ApNortheast1;
+        let virginia = Region::UsEast1;
+        for _ in 0..2 {
+            cache.get_or_build(&tokyo, || build_client(&tokyo, &builds));
+            cache.get_or_build(&virginia, || build_client(&virginia, &builds));
+        }
+        // tokyo took the only slot, virginia is rebuilt on every call
+        assert_eq!(builds.get(), 3);
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use rusoto_core::request::DispatchSignedRequest;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::Region;
+
+use crate::http_date::parse_http_date;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The container clock; `check_clock_skew` takes it as a parameter so tests can skew it.
+pub fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// Sends an unsigned HEAD to the S3 endpoint and returns its `Date` header.
+/// S3 rejects the request, but every response carries the server time.
+pub async fn fetch_server_time<D: DispatchSignedRequest>(
+    dispatcher: &D,
+    region: &Region,
+) -> Result<SystemTime> {
+    let request = SignedRequest::new("HEAD", "s3", region, "/");
+    let response = dispatcher.dispatch(request, Some(FETCH_TIMEOUT)).await?;
+    let date = response
+        .headers
+        .get("date")
+        .ok_or_else(|| anyhow!("S3 response has no Date header"))?;
+    parse_http_date(date).ok_or_else(|| anyhow!("invalid Date header: {}", date))
+}
+
+/// Warns when the clock is more than `threshold` away from `server_time`;
+/// signatures and presigned URLs from a skewed clock are rejected or expire early.
+pub fn check_clock_skew<F>(server_time: SystemTime, now: F, threshold: Duration) -> Option<Duration>
+where
+    F: Fn() -> SystemTime,
+{
+    let skew = match now().duration_since(server_time) {
+        Ok(ahead) => ahead,
+        Err(err) => err.duration(),
+    };
+    if skew <= threshold {
+        return None;
+    }
+    warn!(
+        "Container clock is {}ms off from S3, signed requests and presigned URLs may fail",
+        skew.as_millis()
+    );
+    Some(skew)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::testing::{capture_logs, get_captured_logs};
+    use rusoto_mock::MockRequestDispatcher;
+    use std::time::UNIX_EPOCH;
+
+    const SERVER_DATE: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn get_server_time() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(784_111_777)
+    }
+
+    #[tokio::test]
+    async fn can_fetch_server_time() {
+        let dispatcher = MockRequestDispatcher::with_status(403).with_header("Date", SERVER_DATE);
+        let server_time = fetch_server_time(&dispatcher, &Region::ApNortheast1)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(server_time, get_server_time());
+        let dispatcher = MockRequestDispatcher::with_status(403);
+        assert!(fetch_server_time(&dispatcher, &Region::ApNortheast1)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn can_warn_about_skewed_clock() {
+        capture_logs();
+        let threshold = Duration::from_secs(60);
+        let in_sync = || get_server_time() + Duration::from_secs(5);
+        assert_eq!(
+            check_clock_skew(get_server_time(), in_sync, threshold),
+            None
+        );
+        let behind = || get_server_time() - Duration::from_secs(900);
+        assert_eq!(
+            check_clock_skew(get_server_time(), behind, threshold),
+            Some(Duration::from_secs(900))
+        );
+        assert_eq!(
+            get_captured_logs("900000ms off from S3"),
+            vec![
+                "WARN Container clock is 900000ms off from S3, signed requests and presigned URLs may fail"
+            ]
+        );
+    }
+}
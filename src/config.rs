@@ -0,0 +1,555 @@
+use std::fmt;
+
+use anyhow::Result;
+use rusoto_core::Region;
+
+use crate::circuit_breaker::DEFAULT_THRESHOLD_MS;
+use crate::key_strategy::KeyStrategy;
+use crate::precondition::PutObjectMode;
+use crate::preprocess::{
+    BomStripper, MaxLengthEnforcer, PiiRedactor, Preprocessor, PreprocessorChain,
+    WhitespaceNormalizer,
+};
+use crate::user_agent::DEFAULT_USER_AGENT;
+use crate::{
+    get_s3_region, is_local_env, parse_flag, ErrorFormat, ALLOWED_CONTENT_TYPES_KEY,
+    ALLOWED_OPS_KEY, ALLOW_EMPTY_UPLOAD_KEY, ANALYZE_TEXT_KEY, API_KEY_KEY,
+    AUTO_COMPRESS_THRESHOLD_KEY, BUCKET_NAME_KEY, CIRCUIT_BREAKER_THRESHOLD_MS_KEY,
+    CLOCK_SKEW_THRESHOLD_MS_KEY, DECOMPRESS_ON_READ_KEY, DEFAULT_BODY_KEY,
+    DEFAULT_CACHE_CONTROL_KEY, DEFAULT_KMS_KEY_ID_KEY, DEFAULT_MAX_BATCH_ITEMS,
+    DEFAULT_MAX_RAW_EVENT_BYTES, DEFAULT_MAX_RETRIES, DEFAULT_SHUTDOWN_DEADLINE_MS,
+    DEFAULT_TIMEOUT_BUFFER_MS, DLQ_BUCKET_KEY, EVENT_PAYLOAD_PATH_KEY, FAN_OUT_BATCHES_KEY,
+    FUNCTION_NAME_KEY, HMAC_SECRET_KEY, INCLUDE_METRICS_IN_RESPONSE_KEY, INDEX_ENABLED_KEY,
+    INTERNAL_SIGNING_KEY_KEY, JSON_ERRORS_KEY, KEY_PREFIX_KEY,
+    LAMBDA_HANDLER_TIMEOUT_BUFFER_MS_KEY, LOCAL_KEY, LOCAL_REPLAY_ENABLED_KEY, LOG_REJECTIONS_KEY,
+    LOWERCASE_KEYS_KEY, MAX_BATCH_ITEMS_KEY, MAX_GET_BYTES_KEY, MAX_KEY_DEPTH_KEY,
+    MAX_RAW_EVENT_BYTES_KEY, MAX_TEXT_BODY_LEN, MOCK_KEY, NAMESPACE_BY_FUNCTION_KEY,
+    OBJECT_KEY_FORMAT_KEY, PREFER_ITEMS_KEY, PROBLEM_JSON_KEY, PUT_OBJECT_MODE_KEY, REDACT_PII_KEY,
+    REQUIRE_EXPLICIT_KMS_KEY_KEY, RETRIABLE_STATUS_CODES_KEY, S3_MAX_RETRIES_KEY,
+    S3_REQUEST_LOGGING_KEY, S3_USER_AGENT_KEY, SCHEMA_VERSION_KEY, SENTRY_DSN_KEY,
+    SHUTDOWN_DEADLINE_MS_KEY, WRITE_PREFIX_KEY,
+};
+
+/// A setting `from_vars` could not use; `value` is empty for one that is missing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub field: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={:?}: {}", self.field, self.value, self.reason)
+    }
+}
+
+/// Every invalid setting at once, so a deployment can fix them in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigValidationError(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors: Vec<String> = self.0.iter().map(ConfigError::to_string).collect();
+        write!(f, "invalid configuration: {}", errors.join("; "))
+    }
+}
+
+impl std::error::Error for ConfigValidationError {}
+
+/// Deployment settings, read once in `main` and handed to every invocation,
+/// so handlers and tests don't go through the process environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bucket_name: String,
+    pub allowed_ops: Option<String>,
+    pub circuit_breaker_threshold_ms: u64,
+    pub include_metrics_in_response: bool,
+    pub allow_empty_upload: bool,
+    pub default_body: Option<String>,
+    pub log_rejections: bool,
+    pub redact_pii: bool,
+    pub default_cache_control: Option<String>,
+    pub allowed_content_types: Option<String>,
+    pub put_object_mode: PutObjectMode,
+    pub index_enabled: bool,
+    pub max_batch_items: usize,
+    pub decompress_on_read: bool,
+    pub max_get_bytes: Option<u64>,
+    pub lowercase_keys: bool,
+    pub key_prefix: Option<String>,
+    pub key_strategy: KeyStrategy,
+    pub function_namespace: Option<String>,
+    pub dlq_bucket: Option<String>,
+    pub max_retries: u32,
+    pub retriable_status_codes: Vec<u16>,
+    pub local_replay_enabled: bool,
+    pub api_key: Option<String>,
+    pub hmac_secret: Option<String>,
+    pub clock_skew_threshold_ms: Option<u64>,
+    pub max_raw_event_bytes: usize,
+    pub analyze_text: bool,
+    pub fan_out_function: Option<String>,
+    pub handler_timeout_buffer_ms: u64,
+    // the region the S3 client talks to, only used here to build object URLs
+    pub region: Region,
+    pub default_kms_key_id: Option<String>,
+    pub require_explicit_kms_key: bool,
+    // a put with both textBody and items writes the items instead of being rejected
+    pub prefer_items: bool,
+    // writes to resolved keys outside this prefix are rejected, reads are not
+    pub write_prefix: Option<String>,
+    // dot path of the real event inside an envelope, e.g. `detail` for EventBridge
+    pub event_payload_path: Option<String>,
+    // most `/`-separated segments a written key may have
+    pub max_key_depth: Option<usize>,
+    // shared by the fan-out parent and its children, see internal_signature.rs
+    pub internal_signing_key: Option<String>,
+    // stored as `x-amz-meta-schema-version` on every object written
+    pub schema_version: Option<String>,
+    // only read with the sentry-integration feature, see error_tracking.rs
+    #[cfg_attr(not(feature = "sentry-integration"), allow(dead_code))]
+    pub sentry_dsn: Option<String>,
+    // put bodies over this many bytes are stored gzipped when that saves space
+    pub auto_compress_threshold: Option<usize>,
+    // how long a container waits for in-flight invocations after SIGTERM
+    pub shutdown_deadline_ms: u64,
+    // LOCAL_FLAG, AWS_SAM_LOCAL or LOCALSTACK_HOSTNAME, see is_local_env
+    pub local: bool,
+    // AWS_MOCK_FLAG, only honored in builds with the mock-s3 feature
+    pub mock_s3: bool,
+    pub s3_user_agent: String,
+    // trace-level logs of every S3 request, see request_log.rs
+    pub s3_request_logging: bool,
+    // from PROBLEM_JSON and JSON_ERRORS
+    pub error_format: ErrorFormat,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bucket_name: String::new(),
+            allowed_ops: None,
+            circuit_breaker_threshold_ms: DEFAULT_THRESHOLD_MS,
+            include_metrics_in_response: false,
+            allow_empty_upload: false,
+            default_body: None,
+            log_rejections: true,
+            redact_pii: false,
+            default_cache_control: None,
+            allowed_content_types: None,
+            put_object_mode: PutObjectMode::Overwrite,
+            index_enabled: false,
+            max_batch_items: DEFAULT_MAX_BATCH_ITEMS,
+            decompress_on_read: true,
+            max_get_bytes: None,
+            lowercase_keys: false,
+            key_prefix: None,
+            key_strategy: KeyStrategy::default(),
+            function_namespace: None,
+            dlq_bucket: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retriable_status_codes: Vec::new(),
+            local_replay_enabled: false,
+            api_key: None,
+            hmac_secret: None,
+            clock_skew_threshold_ms: None,
+            max_raw_event_bytes: DEFAULT_MAX_RAW_EVENT_BYTES,
+            analyze_text: false,
+            fan_out_function: None,
+            handler_timeout_buffer_ms: DEFAULT_TIMEOUT_BUFFER_MS,
+            region: Region::ApNortheast1,
+            default_kms_key_id: None,
+            require_explicit_kms_key: false,
+            prefer_items: false,
+            write_prefix: None,
+            event_payload_path: None,
+            max_key_depth: None,
+            internal_signing_key: None,
+            schema_version: None,
+            sentry_dsn: None,
+            auto_compress_threshold: None,
+            shutdown_deadline_ms: DEFAULT_SHUTDOWN_DEADLINE_MS,
+            local: false,
+            mock_s3: false,
+            s3_user_agent: DEFAULT_USER_AGENT.to_string(),
+            s3_request_logging: false,
+            error_format: ErrorFormat::Plain,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> std::result::Result<Config, ConfigValidationError> {
+        Config::from_vars(|key| std::env::var(key).ok())
+    }
+
+    /// Flags are read with `parse_flag`; unparsable numbers fall back to the default.
+    /// A missing bucket or an unknown mode is an error, and all of them are reported.
+    pub fn from_vars<F>(get_var: F) -> std::result::Result<Config, ConfigValidationError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let defaults = Config::default();
+        let flag = |key, default| get_var(key).map(|v| parse_flag(&v)).unwrap_or(default);
+        let mut errors = Vec::new();
+        let local = is_local_env(&get_var);
+        let config = Config {
+            bucket_name: get_var(BUCKET_NAME_KEY).unwrap_or_else(|| {
+                errors.push(ConfigError {
+                    field: BUCKET_NAME_KEY.to_string(),
+                    value: String::new(),
+                    reason: "is not set".to_string(),
+                });
+                String::new()
+            }),
+            allowed_ops: get_var(ALLOWED_OPS_KEY),
+            circuit_breaker_threshold_ms: get_var(CIRCUIT_BREAKER_THRESHOLD_MS_KEY)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.circuit_breaker_threshold_ms),
+            include_metrics_in_response: flag(INCLUDE_METRICS_IN_RESPONSE_KEY, false),
+            allow_empty_upload: flag(ALLOW_EMPTY_UPLOAD_KEY, false),
+            default_body: get_var(DEFAULT_BODY_KEY).filter(|v| !v.is_empty()),
+            log_rejections: flag(LOG_REJECTIONS_KEY, true),
+            redact_pii: flag(REDACT_PII_KEY, false),
+            default_cache_control: get_var(DEFAULT_CACHE_CONTROL_KEY),
+            allowed_content_types: get_var(ALLOWED_CONTENT_TYPES_KEY),
+            put_object_mode: validate(
+                &mut errors,
+                PUT_OBJECT_MODE_KEY,
+                get_var(PUT_OBJECT_MODE_KEY),
+                PutObjectMode::parse,
+                defaults.put_object_mode,
+            ),
+            index_enabled: flag(INDEX_ENABLED_KEY, false),
+            max_batch_items: get_var(MAX_BATCH_ITEMS_KEY)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_batch_items),
+            decompress_on_read: flag(DECOMPRESS_ON_READ_KEY, true),
+            max_get_bytes: get_var(MAX_GET_BYTES_KEY).and_then(|v| v.parse().ok()),
+            lowercase_keys: flag(LOWERCASE_KEYS_KEY, false),
+            key_prefix: get_var(KEY_PREFIX_KEY),
+            key_strategy: validate(
+                &mut errors,
+                OBJECT_KEY_FORMAT_KEY,
+                get_var(OBJECT_KEY_FORMAT_KEY),
+                KeyStrategy::parse,
+                defaults.key_strategy,
+            ),
+            // Lambda always sets the function name, locally there is none to namespace by
+            function_namespace: if flag(NAMESPACE_BY_FUNCTION_KEY, false) {
+                get_var(FUNCTION_NAME_KEY).filter(|v| !v.is_empty())
+            } else {
+                None
+            },
+            dlq_bucket: get_var(DLQ_BUCKET_KEY).filter(|v| !v.is_empty()),
+            max_retries: get_var(S3_MAX_RETRIES_KEY)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_retries),
+            retriable_status_codes: get_var(RETRIABLE_STATUS_CODES_KEY)
+                .map(|v| parse_status_codes(&v))
+                .unwrap_or_default(),
+            local_replay_enabled: flag(LOCAL_REPLAY_ENABLED_KEY, false),
+            // an empty secret would accept an empty key, treat it as unset
+            api_key: get_var(API_KEY_KEY).filter(|v| !v.is_empty()),
+            hmac_secret: get_var(HMAC_SECRET_KEY).filter(|v| !v.is_empty()),
+            clock_skew_threshold_ms: get_var(CLOCK_SKEW_THRESHOLD_MS_KEY)
+                .and_then(|v| v.parse().ok()),
+            max_raw_event_bytes: get_var(MAX_RAW_EVENT_BYTES_KEY)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.max_raw_event_bytes),
+            analyze_text: flag(ANALYZE_TEXT_KEY, false),
+            // oversized batches are split and sent back to this same function
+            fan_out_function: if flag(FAN_OUT_BATCHES_KEY, false) {
+                get_var(FUNCTION_NAME_KEY).filter(|v| !v.is_empty())
+            } else {
+                None
+            },
+            handler_timeout_buffer_ms: get_var(LAMBDA_HANDLER_TIMEOUT_BUFFER_MS_KEY)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.handler_timeout_buffer_ms),
+            region: get_s3_region(local),
+            default_kms_key_id: get_var(DEFAULT_KMS_KEY_ID_KEY).filter(|v| !v.is_empty()),
+            require_explicit_kms_key: flag(REQUIRE_EXPLICIT_KMS_KEY_KEY, false),
+            prefer_items: flag(PREFER_ITEMS_KEY, false),
+            write_prefix: get_var(WRITE_PREFIX_KEY).filter(|v| !v.is_empty()),
+            event_payload_path: get_var(EVENT_PAYLOAD_PATH_KEY).filter(|v| !v.is_empty()),
+            max_key_depth: get_var(MAX_KEY_DEPTH_KEY).and_then(|v| v.parse().ok()),
+            internal_signing_key: get_var(INTERNAL_SIGNING_KEY_KEY).filter(|v| !v.is_empty()),
+            schema_version: get_var(SCHEMA_VERSION_KEY).filter(|v| !v.is_empty()),
+            sentry_dsn: get_var(SENTRY_DSN_KEY).filter(|v| !v.is_empty()),
+            auto_compress_threshold: get_var(AUTO_COMPRESS_THRESHOLD_KEY)
+                .and_then(|v| v.parse().ok()),
+            shutdown_deadline_ms: get_var(SHUTDOWN_DEADLINE_MS_KEY)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.shutdown_deadline_ms),
+            local,
+            mock_s3: cfg!(feature = "mock-s3") && flag(MOCK_KEY, false),
+            s3_user_agent: get_var(S3_USER_AGENT_KEY)
+                .filter(|v| !v.is_empty())
+                .unwrap_or(defaults.s3_user_agent),
+            s3_request_logging: flag(S3_REQUEST_LOGGING_KEY, false),
+            error_format: if flag(PROBLEM_JSON_KEY, false) {
+                ErrorFormat::ProblemJson
+            } else if flag(JSON_ERRORS_KEY, false) {
+                ErrorFormat::Json
+            } else {
+                defaults.error_format
+            },
+        };
+        // fan-out children are authorized by the internal signature alone
+        if config.fan_out_function.is_some() && config.internal_signing_key.is_none() {
+            errors.push(ConfigError {
+                field: INTERNAL_SIGNING_KEY_KEY.to_string(),
+                value: String::new(),
+                reason: format!("is required with {}", FAN_OUT_BATCHES_KEY),
+            });
+        }
+        if !errors.is_empty() {
+            return Err(ConfigValidationError(errors));
+        }
+        Ok(config)
+    }
+
+    /// The steps a put body goes through; normalization comes before the length
+    /// check so whitespace the user did not mean to send does not count.
+    pub fn build_pipeline(&self) -> PreprocessorChain {
+        let mut processors: Vec<Box<dyn Preprocessor>> =
+            vec![Box::new(BomStripper), Box::new(WhitespaceNormalizer)];
+        if self.redact_pii {
+            processors.push(Box::new(PiiRedactor));
+        }
+        processors.push(Box::new(MaxLengthEnforcer(MAX_TEXT_BODY_LEN)));
+        PreprocessorChain::new(processors)
+    }
+}
+
+// records a setting that doesn't parse and carries on with `default`, so the
+// rest of the settings are still checked
+fn validate<T, P>(
+    errors: &mut Vec<ConfigError>,
+    field: &str,
+    value: Option<String>,
+    parse: P,
+    default: T,
+) -> T
+where
+    P: FnOnce(Option<&str>) -> Result<T>,
+{
+    match parse(value.as_deref()) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            errors.push(ConfigError {
+                field: field.to_string(),
+                value: value.unwrap_or_default(),
+                reason: err.to_string(),
+            });
+            default
+        }
+    }
+}
+
+// a comma-separated list like `429,425`; entries that aren't numbers are skipped
+fn parse_status_codes(value: &str) -> Vec<u16> {
+    value
+        .split(',')
+        .filter_map(|code| code.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_map(vars: &[(&str, &str)]) -> std::result::Result<Config, ConfigValidationError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::from_vars(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn can_load_config_defaults() {
+        let config = from_map(&[(BUCKET_NAME_KEY, "test-bucket")]).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                bucket_name: "test-bucket".to_string(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn can_load_config_overrides() {
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (DECOMPRESS_ON_READ_KEY, ""),
+            (LOWERCASE_KEYS_KEY, "1"),
+            (INDEX_ENABLED_KEY, "false"),
+            (MAX_BATCH_ITEMS_KEY, "5"),
+            (S3_MAX_RETRIES_KEY, "not-a-number"),
+            (PUT_OBJECT_MODE_KEY, "fail_if_exists"),
+            (RETRIABLE_STATUS_CODES_KEY, "429, 425,teapot"),
+            (LAMBDA_HANDLER_TIMEOUT_BUFFER_MS_KEY, "1000"),
+        ])
+        .unwrap();
+        assert!(!config.decompress_on_read);
+        assert!(config.lowercase_keys);
+        assert!(!config.index_enabled);
+        assert_eq!(config.max_batch_items, 5);
+        assert_eq!(config.max_retries, DEFAULT_MAX_RETRIES);
+        assert_eq!(config.put_object_mode, PutObjectMode::FailIfExists);
+        assert_eq!(config.retriable_status_codes, vec![429, 425]);
+        assert_eq!(config.handler_timeout_buffer_ms, 1000);
+    }
+
+    #[test]
+    fn can_load_error_format() {
+        let format = |vars: &[(&str, &str)]| {
+            let mut vars = vars.to_vec();
+            vars.push((BUCKET_NAME_KEY, "test-bucket"));
+            from_map(&vars).unwrap().error_format
+        };
+        assert_eq!(format(&[]), ErrorFormat::Plain);
+        assert_eq!(format(&[(JSON_ERRORS_KEY, "1")]), ErrorFormat::Json);
+        assert_eq!(format(&[(PROBLEM_JSON_KEY, "1")]), ErrorFormat::ProblemJson);
+        assert_eq!(
+            format(&[(PROBLEM_JSON_KEY, "1"), (JSON_ERRORS_KEY, "1")]),
+            ErrorFormat::ProblemJson
+        );
+        assert_eq!(format(&[(PROBLEM_JSON_KEY, "off")]), ErrorFormat::Plain);
+    }
+
+    #[test]
+    fn can_load_local_and_s3_client_settings() {
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (LOCAL_KEY, "local"),
+            (MOCK_KEY, "1"),
+            (S3_USER_AGENT_KEY, "uploader/1.0"),
+            (S3_REQUEST_LOGGING_KEY, "1"),
+        ])
+        .unwrap();
+        assert!(config.local);
+        assert_eq!(config.region, get_s3_region(true));
+        assert_eq!(config.mock_s3, cfg!(feature = "mock-s3"));
+        assert_eq!(config.s3_user_agent, "uploader/1.0");
+        assert!(config.s3_request_logging);
+        let config =
+            from_map(&[(BUCKET_NAME_KEY, "test-bucket"), (S3_USER_AGENT_KEY, "")]).unwrap();
+        assert!(!config.local);
+        assert_eq!(config.region, Region::ApNortheast1);
+        assert!(!config.mock_s3);
+        assert_eq!(config.s3_user_agent, DEFAULT_USER_AGENT);
+        assert!(!config.s3_request_logging);
+    }
+
+    #[test]
+    fn can_load_function_namespace() {
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (NAMESPACE_BY_FUNCTION_KEY, "1"),
+            (FUNCTION_NAME_KEY, "hello"),
+        ])
+        .unwrap();
+        assert_eq!(config.function_namespace, Some("hello".to_string()));
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (NAMESPACE_BY_FUNCTION_KEY, "1"),
+        ])
+        .unwrap();
+        assert_eq!(config.function_namespace, None);
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (FUNCTION_NAME_KEY, "hello"),
+        ])
+        .unwrap();
+        assert_eq!(config.function_namespace, None);
+    }
+
+    #[test]
+    fn can_load_fan_out_function() {
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (FAN_OUT_BATCHES_KEY, "1"),
+            (FUNCTION_NAME_KEY, "hello"),
+            (INTERNAL_SIGNING_KEY_KEY, "internal-secret"),
+        ])
+        .unwrap();
+        assert_eq!(config.fan_out_function, Some("hello".to_string()));
+        let err = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (FAN_OUT_BATCHES_KEY, "1"),
+            (FUNCTION_NAME_KEY, "hello"),
+        ])
+        .expect_err("expected Err(_) value");
+        assert_eq!(err.0[0].field, INTERNAL_SIGNING_KEY_KEY);
+        let config = from_map(&[
+            (BUCKET_NAME_KEY, "test-bucket"),
+            (FUNCTION_NAME_KEY, "hello"),
+        ])
+        .unwrap();
+        assert_eq!(config.fan_out_function, None);
+    }
+
+    #[test]
+    fn can_build_pipeline_from_config() {
+        let config = Config::default();
+        let text = config.build_pipeline().run(" me@example.com ".to_string());
+        assert_eq!(text.unwrap(), "me@example.com");
+        let config = Config {
+            redact_pii: true,
+            ..Default::default()
+        };
+        let text = config.build_pipeline().run(" me@example.com ".to_string());
+        assert_eq!(text.unwrap(), "[email]");
+        assert!(config.build_pipeline().run("a".repeat(101)).is_err());
+    }
+
+    #[test]
+    fn can_reject_config_without_bucket() {
+        assert!(from_map(&[]).is_err());
+        assert!(from_map(&[(BUCKET_NAME_KEY, "b"), (PUT_OBJECT_MODE_KEY, "append")]).is_err());
+        assert!(from_map(&[(BUCKET_NAME_KEY, "b"), (OBJECT_KEY_FORMAT_KEY, "random")]).is_err());
+    }
+
+    #[test]
+    fn can_report_every_invalid_setting() {
+        let err = from_map(&[
+            (BUCKET_NAME_KEY, "b"),
+            (PUT_OBJECT_MODE_KEY, "append"),
+            (OBJECT_KEY_FORMAT_KEY, "random"),
+        ])
+        .expect_err("expected Err(_) value");
+        let fields: Vec<&str> = err.0.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec![PUT_OBJECT_MODE_KEY, OBJECT_KEY_FORMAT_KEY]);
+        assert_eq!(err.0[0].value, "append");
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: \
+             PUT_OBJECT_MODE=\"append\": Unknown PUT_OBJECT_MODE append; \
+             OBJECT_KEY_FORMAT=\"random\": Unknown OBJECT_KEY_FORMAT random"
+        );
+
+        let err = from_map(&[(PUT_OBJECT_MODE_KEY, "fail_if_exists")])
+            .expect_err("expected Err(_) value");
+        assert_eq!(
+            err,
+            ConfigValidationError(vec![ConfigError {
+                field: BUCKET_NAME_KEY.to_string(),
+                value: String::new(),
+                reason: "is not set".to_string(),
+            }])
+        );
+
+        assert!(from_map(&[
+            (BUCKET_NAME_KEY, "b"),
+            (PUT_OBJECT_MODE_KEY, "fail_if_changed"),
+            (OBJECT_KEY_FORMAT_KEY, "uuid_v4"),
+        ])
+        .is_ok());
+    }
+}
@@ -0,0 +1,41 @@
+//! Guards the wire format of `CustomEvent`. The schema derived from the structs
+//! must match the committed `schema/CustomEvent.json`; when a change to the
+//! event is intentional, regenerate that file in the same commit so the break
+//! is visible in review.
+
+use schemars::schema_for;
+use serde_json::Value;
+
+use crate::CustomEvent;
+
+const CURRENT_SCHEMA_JSON: &str = include_str!("../schema/CustomEvent.json");
+
+// lines only in one of the two pretty-printed schemas, good enough to spot the changed field
+fn get_schema_diff(committed: &str, current: &str) -> String {
+    let committed_lines: Vec<&str> = committed.lines().collect();
+    let current_lines: Vec<&str> = current.lines().collect();
+    let removed = committed_lines
+        .iter()
+        .filter(|line| !current_lines.contains(line))
+        .map(|line| format!("- {}", line));
+    let added = current_lines
+        .iter()
+        .filter(|line| !committed_lines.contains(line))
+        .map(|line| format!("+ {}", line));
+    removed.chain(added).collect::<Vec<_>>().join("\n")
+}
+
+#[test]
+fn can_keep_custom_event_schema_stable() {
+    let current = serde_json::to_value(schema_for!(CustomEvent)).unwrap();
+    let committed: Value = serde_json::from_str(CURRENT_SCHEMA_JSON).unwrap();
+    if current != committed {
+        panic!(
+            "CustomEvent schema changed, update schema/CustomEvent.json if this is intended:\n{}",
+            get_schema_diff(
+                &serde_json::to_string_pretty(&committed).unwrap(),
+                &serde_json::to_string_pretty(&current).unwrap()
+            )
+        );
+    }
+}
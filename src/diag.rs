@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use serde_json::{json, Value};
+
+const METRICS_NAMESPACE: &str = "lambda-rust-sample";
+
+/// Bytes uploaded by this container since it started, reported by the `diag` op.
+/// Only a sanity gauge: it resets on every cold start and is per container.
+pub static TOTAL_BYTES_WRITTEN: ByteCounter = ByteCounter::new();
+
+pub struct ByteCounter {
+    bytes: AtomicU64,
+}
+
+impl ByteCounter {
+    pub const fn new() -> ByteCounter {
+        ByteCounter {
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// True until the first invocation of this container takes it.
+pub static IS_COLD_START: ColdStart = ColdStart::new();
+
+pub struct ColdStart {
+    cold: AtomicBool,
+}
+
+impl ColdStart {
+    pub const fn new() -> ColdStart {
+        ColdStart {
+            cold: AtomicBool::new(true),
+        }
+    }
+
+    /// Returns true exactly once, for the invocation that started the container.
+    pub fn take(&self) -> bool {
+        self.cold.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// A `ColdStart` metric of 1 or 0 in CloudWatch embedded metric format, so the
+/// log line alone publishes it, the same way Lambda Powertools does. The
+/// `cold_start` and `requestId` fields are plain log fields next to it.
+pub fn build_cold_start_record(cold_start: bool, request_id: &str, timestamp_ms: u64) -> Value {
+    json!({
+        "cold_start": cold_start,
+        "requestId": request_id,
+        "_aws": {
+            "Timestamp": timestamp_ms,
+            "CloudWatchMetrics": [{
+                "Namespace": METRICS_NAMESPACE,
+                "Dimensions": [[]],
+                "Metrics": [{ "Name": "ColdStart", "Unit": "Count" }],
+            }],
+        },
+        "ColdStart": if cold_start { 1 } else { 0 },
+    })
+}
+
+/// Writes an EMF document as a bare JSON line. CloudWatch only extracts the
+/// metrics from lines that are nothing but the document, so this goes to stdout
+/// directly instead of through the logger and its timestamp and level prefix.
+pub fn write_metric_line<W: Write>(out: &mut W, record: &Value) -> io::Result<()> {
+    writeln!(out, "{}", record)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn can_sum_bytes_across_threads() {
+        let counter = Arc::new(ByteCounter::new());
+        let handles: Vec<_> = [9, 8]
+            .iter()
+            .map(|bytes| {
+                let counter = counter.clone();
+                thread::spawn(move || counter.add(*bytes))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(counter.get(), 17);
+    }
+
+    #[test]
+    fn can_take_cold_start_once() {
+        let cold_start = ColdStart::new();
+        assert!(cold_start.take());
+        assert!(!cold_start.take());
+        assert!(!cold_start.take());
+    }
+
+    #[test]
+    fn can_build_cold_start_record() {
+        let metric = build_cold_start_record(true, "request-id", 1605500000000);
+        assert_eq!(metric["cold_start"], true);
+        assert_eq!(metric["ColdStart"], 1);
+        assert_eq!(metric["_aws"]["Timestamp"], 1605500000000u64);
+        assert_eq!(
+            metric["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Name"],
+            "ColdStart"
+        );
+        let metric = build_cold_start_record(false, "request-id", 1605500000000);
+        assert_eq!(metric["cold_start"], false);
+        assert_eq!(metric["ColdStart"], 0);
+    }
+
+    #[test]
+    fn can_write_metric_line_as_bare_json() {
+        let mut out = Vec::new();
+        let record = build_cold_start_record(true, "request-id", 1605500000000);
+        write_metric_line(&mut out, &record).expect("expected Ok(_) value");
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.ends_with('\n'));
+        let line: Value = serde_json::from_str(out.trim_end()).unwrap();
+        assert_eq!(
+            line["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Name"],
+            "ColdStart"
+        );
+        assert_eq!(line["ColdStart"], 1);
+    }
+}
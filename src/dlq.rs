@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+pub use aws_lambda_events::event::sqs::{SqsEvent, SqsMessage};
+use serde_derive::Serialize;
+
+use crate::{parse_custom_event_streaming, CustomEvent};
+
+// message attributes Lambda attaches when it sends a failed async event to an SQS DLQ
+const ATTR_REQUEST_ID: &str = "RequestID";
+const ATTR_ERROR_CODE: &str = "ErrorCode";
+const ATTR_ERROR_MESSAGE: &str = "ErrorMessage";
+// system attributes set by SQS
+const ATTR_SENT_TIMESTAMP: &str = "SentTimestamp";
+const ATTR_FIRST_RECEIVE_TIMESTAMP: &str = "ApproximateFirstReceiveTimestamp";
+
+/// The `ReportBatchItemFailures` response: SQS deletes every message of the
+/// batch except the listed ones, which become visible again and are retried.
+#[derive(Serialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SqsBatchResponse {
+    pub batch_item_failures: Vec<SqsBatchItemFailure>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SqsBatchItemFailure {
+    pub item_identifier: String,
+}
+
+impl SqsBatchItemFailure {
+    pub fn new(sqs_message: &SqsMessage) -> SqsBatchItemFailure {
+        // SQS always sets it; an empty identifier makes Lambda retry the whole batch
+        SqsBatchItemFailure {
+            item_identifier: sqs_message.message_id.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DlqMessage {
+    pub original_event: CustomEvent,
+    pub error_code: String,
+    pub error_message: String,
+    pub request_id: String,
+    pub failed_at: String,
+}
+
+pub fn parse_dlq_message(sqs_message: &SqsMessage) -> Result<DlqMessage> {
+    let body = sqs_message
+        .body
+        .as_ref()
+        .ok_or_else(|| anyhow!("DLQ message has no body"))?;
+    let original_event = parse_custom_event_streaming(body.as_bytes())?;
+    // SentTimestamp is when Lambda gave up on the event; fall back to the first receive time
+    let failed_at = sqs_message
+        .attributes
+        .get(ATTR_SENT_TIMESTAMP)
+        .or_else(|| sqs_message.attributes.get(ATTR_FIRST_RECEIVE_TIMESTAMP))
+        .cloned()
+        .ok_or_else(|| anyhow!("DLQ message has no timestamp attribute"))?;
+    Ok(DlqMessage {
+        original_event,
+        error_code: get_message_attribute(sqs_message, ATTR_ERROR_CODE)?,
+        error_message: get_message_attribute(sqs_message, ATTR_ERROR_MESSAGE)?,
+        request_id: get_message_attribute(sqs_message, ATTR_REQUEST_ID)?,
+        failed_at,
+    })
+}
+
+fn get_message_attribute(sqs_message: &SqsMessage, name: &str) -> Result<String> {
+    sqs_message
+        .message_attributes
+        .get(name)
+        .and_then(|attr| attr.string_value.clone())
+        .ok_or_else(|| anyhow!("DLQ message has no {} attribute", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DLQ_RECORD: &str = r#"{
+        "messageId": "2e1424d4-f796-459a-8184-9c92662be6da",
+        "receiptHandle": "AQEBzWwaftRI0KuVm4tP+/7q1rGgNqicHq...",
+        "body": "{\"textBody\":\"Firstname\"}",
+        "attributes": {
+            "ApproximateReceiveCount": "1",
+            "SentTimestamp": "1605500000000",
+            "SenderId": "AIDAIENQZJOLO23YVJ4VO",
+            "ApproximateFirstReceiveTimestamp": "1605500000123"
+        },
+        "messageAttributes": {
+            "RequestID": {
+                "stringValue": "c6af9ac6-7b61-11e6-9a41-93e812345678",
+                "stringListValues": [],
+                "binaryListValues": [],
+                "dataType": "String"
+            },
+            "ErrorCode": {
+                "stringValue": "200",
+                "stringListValues": [],
+                "binaryListValues": [],
+                "dataType": "Number"
+            },
+            "ErrorMessage": {
+                "stringValue": "[400] Text body is too long (max: 100)",
+                "stringListValues": [],
+                "binaryListValues": [],
+                "dataType": "String"
+            }
+        },
+        "md5OfBody": "e4e68fb7bd0e697a0ae8f1bb342846b3",
+        "eventSource": "aws:sqs",
+        "eventSourceARN": "arn:aws:sqs:ap-northeast-1:123456789012:hello-dlq",
+        "awsRegion": "ap-northeast-1"
+    }"#;
+
+    #[test]
+    fn can_parse_dlq_message() {
+        let sqs_message: SqsMessage = serde_json::from_str(DLQ_RECORD).unwrap();
+        let message = parse_dlq_message(&sqs_message).expect("expected Ok(_) value");
+        assert_eq!(
+            message.original_event.text_body,
+            Some("Firstname".to_string())
+        );
+        assert_eq!(message.error_code, "200");
+        assert_eq!(
+            message.error_message,
+            "[400] Text body is too long (max: 100)"
+        );
+        assert_eq!(message.request_id, "c6af9ac6-7b61-11e6-9a41-93e812345678");
+        assert_eq!(message.failed_at, "1605500000000");
+    }
+
+    #[test]
+    fn can_parse_dlq_message_without_sent_timestamp() {
+        let mut sqs_message: SqsMessage = serde_json::from_str(DLQ_RECORD).unwrap();
+        sqs_message.attributes.remove(ATTR_SENT_TIMESTAMP);
+        let message = parse_dlq_message(&sqs_message).expect("expected Ok(_) value");
+        assert_eq!(message.failed_at, "1605500000123");
+    }
+
+    #[test]
+    fn can_parse_dlq_event_records() {
+        let event: SqsEvent =
+            serde_json::from_str(&format!(r#"{{"Records": [{}]}}"#, DLQ_RECORD)).unwrap();
+        assert_eq!(event.records.len(), 1);
+        assert!(parse_dlq_message(&event.records[0]).is_ok());
+    }
+
+    #[test]
+    fn can_reject_message_without_error_attributes() {
+        let mut sqs_message: SqsMessage = serde_json::from_str(DLQ_RECORD).unwrap();
+        sqs_message.message_attributes.remove(ATTR_ERROR_CODE);
+        let result = parse_dlq_message(&sqs_message);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn can_serialize_batch_response() {
+        let sqs_message: SqsMessage = serde_json::from_str(DLQ_RECORD).unwrap();
+        let response = SqsBatchResponse {
+            batch_item_failures: vec![SqsBatchItemFailure::new(&sqs_message)],
+        };
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({
+                "batchItemFailures": [
+                    { "itemIdentifier": "2e1424d4-f796-459a-8184-9c92662be6da" }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn can_reject_message_with_invalid_body() {
+        let mut sqs_message: SqsMessage = serde_json::from_str(DLQ_RECORD).unwrap();
+        sqs_message.body = Some("not json".to_string());
+        assert!(parse_dlq_message(&sqs_message).is_err());
+    }
+}
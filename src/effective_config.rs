@@ -0,0 +1,98 @@
+use serde_derive::Serialize;
+
+use crate::config::Config;
+use crate::uploader::{UploadParams, UPLOAD_ACL};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// What a put sent to S3 once request overrides and deployment defaults were
+/// applied. Secrets only say whether they are set.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectiveConfig {
+    pub bucket: String,
+    pub key: String,
+    pub acl: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kms_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hmac_secret: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub internal_signing_key: Option<&'static str>,
+}
+
+impl EffectiveConfig {
+    pub fn new(params: &UploadParams, config: &Config) -> EffectiveConfig {
+        EffectiveConfig {
+            bucket: params.bucket.clone(),
+            key: params.key.clone(),
+            acl: UPLOAD_ACL.to_string(),
+            content_type: params.content_type.clone(),
+            cache_control: params.cache_control.clone(),
+            content_language: params.content_language.clone(),
+            content_encoding: params.content_encoding.clone(),
+            expires: params.expires.clone(),
+            encryption: params.server_side_encryption.clone(),
+            kms_key_id: params.ssekms_key_id.clone(),
+            api_key: redact(&config.api_key),
+            hmac_secret: redact(&config.hmac_secret),
+            internal_signing_key: redact(&config.internal_signing_key),
+        }
+    }
+}
+
+fn redact(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| REDACTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_redact_configured_secrets() {
+        let params = UploadParams {
+            bucket: "test-bucket".to_string(),
+            key: "test.txt".to_string(),
+            body: b"Firstname".to_vec(),
+            cache_control: None,
+            content_type: None,
+            content_language: None,
+            content_encoding: None,
+            expires: None,
+            website_redirect_location: None,
+            metadata: None,
+            tagging: None,
+            server_side_encryption: None,
+            ssekms_key_id: None,
+        };
+        let config = Config {
+            hmac_secret: Some("hmac-secret".to_string()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(EffectiveConfig::new(&params, &config)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "bucket": "test-bucket",
+                "key": "test.txt",
+                "acl": "public-read",
+                "hmacSecret": "[REDACTED]",
+            })
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::Config;
+use crate::LambdaError;
+
+const SSE_S3: &str = "AES256";
+const SSE_KMS: &str = "aws:kms";
+
+/// Server-side encryption headers for a put. `kms_key_id` is only set for
+/// `aws:kms`; without it S3 uses the account's default `aws/s3` key.
+#[derive(Debug, PartialEq)]
+pub struct Encryption {
+    pub algorithm: String,
+    pub kms_key_id: Option<String>,
+}
+
+/// No `encryption` leaves the bucket's default encryption in charge. A KMS
+/// request without a key falls back to `DEFAULT_KMS_KEY_ID`, and with
+/// `REQUIRE_EXPLICIT_KMS_KEY` it is rejected rather than left to S3's default.
+pub fn resolve_encryption(
+    requested: Option<&str>,
+    kms_key_id: Option<String>,
+    config: &Config,
+) -> Result<Option<Encryption>, LambdaError> {
+    let kms_key_id = kms_key_id.filter(|key_id| !key_id.is_empty());
+    match requested {
+        None | Some("") => Ok(None),
+        // a key is meaningless for SSE-S3, S3 rejects the combination
+        Some(SSE_S3) if kms_key_id.is_none() => Ok(Some(Encryption {
+            algorithm: SSE_S3.to_string(),
+            kms_key_id: None,
+        })),
+        Some(SSE_KMS) => {
+            let kms_key_id = kms_key_id.or_else(|| config.default_kms_key_id.clone());
+            if kms_key_id.is_none() && config.require_explicit_kms_key {
+                return Err(LambdaError::MissingKmsKey);
+            }
+            Ok(Some(Encryption {
+                algorithm: SSE_KMS.to_string(),
+                kms_key_id,
+            }))
+        }
+        Some(name) => Err(LambdaError::UnsupportedEncryption(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_ID: &str = "arn:aws:kms:ap-northeast-1:123456789012:key/1234abcd";
+
+    #[test]
+    fn can_resolve_encryption() {
+        let config = Config::default();
+        assert_eq!(resolve_encryption(None, None, &config), Ok(None));
+        assert_eq!(
+            resolve_encryption(Some("AES256"), None, &config),
+            Ok(Some(Encryption {
+                algorithm: "AES256".to_string(),
+                kms_key_id: None,
+            }))
+        );
+        assert_eq!(
+            resolve_encryption(Some("aws:kms"), Some(KEY_ID.to_string()), &config),
+            Ok(Some(Encryption {
+                algorithm: "aws:kms".to_string(),
+                kms_key_id: Some(KEY_ID.to_string()),
+            }))
+        );
+        assert_eq!(
+            resolve_encryption(Some("AES256"), Some(KEY_ID.to_string()), &config),
+            Err(LambdaError::UnsupportedEncryption("AES256".to_string()))
+        );
+        assert_eq!(
+            resolve_encryption(Some("aws:kms:dsse"), None, &config),
+            Err(LambdaError::UnsupportedEncryption(
+                "aws:kms:dsse".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn can_fall_back_to_default_kms_key() {
+        let config = Config::default();
+        assert_eq!(
+            resolve_encryption(Some("aws:kms"), None, &config),
+            Ok(Some(Encryption {
+                algorithm: "aws:kms".to_string(),
+                kms_key_id: None,
+            }))
+        );
+        let config = Config {
+            default_kms_key_id: Some(KEY_ID.to_string()),
+            require_explicit_kms_key: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_encryption(Some("aws:kms"), None, &config),
+            Ok(Some(Encryption {
+                algorithm: "aws:kms".to_string(),
+                kms_key_id: Some(KEY_ID.to_string()),
+            }))
+        );
+    }
+
+    #[test]
+    fn can_reject_kms_without_key_when_required() {
+        let config = Config {
+            require_explicit_kms_key: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            resolve_encryption(Some("aws:kms"), None, &config),
+            Err(LambdaError::MissingKmsKey)
+        );
+        assert_eq!(
+            resolve_encryption(Some("aws:kms"), Some(String::new()), &config),
+            Err(LambdaError::MissingKmsKey)
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use sentry::{ClientInitGuard, ClientOptions};
+
+// Lambda freezes the container as soon as the handler returns, so queued events
+// are flushed at the end of every invocation instead of on shutdown
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Starts the Sentry client for `SENTRY_DSN`; events are only sent while the
+/// returned guard is alive, so `main` keeps it for the life of the process.
+pub fn init(dsn: &str) -> ClientInitGuard {
+    sentry::init((
+        dsn,
+        ClientOptions {
+            release: sentry::release_name!(),
+            ..Default::default()
+        },
+    ))
+}
+
+pub fn start_session() {
+    sentry::start_session();
+}
+
+/// Sends the handler error, if any; does nothing before `init`.
+pub fn capture<T>(result: &Result<T>) {
+    if let Err(err) = result {
+        sentry_anyhow::capture_anyhow(err);
+    }
+}
+
+/// Ends the invocation's session and waits for its events to be sent.
+pub fn flush() {
+    sentry::end_session();
+    if let Some(client) = sentry::Hub::current().client() {
+        client.flush(Some(FLUSH_TIMEOUT));
+    }
+}
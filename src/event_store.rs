@@ -0,0 +1,88 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::CustomEvent;
+
+// /tmp is the only writable path on Lambda and survives warm invocations
+pub const DEFAULT_EVENT_LOG_PATH: &str = "/tmp/events.ndjson";
+
+/// Newline-delimited JSON log of received events, for replaying them locally.
+pub struct EventStore {
+    path: PathBuf,
+}
+
+impl EventStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> EventStore {
+        EventStore { path: path.into() }
+    }
+
+    pub fn append(&self, event: &CustomEvent) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // one write per line so concurrent appends don't interleave within a record
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Events in the order they were appended; a missing log is empty.
+    pub fn replay(&self) -> Result<Vec<CustomEvent>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut events = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                events.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn can_append_and_replay_events() {
+        let file = NamedTempFile::new().unwrap();
+        let store = EventStore::new(file.path());
+        for text in &["Firstname", "Lastname"] {
+            let event = CustomEvent {
+                text_body: Some(text.to_string()),
+                ..Default::default()
+            };
+            store.append(&event).expect("expected Ok(_) value");
+        }
+        let events = store.replay().expect("expected Ok(_) value");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].text_body, Some("Firstname".to_string()));
+        assert_eq!(events[1].text_body, Some("Lastname".to_string()));
+    }
+
+    #[test]
+    fn can_replay_missing_log_as_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = EventStore::new(dir.path().join("events.ndjson"));
+        assert!(store.replay().expect("expected Ok(_) value").is_empty());
+    }
+
+    #[test]
+    fn can_reject_corrupted_log() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "not json").unwrap();
+        let store = EventStore::new(file.path());
+        assert!(store.replay().is_err());
+    }
+}
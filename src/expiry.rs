@@ -0,0 +1,63 @@
+use std::time::{Duration, SystemTime};
+
+use crate::clock_skew;
+use crate::http_date::format_http_date;
+use crate::LambdaError;
+
+// the longest S3 accepts for a signed URL, Expires has no limit of its own
+pub const MAX_EXPIRES_IN_SECONDS: u64 = 7 * 365 * 24 * 60 * 60;
+
+pub fn check_expires_in(seconds: u64) -> Result<(), LambdaError> {
+    if (1..=MAX_EXPIRES_IN_SECONDS).contains(&seconds) {
+        Ok(())
+    } else {
+        Err(LambdaError::InvalidExpiry)
+    }
+}
+
+/// The `Expires` header for an object that should be considered stale
+/// `ttl_seconds` from now. S3 only stores it; nothing is deleted.
+pub fn compute_expiry_header(ttl_seconds: u64) -> String {
+    compute_expiry_header_at(clock_skew::now(), ttl_seconds)
+}
+
+fn compute_expiry_header_at(now: SystemTime, ttl_seconds: u64) -> String {
+    format_http_date(now + Duration::from_secs(ttl_seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn can_check_expires_in_bounds() {
+        assert_eq!(check_expires_in(0), Err(LambdaError::InvalidExpiry));
+        assert_eq!(check_expires_in(1), Ok(()));
+        assert_eq!(check_expires_in(MAX_EXPIRES_IN_SECONDS), Ok(()));
+        assert_eq!(
+            check_expires_in(MAX_EXPIRES_IN_SECONDS + 1),
+            Err(LambdaError::InvalidExpiry)
+        );
+    }
+
+    #[test]
+    fn can_compute_expiry_header() {
+        let now = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(
+            compute_expiry_header_at(now, 1),
+            "Sun, 06 Nov 1994 08:49:38 GMT"
+        );
+        assert_eq!(
+            compute_expiry_header_at(now, MAX_EXPIRES_IN_SECONDS),
+            "Sun, 04 Nov 2001 08:49:37 GMT"
+        );
+    }
+
+    #[test]
+    fn can_compute_expiry_header_from_now() {
+        let header = compute_expiry_header(60);
+        assert!(header.ends_with(" GMT"));
+        assert_eq!(header.len(), "Sun, 06 Nov 1994 08:49:38 GMT".len());
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use rusoto_lambda::{InvocationRequest, Lambda};
+
+use crate::auth::get_unix_now;
+use crate::internal_signature::sign_internal_event;
+use crate::{BatchItem, CustomEvent};
+
+/// Splits `items` into batches of at most `chunk_size` and invokes
+/// `function_name` asynchronously with each one, taking every other field from
+/// `template`. The caller's API key and signature are not copied: each child is
+/// authorized by an internal signature made with `signing_key` over the whole
+/// child event, including `request_id` and `signedAt`, so a child only verifies
+/// within `SIGNATURE_MAX_AGE_SECS` of being sent. Returns the number of invocations.
+pub async fn fan_out_batch(
+    mut items: Vec<BatchItem>,
+    template: &CustomEvent,
+    function_name: &str,
+    chunk_size: usize,
+    request_id: &str,
+    signing_key: &str,
+    lambda: &dyn Lambda,
+) -> Result<usize> {
+    let mut invocations = 0;
+    while !items.is_empty() {
+        let rest = items.split_off(chunk_size.max(1).min(items.len()));
+        let chunk = std::mem::replace(&mut items, rest);
+        // everything else is copied, so a new event field reaches the children too
+        let mut event = CustomEvent {
+            items: Some(chunk),
+            api_key: None,
+            signature: None,
+            signed_at: Some(get_unix_now()),
+            internal_signature: None,
+            internal_request_id: Some(request_id.to_string()),
+            ..template.clone()
+        };
+        event.internal_signature = Some(sign_internal_event(&event, signing_key.as_bytes()));
+        lambda
+            .invoke(InvocationRequest {
+                function_name: function_name.to_string(),
+                // fire and forget, each chunk is retried by Lambda on its own
+                invocation_type: Some("Event".to_string()),
+                payload: Some(serde_json::to_vec(&event)?.into()),
+                ..Default::default()
+            })
+            .await?;
+        invocations += 1;
+    }
+    Ok(invocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal_signature::verify_internal_event;
+    use rusoto_core::signature::SignedRequestPayload;
+    use rusoto_core::Region;
+    use rusoto_lambda::LambdaClient;
+    use rusoto_mock::{MockCredentialsProvider, MockRequestDispatcher};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn get_items(count: usize) -> Vec<BatchItem> {
+        (0..count)
+            .map(|i| BatchItem {
+                key: Some(format!("{}.txt", i)),
+                text_body: Some("Firstname".to_string()),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn can_fan_out_batch_in_chunks() {
+        for &(count, expected) in &[(1, 1), (25, 1), (26, 2), (51, 3)] {
+            let invocations = Arc::new(AtomicUsize::new(0));
+            let seen = invocations.clone();
+            let lambda = LambdaClient::new_with(
+                MockRequestDispatcher::with_status(202).with_request_checker(move |request| {
+                    assert_eq!(request.path, "/2015-03-31/functions/hello/invocations");
+                    assert_eq!(
+                        request.headers.get("x-amz-invocation-type"),
+                        Some(&vec![b"Event".to_vec()])
+                    );
+                    seen.fetch_add(1, Ordering::SeqCst);
+                }),
+                MockCredentialsProvider,
+                Region::ApNortheast1,
+            );
+            let template = CustomEvent {
+                api_key: Some("secret".to_string()),
+                ..Default::default()
+            };
+            let result = fan_out_batch(
+                get_items(count),
+                &template,
+                "hello",
+                25,
+                "parent-request",
+                "internal-secret",
+                &lambda,
+            )
+            .await
+            .expect("expected Ok(_) value");
+            assert_eq!(result, expected);
+            assert_eq!(invocations.load(Ordering::SeqCst), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn can_fan_out_batch_report_invoke_error() {
+        let lambda = LambdaClient::new_with(
+            MockRequestDispatcher::with_status(500),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let result = fan_out_batch(
+            get_items(2),
+            &CustomEvent::default(),
+            "hello",
+            25,
+            "parent-request",
+            "internal-secret",
+            &lambda,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn can_fan_out_batch_keep_template_fields() {
+        let lambda = LambdaClient::new_with(
+            MockRequestDispatcher::with_status(202).with_request_checker(|request| {
+                let body = match &request.payload {
+                    Some(SignedRequestPayload::Buffer(body)) => body,
+                    _ => panic!("expected a buffered payload"),
+                };
+                let event: CustomEvent = serde_json::from_slice(body).unwrap();
+                assert_eq!(event.suffix_on_collision, Some(true));
+                assert_eq!(event.content_type, Some("text/markdown".to_string()));
+                assert_eq!(event.items.map(|items| items.len()), Some(2));
+            }),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let template = CustomEvent {
+            suffix_on_collision: Some(true),
+            content_type: Some("text/markdown".to_string()),
+            ..Default::default()
+        };
+        let result = fan_out_batch(
+            get_items(2),
+            &template,
+            "hello",
+            25,
+            "parent-request",
+            "internal-secret",
+            &lambda,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(result, 1);
+    }
+
+    #[tokio::test]
+    async fn can_fan_out_batch_sign_child_events() {
+        let lambda = LambdaClient::new_with(
+            MockRequestDispatcher::with_status(202).with_request_checker(|request| {
+                let body = match &request.payload {
+                    Some(SignedRequestPayload::Buffer(body)) => body,
+                    _ => panic!("expected a buffered payload"),
+                };
+                let event: CustomEvent = serde_json::from_slice(body).unwrap();
+                assert_eq!(
+                    event.internal_request_id,
+                    Some("parent-request".to_string())
+                );
+                let sig = event.internal_signature.clone().unwrap();
+                assert!(verify_internal_event(&event, &sig, b"internal-secret"));
+                assert!(event.signed_at.is_some());
+                // the caller's credentials stay with the parent invocation
+                assert_eq!(event.api_key, None);
+                assert_eq!(event.signature, None);
+            }),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let template = CustomEvent {
+            api_key: Some("secret".to_string()),
+            signature: Some("caller-signature".to_string()),
+            ..Default::default()
+        };
+        let result = fan_out_batch(
+            get_items(2),
+            &template,
+            "hello",
+            25,
+            "parent-request",
+            "internal-secret",
+            &lambda,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(result, 1);
+    }
+}
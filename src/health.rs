@@ -0,0 +1,151 @@
+use std::time::{Duration, Instant};
+
+use rusoto_s3::{HeadBucketRequest, S3Client, S3};
+use serde_derive::Serialize;
+use tokio::time::timeout;
+
+use crate::config::Config;
+
+const STATUS_OK: &str = "ok";
+const STATUS_ERROR: &str = "error";
+
+/// Overall status is `ok` only when every check that ran passed.
+#[derive(Serialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub status: String,
+    pub checks: Vec<HealthCheck>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: String,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HealthCheck {
+    fn new(name: &str, started_at: Instant, result: Result<(), String>) -> HealthCheck {
+        let (status, error) = match result {
+            Ok(()) => (STATUS_OK, None),
+            Err(err) => (STATUS_ERROR, Some(err)),
+        };
+        HealthCheck {
+            name: name.to_string(),
+            status: status.to_string(),
+            latency_ms: started_at.elapsed().as_millis() as u64,
+            error,
+        }
+    }
+}
+
+/// Without `deep` only the config is checked, a cheap liveness probe; with it
+/// HeadBucket is sent too, so readiness also covers S3 and the bucket policy.
+pub async fn health_report(
+    s3: &S3Client,
+    config: &Config,
+    deep: bool,
+    limit: Duration,
+) -> HealthReport {
+    let started_at = Instant::now();
+    let mut checks = vec![HealthCheck::new("config", started_at, check_config(config))];
+    if deep {
+        let started_at = Instant::now();
+        let result = check_bucket(s3, &config.bucket_name, limit).await;
+        checks.push(HealthCheck::new("s3", started_at, result));
+    }
+    let status = if checks.iter().all(|check| check.status == STATUS_OK) {
+        STATUS_OK
+    } else {
+        STATUS_ERROR
+    };
+    HealthReport {
+        status: status.to_string(),
+        checks,
+    }
+}
+
+// `from_env` already rejects a missing bucket, this catches an empty one
+fn check_config(config: &Config) -> Result<(), String> {
+    if config.bucket_name.is_empty() {
+        return Err("bucket name is empty".to_string());
+    }
+    Ok(())
+}
+
+async fn check_bucket(s3: &S3Client, bucket_name: &str, limit: Duration) -> Result<(), String> {
+    let request = HeadBucketRequest {
+        bucket: bucket_name.to_string(),
+        ..Default::default()
+    };
+    match timeout(limit, s3.head_bucket(request)).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err(format!("timed out after {}ms", limit.as_millis())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::Region;
+    use rusoto_mock::{MockCredentialsProvider, MockRequestDispatcher};
+
+    const LIMIT: Duration = Duration::from_secs(1);
+
+    fn get_config() -> Config {
+        Config {
+            bucket_name: "test-bucket".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn get_s3(dispatcher: MockRequestDispatcher) -> S3Client {
+        S3Client::new_with(dispatcher, MockCredentialsProvider, Region::ApNortheast1)
+    }
+
+    #[tokio::test]
+    async fn can_report_all_healthy() {
+        let s3 = get_s3(
+            MockRequestDispatcher::default().with_request_checker(|request| {
+                assert_eq!(request.method, "HEAD");
+                assert_eq!(request.path, "/test-bucket");
+            }),
+        );
+        let report = health_report(&s3, &get_config(), true, LIMIT).await;
+        assert_eq!(report.status, STATUS_OK);
+        let names: Vec<&str> = report.checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["config", "s3"]);
+        assert!(report.checks.iter().all(|c| c.error.is_none()));
+    }
+
+    #[tokio::test]
+    async fn can_report_s3_unreachable() {
+        let s3 = get_s3(MockRequestDispatcher::with_status(403));
+        let report = health_report(&s3, &get_config(), true, LIMIT).await;
+        assert_eq!(report.status, STATUS_ERROR);
+        assert_eq!(report.checks[0].status, STATUS_OK);
+        assert_eq!(report.checks[1].name, "s3");
+        assert_eq!(report.checks[1].status, STATUS_ERROR);
+        assert!(report.checks[1].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn can_skip_s3_check_when_shallow() {
+        // a HeadBucket would get the 403 and fail the report
+        let s3 = get_s3(MockRequestDispatcher::with_status(403));
+        let report = health_report(&s3, &get_config(), false, LIMIT).await;
+        assert_eq!(report.status, STATUS_OK);
+        assert_eq!(report.checks.len(), 1);
+
+        let config = Config {
+            bucket_name: String::new(),
+            ..get_config()
+        };
+        let report = health_report(&s3, &config, false, LIMIT).await;
+        assert_eq!(report.status, STATUS_ERROR);
+    }
+}
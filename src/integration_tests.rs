@@ -0,0 +1,53 @@
+//! Tests against a real S3 API provided by LocalStack.
+//! Run with `cargo test --features integration-tests`.
+
+use std::env;
+
+use rusoto_core::Region;
+use rusoto_s3::{
+    CreateBucketConfiguration, CreateBucketRequest, HeadObjectRequest, PutObjectRequest, S3Client,
+    S3,
+};
+
+const LOCALSTACK_ENDPOINT_KEY: &str = "LOCALSTACK_ENDPOINT";
+const BUCKET_NAME: &str = "lambda-rust-sample-integration";
+
+fn get_localstack_client() -> S3Client {
+    S3Client::new(Region::Custom {
+        name: "ap-northeast-1".to_owned(),
+        endpoint: env::var(LOCALSTACK_ENDPOINT_KEY)
+            .unwrap_or_else(|_| "http://localhost:4566".to_owned()),
+    })
+}
+
+#[tokio::test]
+async fn can_put_object_to_localstack() {
+    let s3 = get_localstack_client();
+    // the bucket survives between runs against the same container
+    let _ = s3
+        .create_bucket(CreateBucketRequest {
+            bucket: BUCKET_NAME.to_string(),
+            create_bucket_configuration: Some(CreateBucketConfiguration {
+                location_constraint: Some("ap-northeast-1".to_string()),
+            }),
+            ..Default::default()
+        })
+        .await;
+    s3.put_object(PutObjectRequest {
+        bucket: BUCKET_NAME.to_string(),
+        key: "test.txt".to_string(),
+        body: Some("Firstname".to_string().into_bytes().into()),
+        ..Default::default()
+    })
+    .await
+    .expect("expected Ok(_) value");
+    let head = s3
+        .head_object(HeadObjectRequest {
+            bucket: BUCKET_NAME.to_string(),
+            key: "test.txt".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("expected Ok(_) value");
+    assert_eq!(head.content_length, Some(9));
+}
@@ -0,0 +1,127 @@
+use hmac::{Hmac, Mac, NewMac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::CustomEvent;
+
+/// Signs a fan-out child event with `INTERNAL_SIGNING_KEY`, so the child can tell
+/// it was sent by this function and not by an outside caller. The hex
+/// HMAC-SHA256 covers a SHA-256 digest of `get_signing_payload`, so every field
+/// including the parent's request id (`internalRequestId`) and `signedAt` is signed.
+pub fn sign_internal_event(event: &CustomEvent, key: &[u8]) -> String {
+    hex::encode(get_mac(event, key).finalize().into_bytes())
+}
+
+/// Compares in constant time; a signature that is not hex never verifies.
+pub fn verify_internal_event(event: &CustomEvent, sig: &str, key: &[u8]) -> bool {
+    match hex::decode(sig) {
+        Ok(sig) => get_mac(event, key).verify(&sig).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn get_mac(event: &CustomEvent, key: &[u8]) -> Hmac<Sha256> {
+    // HMAC takes keys of any length, new_varkey can't fail for it
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts any key length");
+    mac.update(&get_digest(event));
+    mac
+}
+
+fn get_digest(event: &CustomEvent) -> Vec<u8> {
+    Sha256::digest(&get_signing_payload(event)).to_vec()
+}
+
+/// What both the `hmac-auth` signature and the internal signature are computed
+/// over: the event as compact JSON with sorted keys, leaving out `null` members
+/// and the two signatures themselves. A field can't be changed without
+/// breaking the signature, and sending `null` signs the same as leaving it out.
+pub fn get_signing_payload(event: &CustomEvent) -> Vec<u8> {
+    // the event is plain strings and numbers, serializing it never fails
+    let mut value = serde_json::to_value(event).unwrap_or_default();
+    if let Value::Object(fields) = &mut value {
+        fields.remove("signature");
+        fields.remove("internalSignature");
+    }
+    // serde_json's map keeps its keys sorted
+    serde_json::to_vec(&strip_nulls(value)).unwrap_or_default()
+}
+
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(name, value)| (name, strip_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(strip_nulls).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BatchItem;
+
+    const KEY: &[u8] = b"internal-secret";
+
+    fn get_event() -> CustomEvent {
+        CustomEvent {
+            items: Some(vec![BatchItem {
+                key: Some("a.txt".to_string()),
+                text_body: Some("Firstname".to_string()),
+            }]),
+            internal_request_id: Some("parent-request".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn can_verify_internal_signature() {
+        let event = get_event();
+        let sig = sign_internal_event(&event, KEY);
+        assert_eq!(sig.len(), 64);
+        assert!(verify_internal_event(&event, &sig, KEY));
+        assert!(!verify_internal_event(&event, &sig, b"other-secret"));
+        assert!(!verify_internal_event(&event, "not-hex", KEY));
+    }
+
+    #[test]
+    fn can_reject_internal_signature_for_changed_event() {
+        let sig = sign_internal_event(&get_event(), KEY);
+        let event = CustomEvent {
+            items: Some(vec![BatchItem {
+                key: Some("a.txt".to_string()),
+                text_body: Some("Lastname".to_string()),
+            }]),
+            ..get_event()
+        };
+        assert!(!verify_internal_event(&event, &sig, KEY));
+        let event = CustomEvent {
+            internal_request_id: Some("other-request".to_string()),
+            ..get_event()
+        };
+        assert!(!verify_internal_event(&event, &sig, KEY));
+        let event = CustomEvent {
+            content_type: Some("text/html".to_string()),
+            ..get_event()
+        };
+        assert!(!verify_internal_event(&event, &sig, KEY));
+    }
+
+    #[test]
+    fn can_build_signing_payload() {
+        let event = CustomEvent {
+            op: Some("put".to_string()),
+            signature: Some("ignored".to_string()),
+            internal_signature: Some("ignored".to_string()),
+            ..get_event()
+        };
+        assert_eq!(
+            String::from_utf8(get_signing_payload(&event)).unwrap(),
+            r#"{"internalRequestId":"parent-request","items":[{"key":"a.txt","textBody":"Firstname"}],"op":"put"}"#
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Result;
+use rusoto_s3::{ListObjectsV2Request, Object, S3Client, S3};
+
+const CSV_HEADER: &str = "key,size,last_modified,etag";
+// S3 never returns more than this per ListObjectsV2 call
+pub const MAX_LIST_RESULTS: i64 = 1000;
+
+/// One page of results; pass `next_token` back to get the next one.
+/// `page_size` is the page size S3 applied, which can be below the one asked for.
+#[derive(Debug, PartialEq)]
+pub struct PagedOutput<T> {
+    pub items: Vec<T>,
+    pub next_token: Option<String>,
+    pub is_truncated: bool,
+    pub page_size: usize,
+}
+
+/// Lists at most `max_results` keys under `prefix`, so large buckets are paged
+/// by the caller instead of being held in memory.
+pub async fn list_key_page(
+    s3: &S3Client,
+    bucket_name: &str,
+    prefix: Option<&str>,
+    max_results: i64,
+    continuation_token: Option<String>,
+) -> Result<PagedOutput<String>> {
+    let max_keys = max_results.max(1).min(MAX_LIST_RESULTS);
+    let output = s3
+        .list_objects_v2(ListObjectsV2Request {
+            bucket: bucket_name.to_string(),
+            prefix: prefix.map(str::to_string),
+            max_keys: Some(max_keys),
+            continuation_token,
+            ..Default::default()
+        })
+        .await?;
+    let page_size = output.max_keys.unwrap_or(max_keys) as usize;
+    let items = output
+        .contents
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|object| object.key)
+        .collect();
+    let is_truncated = output.is_truncated.unwrap_or(false);
+    let next_token = if is_truncated {
+        output.next_continuation_token
+    } else {
+        None
+    };
+    Ok(PagedOutput {
+        items,
+        next_token,
+        is_truncated,
+        page_size,
+    })
+}
+
+/// Lists every object under `prefix`, following continuation tokens across pages.
+pub async fn list_all_objects(
+    s3: &S3Client,
+    bucket_name: &str,
+    prefix: Option<&str>,
+) -> Result<Vec<Object>> {
+    let mut objects = Vec::new();
+    let mut continuation_token = None;
+    loop {
+        let output = s3
+            .list_objects_v2(ListObjectsV2Request {
+                bucket: bucket_name.to_string(),
+                prefix: prefix.map(str::to_string),
+                continuation_token: continuation_token.take(),
+                ..Default::default()
+            })
+            .await?;
+        objects.extend(output.contents.unwrap_or_default());
+        match output.next_continuation_token {
+            Some(token) if output.is_truncated.unwrap_or(false) => continuation_token = Some(token),
+            _ => return Ok(objects),
+        }
+    }
+}
+
+/// Renders `key,size,last_modified,etag` rows with a header line.
+pub fn build_inventory_csv(objects: &[Object]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for object in objects {
+        let row = [
+            escape_csv_field(object.key.as_deref().unwrap_or_default()),
+            object.size.unwrap_or_default().to_string(),
+            escape_csv_field(object.last_modified.as_deref().unwrap_or_default()),
+            escape_csv_field(object.e_tag.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+// ETags come quoted from S3 and keys may contain commas, so quote per RFC 4180
+fn escape_csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::Region;
+    use rusoto_mock::{
+        MockCredentialsProvider, MockRequestDispatcher, MultipleMockRequestDispatcher,
+    };
+
+    fn list_page(keys: &[&str], next_token: Option<&str>) -> MockRequestDispatcher {
+        let contents: String = keys
+            .iter()
+            .map(|key| {
+                format!(
+                    "<Contents><Key>{}</Key><LastModified>2020-11-16T00:00:00.000Z</LastModified>\
+                     <ETag>&quot;etag-{}&quot;</ETag><Size>9</Size></Contents>",
+                    key, key
+                )
+            })
+            .collect();
+        let token = next_token
+            .map(|token| format!("<NextContinuationToken>{}</NextContinuationToken>", token))
+            .unwrap_or_default();
+        MockRequestDispatcher::default().with_body(&format!(
+            "<ListBucketResult><Name>bucket</Name><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+            next_token.is_some(),
+            token,
+            contents
+        ))
+    }
+
+    #[tokio::test]
+    async fn can_list_all_objects_across_pages() {
+        let s3 = S3Client::new_with(
+            MultipleMockRequestDispatcher::new(vec![
+                list_page(&["logs/a.txt", "logs/b.txt"], Some("page-2")),
+                list_page(&["logs/c.txt"], None),
+            ]),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let objects = list_all_objects(&s3, "bucket", Some("logs/"))
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(objects.len(), 3);
+
+        let csv = build_inventory_csv(&objects);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            r#"logs/a.txt,9,2020-11-16T00:00:00.000Z,"""etag-logs/a.txt""""#
+        );
+    }
+
+    #[tokio::test]
+    async fn can_list_key_pages_with_continuation_token() {
+        let first_page = list_page(&["logs/a.txt", "logs/b.txt"], Some("page-2"))
+            .with_request_checker(|request| {
+                assert_eq!(request.params.get("max-keys"), Some(&Some("2".to_string())));
+                assert_eq!(request.params.get("continuation-token"), None);
+            });
+        let s3 = S3Client::new_with(first_page, MockCredentialsProvider, Region::ApNortheast1);
+        let page = list_key_page(&s3, "bucket", Some("logs/"), 2, None)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(page.items, vec!["logs/a.txt", "logs/b.txt"]);
+        assert_eq!(page.next_token, Some("page-2".to_string()));
+        assert!(page.is_truncated);
+        assert_eq!(page.page_size, 2);
+
+        let last_page = list_page(&["logs/c.txt"], None).with_request_checker(|request| {
+            assert_eq!(
+                request.params.get("continuation-token"),
+                Some(&Some("page-2".to_string()))
+            );
+        });
+        let s3 = S3Client::new_with(last_page, MockCredentialsProvider, Region::ApNortheast1);
+        let page = list_key_page(&s3, "bucket", Some("logs/"), 2, page.next_token)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(page.items, vec!["logs/c.txt"]);
+        assert_eq!(page.next_token, None);
+        assert!(!page.is_truncated);
+    }
+
+    #[tokio::test]
+    async fn can_cap_max_results() {
+        let s3 = S3Client::new_with(
+            list_page(&[], None).with_request_checker(|request| {
+                assert_eq!(
+                    request.params.get("max-keys"),
+                    Some(&Some("1000".to_string()))
+                );
+            }),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let page = list_key_page(&s3, "bucket", None, 50_000, None)
+            .await
+            .expect("expected Ok(_) value");
+        assert!(page.items.is_empty());
+        assert_eq!(page.page_size, 1000);
+    }
+
+    #[test]
+    fn can_escape_csv_field() {
+        assert_eq!(escape_csv_field("plain.txt"), "plain.txt");
+        assert_eq!(escape_csv_field("a,b.txt"), "\"a,b.txt\"");
+        assert_eq!(escape_csv_field("\"etag\""), "\"\"\"etag\"\"\"");
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+
+use crate::s3_object::S3Object;
+use crate::uploader::{UploadParams, Uploader};
+use crate::LambdaError;
+
+/// Where the list of used keys is kept, in the same bucket as the objects.
+pub const INDEX_KEY: &str = "_index/keys.json";
+
+/// Every key that has ever been written, so a key is never handed out twice.
+///
+/// This is a soft guarantee: the index is read before the put and written
+/// after it with no locking, so two concurrent writes of the same new key can
+/// both be accepted, and a concurrent write of another key can drop an entry.
+/// Run the function with reserved concurrency 1 where this matters.
+#[derive(Debug, Default)]
+pub struct KeyIndex {
+    keys: BTreeSet<String>,
+}
+
+impl KeyIndex {
+    /// A missing index object is an empty index.
+    pub async fn load(uploader: &dyn Uploader, bucket_name: &str) -> Result<KeyIndex> {
+        let keys = match uploader
+            .download(&S3Object::new(bucket_name, INDEX_KEY))
+            .await?
+        {
+            Some(body) => serde_json::from_slice(&body)?,
+            None => BTreeSet::new(),
+        };
+        Ok(KeyIndex { keys })
+    }
+
+    /// The index object itself counts as used so it can't be overwritten by a put.
+    pub fn check(&self, key: &str) -> std::result::Result<(), LambdaError> {
+        if key == INDEX_KEY || self.keys.contains(key) {
+            return Err(LambdaError::KeyReused);
+        }
+        Ok(())
+    }
+
+    /// Adds the object's key and writes the index back to its bucket.
+    pub async fn record(&mut self, uploader: &dyn Uploader, object: &S3Object) -> Result<()> {
+        self.keys.insert(object.key.clone());
+        uploader
+            .upload(UploadParams {
+                bucket: object.bucket.clone(),
+                key: INDEX_KEY.to_string(),
+                body: serde_json::to_vec(&self.keys)?,
+                cache_control: Some("no-cache".to_string()),
+                content_type: Some("application/json".to_string()),
+                content_language: None,
+                content_encoding: None,
+                expires: None,
+                website_redirect_location: None,
+                metadata: None,
+                tagging: None,
+                server_side_encryption: None,
+                ssekms_key_id: None,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uploader::testing::MemoryUploader;
+
+    #[tokio::test]
+    async fn can_accept_and_record_new_key() {
+        let uploader = MemoryUploader::default();
+        let mut index = KeyIndex::load(&uploader, "bucket").await.unwrap();
+        assert_eq!(index.check("a.txt"), Ok(()));
+        index
+            .record(&uploader, &S3Object::new("bucket", "a.txt"))
+            .await
+            .unwrap();
+        assert_eq!(
+            uploader.get("bucket", INDEX_KEY),
+            Some(br#"["a.txt"]"#.to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_reject_reused_key() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("bucket", INDEX_KEY, br#"["a.txt","b.txt"]"#);
+        let index = KeyIndex::load(&uploader, "bucket").await.unwrap();
+        assert_eq!(index.check("b.txt"), Err(LambdaError::KeyReused));
+        assert_eq!(index.check(INDEX_KEY), Err(LambdaError::KeyReused));
+        assert_eq!(index.check("c.txt"), Ok(()));
+    }
+}
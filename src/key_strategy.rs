@@ -0,0 +1,200 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use lambda::Context;
+
+use crate::DEFAULT_OBJECT_KEY;
+
+const STRATEGY_STATIC_PREFIX: &str = "static:";
+const STRATEGY_REQUEST_ID: &str = "request_id";
+const STRATEGY_CONTENT_HASH: &str = "content_hash";
+const STRATEGY_TIMESTAMP: &str = "timestamp";
+const STRATEGY_UUID_V4: &str = "uuid_v4";
+const KEY_EXTENSION: &str = ".txt";
+// hex digits of the MD5 kept by `add_content_hash_suffix`
+const CONTENT_HASH_SUFFIX_LEN: usize = 8;
+
+/// How a put without a `key` names its object, from `OBJECT_KEY_FORMAT`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyStrategy {
+    Static(String),
+    RequestId,
+    /// MD5 of the body, so identical uploads land on the same key.
+    ContentHash,
+    /// Milliseconds since the epoch.
+    Timestamp,
+    UuidV4,
+}
+
+impl Default for KeyStrategy {
+    fn default() -> KeyStrategy {
+        KeyStrategy::Static(DEFAULT_OBJECT_KEY.to_string())
+    }
+}
+
+impl KeyStrategy {
+    /// An unset or empty `OBJECT_KEY_FORMAT` keeps writing to `test.txt`;
+    /// `static:<key>` picks another fixed key.
+    pub fn parse(value: Option<&str>) -> Result<KeyStrategy> {
+        match value {
+            None | Some("") => Ok(KeyStrategy::default()),
+            Some(STRATEGY_REQUEST_ID) => Ok(KeyStrategy::RequestId),
+            Some(STRATEGY_CONTENT_HASH) => Ok(KeyStrategy::ContentHash),
+            Some(STRATEGY_TIMESTAMP) => Ok(KeyStrategy::Timestamp),
+            Some(STRATEGY_UUID_V4) => Ok(KeyStrategy::UuidV4),
+            Some(value) => match value.strip_prefix(STRATEGY_STATIC_PREFIX) {
+                Some(key) if !key.is_empty() => Ok(KeyStrategy::Static(key.to_string())),
+                _ => Err(anyhow!("Unknown OBJECT_KEY_FORMAT {}", value)),
+            },
+        }
+    }
+}
+
+/// Names an object for `strategy`. The clock and the random `seed` are passed in
+/// so the result only depends on the arguments; see `random_seed`.
+pub fn generate_key(
+    strategy: &KeyStrategy,
+    c: &Context,
+    body: &[u8],
+    now: SystemTime,
+    seed: u64,
+) -> String {
+    match strategy {
+        KeyStrategy::Static(key) => key.clone(),
+        KeyStrategy::RequestId => format!("{}{}", c.request_id, KEY_EXTENSION),
+        KeyStrategy::ContentHash => format!("{:x}{}", md5::compute(body), KEY_EXTENSION),
+        KeyStrategy::Timestamp => {
+            let millis = now
+                .duration_since(UNIX_EPOCH)
+                .map(|since| since.as_millis())
+                .unwrap_or(0);
+            format!("{}{}", millis, KEY_EXTENSION)
+        }
+        KeyStrategy::UuidV4 => format!("{}{}", format_uuid_v4(seed), KEY_EXTENSION),
+    }
+}
+
+/// Puts the start of the body's MD5 before the extension, `logo.svg` becomes
+/// `logo.<hash>.svg`, so changed content always gets a new URL.
+pub fn add_content_hash_suffix(key: &str, body: &[u8]) -> String {
+    let hash = format!("{:x}", md5::compute(body));
+    let hash = &hash[..CONTENT_HASH_SUFFIX_LEN];
+    let name_start = key.rfind('/').map(|slash| slash + 1).unwrap_or(0);
+    // a leading dot starts a hidden name, not an extension
+    match key[name_start..].rfind('.').filter(|&dot| dot > 0) {
+        Some(dot) => {
+            let (stem, extension) = key.split_at(name_start + dot);
+            format!("{}.{}{}", stem, hash, extension)
+        }
+        None => format!("{}.{}", key, hash),
+    }
+}
+
+/// `RandomState` is randomly keyed per process and per instance, which is
+/// random enough for object names without pulling in a `rand` dependency.
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+pub fn format_uuid_v4(seed: u64) -> String {
+    let mut state = seed;
+    let high = split_mix64(&mut state);
+    let low = split_mix64(&mut state);
+    // version 4 in the 13th hex digit, the RFC 4122 variant in the 17th
+    let high = (high & !0xf000) | 0x4000;
+    let low = (low & !(0xc << 60)) | (0x8 << 60);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+// SplitMix64, spreads a single seed over the 128 bits a UUID needs
+fn split_mix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn generate(strategy: &KeyStrategy, seed: u64) -> String {
+        let c = Context {
+            request_id: "52fdfc07-2182-154f-163f-5f0f9a621d72".to_string(),
+            ..Default::default()
+        };
+        let now = UNIX_EPOCH + Duration::from_millis(1_605_484_800_123);
+        generate_key(strategy, &c, b"Firstname", now, seed)
+    }
+
+    #[test]
+    fn can_add_content_hash_suffix() {
+        assert_eq!(
+            add_content_hash_suffix("assets/logo.svg", b"Firstname"),
+            "assets/logo.04176f09.svg"
+        );
+        assert_eq!(
+            add_content_hash_suffix("v1.2/README", b"Firstname"),
+            "v1.2/README.04176f09"
+        );
+        assert_eq!(
+            add_content_hash_suffix(".env", b"Firstname"),
+            ".env.04176f09"
+        );
+    }
+
+    #[test]
+    fn can_parse_key_strategy() {
+        assert_eq!(KeyStrategy::parse(None).unwrap(), KeyStrategy::default());
+        assert_eq!(
+            KeyStrategy::parse(Some("static:notes.txt")).unwrap(),
+            KeyStrategy::Static("notes.txt".to_string())
+        );
+        assert_eq!(
+            KeyStrategy::parse(Some("uuid_v4")).unwrap(),
+            KeyStrategy::UuidV4
+        );
+        assert!(KeyStrategy::parse(Some("static:")).is_err());
+        assert!(KeyStrategy::parse(Some("random")).is_err());
+    }
+
+    #[test]
+    fn can_generate_key_for_each_strategy() {
+        assert_eq!(generate(&KeyStrategy::default(), 0), "test.txt");
+        assert_eq!(
+            generate(&KeyStrategy::RequestId, 0),
+            "52fdfc07-2182-154f-163f-5f0f9a621d72.txt"
+        );
+        assert_eq!(
+            generate(&KeyStrategy::ContentHash, 0),
+            "04176f095283bc729f1e3926967e7034.txt"
+        );
+        assert_eq!(generate(&KeyStrategy::Timestamp, 0), "1605484800123.txt");
+    }
+
+    #[test]
+    fn can_generate_uuid_v4_key() {
+        let key = generate(&KeyStrategy::UuidV4, 42);
+        assert_eq!(key, generate(&KeyStrategy::UuidV4, 42));
+        assert_ne!(key, generate(&KeyStrategy::UuidV4, 43));
+        let uuid = key.strip_suffix(".txt").unwrap();
+        let groups: Vec<&str> = uuid.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('4'));
+        assert!(["8", "9", "a", "b"].contains(&&groups[3][..1]));
+    }
+}
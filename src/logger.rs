@@ -0,0 +1,133 @@
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use once_cell::sync::OnceCell;
+use simple_logger::SimpleLogger;
+
+/// Registers with `log` right away but builds the `SimpleLogger` on the first
+/// record that passes the level filter, keeping that work off the cold start.
+pub struct LazyLogger {
+    level: LevelFilter,
+    inner: OnceCell<SimpleLogger>,
+}
+
+impl LazyLogger {
+    pub fn new(level: LevelFilter) -> LazyLogger {
+        LazyLogger {
+            level,
+            inner: OnceCell::new(),
+        }
+    }
+
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    fn get_inner(&self) -> &SimpleLogger {
+        self.inner
+            .get_or_init(|| SimpleLogger::new().with_level(self.level))
+    }
+}
+
+impl Log for LazyLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.get_inner().log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = self.inner.get() {
+            inner.flush();
+        }
+    }
+}
+
+/// Helpers that only tests need.
+#[cfg(test)]
+pub mod testing {
+    use std::sync::{Mutex, Once};
+
+    use log::{LevelFilter, Log, Metadata, Record};
+    use once_cell::sync::Lazy;
+
+    static CAPTURED: Lazy<Mutex<Vec<String>>> = Lazy::new(|| Mutex::new(Vec::new()));
+    static INSTALL: Once = Once::new();
+    static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+    /// Keeps every record as `LEVEL message` so tests can assert on log output.
+    struct CaptureLogger;
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Installs the capturing logger for the whole test binary; safe to call from every test.
+    pub fn capture_logs() {
+        INSTALL.call_once(|| {
+            log::set_logger(&CAPTURE_LOGGER).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+    }
+
+    /// Tests run in parallel, so filter on something unique to the test such as its request id.
+    pub fn get_captured_logs(pattern: &str) -> Vec<String> {
+        CAPTURED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains(pattern))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    #[test]
+    fn can_defer_logger_until_first_record() {
+        let logger = LazyLogger::new(LevelFilter::Info);
+        assert!(logger.inner.get().is_none());
+        logger.log(
+            &Record::builder()
+                .args(format_args!("filtered out"))
+                .level(Level::Debug)
+                .build(),
+        );
+        assert!(logger.inner.get().is_none());
+        logger.log(
+            &Record::builder()
+                .args(format_args!("first record"))
+                .level(Level::Info)
+                .build(),
+        );
+        assert!(logger.inner.get().is_some());
+    }
+
+    #[test]
+    fn can_filter_by_level_after_init() {
+        let logger = LazyLogger::new(LevelFilter::Info);
+        logger.get_inner();
+        assert!(logger.enabled(&Metadata::builder().level(Level::Warn).build()));
+        assert!(!logger.enabled(&Metadata::builder().level(Level::Debug).build()));
+    }
+}
@@ -1,189 +1,5554 @@
+mod api_context;
+mod apigw;
+mod audit;
+mod auth;
+mod circuit_breaker;
+mod client_cache;
+mod clock_skew;
+mod config;
+#[cfg(test)]
+mod contract_tests;
+mod diag;
+mod dlq;
+mod effective_config;
+mod encryption;
+#[cfg(feature = "sentry-integration")]
+mod error_tracking;
+mod event_store;
+mod expiry;
+mod fan_out;
+mod health;
+mod http_date;
+#[cfg(all(test, feature = "integration-tests"))]
+mod integration_tests;
+mod internal_signature;
+mod inventory;
+mod key_index;
+mod key_strategy;
+mod logger;
+#[cfg(all(test, feature = "miri"))]
+mod miri_tests;
+#[cfg(any(test, feature = "mock-s3"))]
+mod mock;
+mod move_object;
+mod multipart;
+mod precondition;
+mod preprocess;
+mod request_log;
+mod s3_object;
+mod shutdown;
+mod stat;
+#[cfg(test)]
+mod test_guard;
+mod text_body;
+mod text_stats;
+mod transform;
+mod uploader;
+mod user_agent;
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use lambda::{handler_fn, Context};
 use anyhow::{anyhow, Result};
+use api_context::{is_proxy_request, parse_proxy_request};
+use audit::build_cloudtrail_record;
+use auth::{authorize, AuthorizedContext};
+use circuit_breaker::CircuitBreaker;
+use client_cache::CLIENT_CACHE;
+use clock_skew::{check_clock_skew, fetch_server_time};
+use config::Config;
+use diag::{build_cold_start_record, write_metric_line, IS_COLD_START, TOTAL_BYTES_WRITTEN};
+use dlq::{parse_dlq_message, DlqMessage, SqsBatchItemFailure, SqsBatchResponse, SqsEvent};
+use effective_config::EffectiveConfig;
+use encryption::resolve_encryption;
+use event_store::{EventStore, DEFAULT_EVENT_LOG_PATH};
+use expiry::{check_expires_in, compute_expiry_header};
+use fan_out::fan_out_batch;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use health::{health_report, HealthReport};
+use inventory::{build_inventory_csv, list_key_page, PagedOutput, MAX_LIST_RESULTS};
+use key_index::KeyIndex;
+use key_strategy::{
+    add_content_hash_suffix, format_uuid_v4, generate_key, random_seed, KeyStrategy,
+};
+use lambda::{handler_fn, Context};
+use log::{debug, error, info, warn, LevelFilter};
+use logger::LazyLogger;
+use move_object::{move_object, MoveOutcome};
+use precondition::check_precondition;
+use preprocess::LengthExceeded;
+use request_log::RequestLoggingDispatcher;
+use rusoto_core::credential::DefaultCredentialsProvider;
+use rusoto_core::{HttpClient, Region, RusotoError};
+use rusoto_lambda::LambdaClient;
+use rusoto_s3::{S3Client, S3};
+use s3_object::{encode_key, S3Object};
+use serde::ser::{SerializeStruct, Serializer};
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
 use simple_logger::SimpleLogger;
-use log::{LevelFilter, error};
-use rusoto_s3::{
-    S3,
-    S3Client,
-    PutObjectRequest,
-};
-use rusoto_core::Region;
-use rusoto_mock::{
-    MockCredentialsProvider,
-    MockRequestDispatcher,
-    MockResponseReader,
-    ReadMockResponse,
-};
+use stat::{stat_object, ObjectStat};
+use text_body::TextBody;
+use text_stats::text_stats;
+use tokio::time::{delay_for, timeout};
+use transform::{apply_transform, Transform};
+use uploader::{S3Uploader, UploadParams, Uploader};
+use user_agent::UserAgentDispatcher;
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 struct CustomEvent {
+    op: Option<String>,
+    key: Option<String>,
     text_body: Option<String>,
+    cache_control: Option<String>,
+    content_type: Option<String>,
+    website_redirect: Option<String>,
+    // include a per-phase `timings` breakdown in the response
+    timings: Option<bool>,
+    // ETag the object must still have, for PUT_OBJECT_MODE=fail_if_changed;
+    // checked before the upload, so best effort, see check_precondition
+    if_match: Option<String>,
+    prefix: Option<String>,
+    destination_key: Option<String>,
+    max_results: Option<i64>,
+    // overrides S3_MAX_RETRIES for this invocation, capped at MAX_REQUEST_RETRIES
+    max_retries: Option<u32>,
+    continuation_token: Option<String>,
+    items: Option<Vec<BatchItem>>,
+    // read past MAX_GET_BYTES anyway
+    allow_large: Option<bool>,
+    // get returns `data:<content-type>;base64,...` instead of the text
+    data_uri: Option<bool>,
+    // applied to the text returned by get, the stored object is left as is
+    read_transform: Option<String>,
+    // also fetch the object's tags in stat
+    include_tags: Option<bool>,
+    // health also sends HeadBucket, a readiness rather than a liveness probe
+    deep: Option<bool>,
+    content_language: Option<String>,
+    // checked against API_KEY with the api-key-auth feature
+    api_key: Option<String>,
+    // hex HMAC-SHA256 of the event, checked with the hmac-auth feature
+    signature: Option<String>,
+    // unix seconds the signature was made at, see SIGNATURE_MAX_AGE_SECS
+    signed_at: Option<u64>,
+    // set by fan_out_batch on child invocations, see internal_signature.rs
+    internal_signature: Option<String>,
+    internal_request_id: Option<String>,
+    // sets the Expires header, the object itself is kept
+    expires_in_seconds: Option<u64>,
+    // cache forever under a content-addressed key, overrides cacheControl
+    immutable: Option<bool>,
+    // AES256 or aws:kms; unset leaves the bucket's default encryption
+    encryption: Option<String>,
+    kms_key_id: Option<String>,
+    // write to `name-1.txt`, `name-2.txt`, ... instead of over an existing object;
+    // best effort, see find_free_key
+    suffix_on_collision: Option<bool>,
+    // attach `effectiveConfig` to a successful put
+    include_effective_config: Option<bool>,
+    // name of an uploaded file, used as the key when `key` is unset
+    filename: Option<String>,
+    // from the API Gateway request context, never from the event itself
+    #[serde(skip)]
+    source_ip: Option<String>,
+}
+
+/// One upload of a batch put; the batch's cache control and content type apply to every item.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[cfg_attr(test, derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BatchItem {
+    key: Option<String>,
+    text_body: Option<String>,
+}
+
+/// Per-item outcome of a batch put; `index` is the item's position in `items`.
+#[derive(Serialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+struct BatchOutput {
+    succeeded: Vec<BatchItemResult>,
+    failed: Vec<BatchItemError>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct BatchItemResult {
+    index: usize,
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
 }
 
+// `key` is the requested one, a failed put may never have generated its own
 #[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct BatchItemError {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
 struct CustomOutput {
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_uri: Option<String>,
+    // the key actually written, after normalization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+    // set when DEFAULT_BODY was written because the request had no body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_body_used: Option<bool>,
+    // set when the body was over AUTO_COMPRESS_THRESHOLD and stored gzipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compressed: Option<bool>,
+    // S3 can omit the ETag (e.g. some S3-compatible servers), so it is optional
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metrics: Option<InvocationMetrics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<BTreeMap<&'static str, f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keys: Option<Vec<String>>,
+    // always set alongside `keys`, so an empty listing reads as `0`, not missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_continuation_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_bytes_written: Option<u64>,
+    // bytes of the body as sent to S3, not characters of the text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    // the server-side encryption S3 reports for the written object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kms_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage_class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effective_config: Option<EffectiveConfig>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    batch: Option<BatchOutput>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    health: Option<HealthReport>,
+}
+
+impl CustomOutput {
+    fn builder() -> CustomOutputBuilder {
+        CustomOutputBuilder {
+            output: CustomOutput {
+                message: "Succeeded.".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Builds a `CustomOutput`; the message defaults to `Succeeded.` and every
+/// other field is left out unless set. Setters taking an `Option` accept
+/// values that may legitimately be missing, like an ETag.
+struct CustomOutputBuilder {
+    output: CustomOutput,
+}
+
+impl CustomOutputBuilder {
+    fn message(mut self, message: impl Into<String>) -> CustomOutputBuilder {
+        self.output.message = message.into();
+        self
+    }
+
+    fn text(mut self, text: impl Into<String>) -> CustomOutputBuilder {
+        self.output.text = Some(text.into());
+        self
+    }
+
+    fn data_uri(mut self, data_uri: impl Into<String>) -> CustomOutputBuilder {
+        self.output.data_uri = Some(data_uri.into());
+        self
+    }
+
+    fn key(mut self, key: impl Into<String>) -> CustomOutputBuilder {
+        self.output.key = Some(key.into());
+        self
+    }
+
+    fn source_key(mut self, source_key: impl Into<String>) -> CustomOutputBuilder {
+        self.output.source_key = Some(source_key.into());
+        self
+    }
+
+    fn warning(mut self, warning: Option<String>) -> CustomOutputBuilder {
+        self.output.warning = warning;
+        self
+    }
+
+    fn default_body_used(mut self, default_body_used: bool) -> CustomOutputBuilder {
+        self.output.default_body_used = Some(true).filter(|_| default_body_used);
+        self
+    }
+
+    fn compressed(mut self, compressed: bool) -> CustomOutputBuilder {
+        self.output.compressed = Some(true).filter(|_| compressed);
+        self
+    }
+
+    fn etag(mut self, etag: Option<String>) -> CustomOutputBuilder {
+        self.output.etag = etag;
+        self
+    }
+
+    fn metrics(mut self, metrics: InvocationMetrics) -> CustomOutputBuilder {
+        self.output.metrics = Some(metrics);
+        self
+    }
+
+    fn page(mut self, page: PagedOutput<String>) -> CustomOutputBuilder {
+        self.output.count = Some(page.items.len());
+        self.output.keys = Some(page.items);
+        self.output.next_continuation_token = page.next_token;
+        self.output.is_truncated = Some(page.is_truncated);
+        self.output.page_size = Some(page.page_size);
+        self
+    }
+
+    fn total_bytes_written(mut self, total_bytes_written: u64) -> CustomOutputBuilder {
+        self.output.total_bytes_written = Some(total_bytes_written);
+        self
+    }
+
+    fn url(mut self, url: impl Into<String>) -> CustomOutputBuilder {
+        self.output.url = Some(url.into());
+        self
+    }
+
+    fn encryption(
+        mut self,
+        encryption: Option<String>,
+        kms_key_id: Option<String>,
+    ) -> CustomOutputBuilder {
+        self.output.encryption = encryption;
+        self.output.kms_key_id = kms_key_id;
+        self
+    }
+
+    fn uploaded_bytes(mut self, uploaded_bytes: u64) -> CustomOutputBuilder {
+        self.output.uploaded_bytes = Some(uploaded_bytes);
+        self
+    }
+
+    fn effective_config(
+        mut self,
+        effective_config: Option<EffectiveConfig>,
+    ) -> CustomOutputBuilder {
+        self.output.effective_config = effective_config;
+        self
+    }
+
+    fn batch(mut self, batch: BatchOutput) -> CustomOutputBuilder {
+        self.output.batch = Some(batch);
+        self
+    }
+
+    fn health(mut self, health: HealthReport) -> CustomOutputBuilder {
+        self.output.health = Some(health);
+        self
+    }
+
+    fn stat(mut self, stat: ObjectStat) -> CustomOutputBuilder {
+        self.output.size = Some(stat.size);
+        self.output.content_type = stat.content_type;
+        self.output.etag = stat.etag;
+        self.output.last_modified = stat.last_modified;
+        self.output.storage_class = Some(stat.storage_class);
+        self.output.tags = stat.tags;
+        self
+    }
+
+    fn build(self) -> CustomOutput {
+        self.output
+    }
+}
+
+/// Microseconds spent in each phase of a put, for tracking down latency regressions.
+#[derive(Serialize, Debug, PartialEq, Default, Clone)]
+struct InvocationMetrics {
+    validation_us: u64,
+    client_build_us: u64,
+    s3_put_us: u64,
+    total_us: u64,
 }
 
 const MOCK_KEY: &str = "AWS_MOCK_FLAG";
 const BUCKET_NAME_KEY: &str = "BUCKET_NAME";
 const LOCAL_KEY: &str = "LOCAL_FLAG";
+const SAM_LOCAL_KEY: &str = "AWS_SAM_LOCAL";
+const LOCALSTACK_HOSTNAME_KEY: &str = "LOCALSTACK_HOSTNAME";
+const FUNCTION_NAME_KEY: &str = "AWS_LAMBDA_FUNCTION_NAME";
+const DLQ_BUCKET_KEY: &str = "DLQ_BUCKET";
+const DEAD_LETTER_PREFIX: &str = "_dlq/";
+const NAMESPACE_BY_FUNCTION_KEY: &str = "NAMESPACE_BY_FUNCTION";
+const FAN_OUT_BATCHES_KEY: &str = "FAN_OUT_BATCHES";
+const DEFAULT_KMS_KEY_ID_KEY: &str = "DEFAULT_KMS_KEY_ID";
+const REQUIRE_EXPLICIT_KMS_KEY_KEY: &str = "REQUIRE_EXPLICIT_KMS_KEY";
+const PREFER_ITEMS_KEY: &str = "PREFER_ITEMS";
+const WRITE_PREFIX_KEY: &str = "WRITE_PREFIX";
+const EVENT_PAYLOAD_PATH_KEY: &str = "EVENT_PAYLOAD_PATH";
+const MAX_KEY_DEPTH_KEY: &str = "MAX_KEY_DEPTH";
+const INTERNAL_SIGNING_KEY_KEY: &str = "INTERNAL_SIGNING_KEY";
+// how far `signedAt` may be from now, either way, for a signature to verify
+const SIGNATURE_MAX_AGE_SECS: u64 = 300;
+const SCHEMA_VERSION_KEY: &str = "SCHEMA_VERSION";
+const SENTRY_DSN_KEY: &str = "SENTRY_DSN";
+const SOURCE_IP_TAG: &str = "source-ip";
+// sent as `x-amz-meta-schema-version`
+const SCHEMA_VERSION_METADATA: &str = "schema-version";
+const DLQ_REPLAY_KEY: &str = "DLQ_REPLAY_FLAG";
+// set when running as a long-lived container instead of on Lambda
+const CONTAINER_MODE_KEY: &str = "CONTAINER_MODE";
+const SHUTDOWN_DEADLINE_MS_KEY: &str = "SHUTDOWN_DEADLINE_MS";
+// under the 30s ECS and Kubernetes give a container between SIGTERM and SIGKILL
+const DEFAULT_SHUTDOWN_DEADLINE_MS: u64 = 25_000;
+const LOCAL_REPLAY_ENABLED_KEY: &str = "LOCAL_REPLAY_ENABLED";
+const LAZY_LOGGER_INIT_KEY: &str = "LAZY_LOGGER_INIT";
+const ALLOW_EMPTY_UPLOAD_KEY: &str = "ALLOW_EMPTY_UPLOAD";
+const LOG_REJECTIONS_KEY: &str = "LOG_REJECTIONS";
+const REDACT_PII_KEY: &str = "REDACT_PII";
+const DEFAULT_BODY_KEY: &str = "DEFAULT_BODY";
+const INDEX_ENABLED_KEY: &str = "INDEX_ENABLED";
+const ANALYZE_TEXT_KEY: &str = "ANALYZE_TEXT";
+const S3_USER_AGENT_KEY: &str = "S3_USER_AGENT";
+const S3_REQUEST_LOGGING_KEY: &str = "S3_REQUEST_LOGGING";
+const CLOCK_SKEW_THRESHOLD_MS_KEY: &str = "CLOCK_SKEW_THRESHOLD_MS";
+const MAX_GET_BYTES_KEY: &str = "MAX_GET_BYTES";
+const INCLUDE_METRICS_IN_RESPONSE_KEY: &str = "INCLUDE_METRICS_IN_RESPONSE";
+const DEFAULT_CACHE_CONTROL_KEY: &str = "DEFAULT_CACHE_CONTROL";
+const S3_MAX_RETRIES_KEY: &str = "S3_MAX_RETRIES";
+const API_KEY_KEY: &str = "API_KEY";
+const HMAC_SECRET_KEY: &str = "HMAC_SECRET";
+const RETRIABLE_STATUS_CODES_KEY: &str = "RETRIABLE_STATUS_CODES";
+const DECOMPRESS_ON_READ_KEY: &str = "DECOMPRESS_ON_READ";
+const AUTO_COMPRESS_THRESHOLD_KEY: &str = "AUTO_COMPRESS_THRESHOLD";
+const DEFAULT_OBJECT_KEY: &str = "test.txt";
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+const OBJECT_KEY_FORMAT_KEY: &str = "OBJECT_KEY_FORMAT";
+const LOWERCASE_KEYS_KEY: &str = "LOWERCASE_KEYS";
+const KEY_PREFIX_KEY: &str = "KEY_PREFIX";
+const ALLOWED_CONTENT_TYPES_KEY: &str = "ALLOWED_CONTENT_TYPES";
+const DEFAULT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+const MAX_TEXT_BODY_LEN: usize = 100;
+const OP_PUT: &str = "put";
+const OP_GET: &str = "get";
+const OP_INVENTORY: &str = "inventory";
+const OP_LIST: &str = "list";
+const OP_MOVE: &str = "move";
+const OP_DIAG: &str = "diag";
+const OP_STAT: &str = "stat";
+const OP_HEALTH: &str = "health";
+const ALLOWED_OPS_KEY: &str = "ALLOWED_OPS";
+const DEFAULT_INVENTORY_KEY: &str = "inventory.csv";
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const MAX_REQUEST_RETRIES: u32 = 10;
+const MAX_COLLISION_SUFFIX: u32 = 10;
+const S3_CONNECTIVITY_TIMEOUT_MS: u64 = 3000;
+const PUT_OBJECT_MODE_KEY: &str = "PUT_OBJECT_MODE";
+const MAX_BATCH_ITEMS_KEY: &str = "MAX_BATCH_ITEMS";
+const DEFAULT_MAX_BATCH_ITEMS: usize = 25;
+const MAX_RAW_EVENT_BYTES_KEY: &str = "MAX_RAW_EVENT_BYTES";
+// the Lambda limit for asynchronous invocation payloads
+const DEFAULT_MAX_RAW_EVENT_BYTES: usize = 256 * 1024;
+const RETRY_BASE_DELAY_MS: u64 = 100;
+const LAMBDA_HANDLER_TIMEOUT_BUFFER_MS_KEY: &str = "LAMBDA_HANDLER_TIMEOUT_BUFFER_MS";
+// time kept back from the deadline so a timeout is reported before Lambda kills us
+const DEFAULT_TIMEOUT_BUFFER_MS: u64 = 500;
+const CIRCUIT_BREAKER_THRESHOLD_MS_KEY: &str = "CIRCUIT_BREAKER_THRESHOLD_MS";
 const MSG_EMPTY_TEXT_BODY: &str = "Empty text body.";
 const MSG_TEXT_BODY_TOO_LONG: &str = "Text body is too long (max: 100)";
+const MSG_INVALID_CACHE_CONTROL: &str = "Invalid cache control";
+const MSG_UNKNOWN_OPERATION: &str = "Unknown operation";
+const MSG_MALFORMED_EVENT: &str = "Malformed event";
+const MSG_UNKNOWN_FIELDS: &str = "Unknown fields in event";
+const MSG_UNSUPPORTED_CONTENT_TYPE: &str = "Unsupported content type";
+const MSG_BATCH_TOO_LARGE: &str = "Batch is too large";
+const MSG_OBJECT_EXISTS: &str = "Object already exists";
+const MSG_PRECONDITION_FAILED: &str = "Object does not match ifMatch";
+const MSG_OP_NOT_ALLOWED: &str = "Operation is not allowed";
+const MSG_UPSTREAM_TIMEOUT: &str = "Upstream call timed out";
+const MSG_MISSING_DESTINATION_KEY: &str = "destinationKey is required";
+const MSG_INSUFFICIENT_TIME: &str = "Not enough time left in the invocation";
+const MSG_KEY_REUSED: &str = "Key has already been used";
+const MSG_OBJECT_TOO_LARGE: &str = "Object is too large to read";
+const MSG_UNKNOWN_TRANSFORM: &str = "Unknown read transform";
+const MSG_INVALID_CONTENT_LANGUAGE: &str = "Content language must look like en or en-US";
+const MSG_UNAUTHORIZED: &str = "Request is not authorized";
+const MSG_EVENT_TOO_LARGE: &str = "Event is too large";
+const MSG_INVALID_EXPIRY: &str = "expiresInSeconds must be between 1 second and 7 years";
+const MSG_INVALID_ENCODING: &str = "Object is not valid UTF-8 text";
+const MSG_UNSUPPORTED_ENCRYPTION: &str = "Unsupported encryption, use AES256 or aws:kms";
+const MSG_MISSING_KMS_KEY: &str = "aws:kms encryption needs an explicit kmsKeyId";
+const MSG_AMBIGUOUS_BODY: &str = "Send either textBody or items, not both";
+const MSG_OUTSIDE_WRITE_PREFIX: &str = "Writes are only allowed under the write prefix";
+const MSG_KEY_TOO_DEEP: &str = "Key has too many path segments";
+const MSG_NO_FREE_KEY: &str = "Every suffixed key is already taken";
+const MSG_SHUTTING_DOWN: &str = "The container is shutting down";
+const MSG_INVALID_WEBSITE_REDIRECT: &str =
+    "Website redirect must be a path starting with / or an http(s) URL";
+const PROBLEM_JSON_KEY: &str = "PROBLEM_JSON";
+const JSON_ERRORS_KEY: &str = "JSON_ERRORS";
+const PROBLEM_TYPE_BASE: &str = "https://github.com/zeroclock/lambda-rust-sample/problems/";
+
+/// How a `LambdaError` is rendered once it leaves the function, see `render_error`.
+/// PROBLEM_JSON wins over JSON_ERRORS when both are set.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorFormat {
+    // `[status] detail`
+    Plain,
+    // `{"error", "message", "code"}`
+    Json,
+    // RFC 7807 problem details
+    ProblemJson,
+}
+
+impl ErrorFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ErrorFormat::Plain => "text/plain",
+            ErrorFormat::Json => "application/json",
+            ErrorFormat::ProblemJson => "application/problem+json",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum LambdaError {
+    EmptyTextBody,
+    TextBodyTooLong,
+    InvalidCacheControl,
+    UnknownOperation,
+    MalformedEvent(String),
+    UnknownFields(String),
+    UnsupportedContentType(String),
+    BatchTooLarge { limit: usize, count: usize },
+    ObjectExists,
+    PreconditionFailed,
+    OpNotAllowed(String),
+    UpstreamTimeout { service: &'static str },
+    MissingDestinationKey,
+    InvalidWebsiteRedirect,
+    InsufficientTime { remaining_ms: u64 },
+    KeyReused,
+    ObjectTooLarge { size: u64, limit: u64 },
+    UnknownTransform(String),
+    InvalidContentLanguage,
+    Unauthorized,
+    EventTooLarge { limit: usize },
+    InvalidExpiry,
+    InvalidEncoding(String),
+    UnsupportedEncryption(String),
+    MissingKmsKey,
+    AmbiguousBody,
+    OutsideWritePrefix(String),
+    KeyTooDeep { limit: usize, depth: usize },
+    NoFreeKey { attempts: u32 },
+    ShuttingDown,
+}
+
+impl LambdaError {
+    fn status(&self) -> u16 {
+        match self {
+            LambdaError::EmptyTextBody => 400,
+            LambdaError::TextBodyTooLong => 400,
+            LambdaError::InvalidCacheControl => 400,
+            LambdaError::UnknownOperation => 400,
+            LambdaError::MalformedEvent(_) => 400,
+            LambdaError::UnknownFields(_) => 400,
+            LambdaError::UnsupportedContentType(_) => 415,
+            LambdaError::BatchTooLarge { .. } => 400,
+            LambdaError::ObjectExists => 409,
+            LambdaError::PreconditionFailed => 412,
+            LambdaError::OpNotAllowed(_) => 403,
+            LambdaError::UpstreamTimeout { .. } => 504,
+            LambdaError::MissingDestinationKey => 400,
+            LambdaError::InvalidWebsiteRedirect => 400,
+            LambdaError::InsufficientTime { .. } => 503,
+            LambdaError::KeyReused => 409,
+            LambdaError::ObjectTooLarge { .. } => 413,
+            LambdaError::UnknownTransform(_) => 400,
+            LambdaError::InvalidContentLanguage => 400,
+            LambdaError::Unauthorized => 401,
+            LambdaError::EventTooLarge { .. } => 413,
+            LambdaError::InvalidExpiry => 400,
+            LambdaError::InvalidEncoding(_) => 415,
+            LambdaError::UnsupportedEncryption(_) => 400,
+            LambdaError::MissingKmsKey => 400,
+            LambdaError::AmbiguousBody => 400,
+            LambdaError::OutsideWritePrefix(_) => 403,
+            LambdaError::KeyTooDeep { .. } => 400,
+            LambdaError::NoFreeKey { .. } => 409,
+            LambdaError::ShuttingDown => 503,
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            LambdaError::EmptyTextBody => MSG_EMPTY_TEXT_BODY.to_string(),
+            LambdaError::TextBodyTooLong => MSG_TEXT_BODY_TOO_LONG.to_string(),
+            LambdaError::InvalidCacheControl => MSG_INVALID_CACHE_CONTROL.to_string(),
+            LambdaError::UnknownOperation => MSG_UNKNOWN_OPERATION.to_string(),
+            LambdaError::MalformedEvent(detail) => format!("{}: {}", MSG_MALFORMED_EVENT, detail),
+            LambdaError::UnknownFields(detail) => format!("{}: {}", MSG_UNKNOWN_FIELDS, detail),
+            LambdaError::UnsupportedContentType(content_type) => {
+                format!("{}: {}", MSG_UNSUPPORTED_CONTENT_TYPE, content_type)
+            }
+            LambdaError::BatchTooLarge { limit, count } => {
+                format!("{} (max: {}, got: {})", MSG_BATCH_TOO_LARGE, limit, count)
+            }
+            LambdaError::ObjectExists => MSG_OBJECT_EXISTS.to_string(),
+            LambdaError::PreconditionFailed => MSG_PRECONDITION_FAILED.to_string(),
+            LambdaError::OpNotAllowed(op) => format!("{}: {}", MSG_OP_NOT_ALLOWED, op),
+            LambdaError::UpstreamTimeout { service } => {
+                format!("{}: {}", MSG_UPSTREAM_TIMEOUT, service)
+            }
+            LambdaError::MissingDestinationKey => MSG_MISSING_DESTINATION_KEY.to_string(),
+            LambdaError::InvalidWebsiteRedirect => MSG_INVALID_WEBSITE_REDIRECT.to_string(),
+            LambdaError::InsufficientTime { remaining_ms } => {
+                format!("{} ({}ms left)", MSG_INSUFFICIENT_TIME, remaining_ms)
+            }
+            LambdaError::KeyReused => MSG_KEY_REUSED.to_string(),
+            LambdaError::ObjectTooLarge { size, limit } => {
+                format!("{} ({} bytes, max: {})", MSG_OBJECT_TOO_LARGE, size, limit)
+            }
+            LambdaError::UnknownTransform(name) => format!("{}: {}", MSG_UNKNOWN_TRANSFORM, name),
+            LambdaError::InvalidContentLanguage => MSG_INVALID_CONTENT_LANGUAGE.to_string(),
+            LambdaError::Unauthorized => MSG_UNAUTHORIZED.to_string(),
+            LambdaError::EventTooLarge { limit } => {
+                format!("{} (limit {} bytes)", MSG_EVENT_TOO_LARGE, limit)
+            }
+            LambdaError::InvalidExpiry => MSG_INVALID_EXPIRY.to_string(),
+            LambdaError::InvalidEncoding(detail) => format!("{}: {}", MSG_INVALID_ENCODING, detail),
+            LambdaError::UnsupportedEncryption(name) => {
+                format!("{}: {}", MSG_UNSUPPORTED_ENCRYPTION, name)
+            }
+            LambdaError::MissingKmsKey => MSG_MISSING_KMS_KEY.to_string(),
+            LambdaError::AmbiguousBody => MSG_AMBIGUOUS_BODY.to_string(),
+            LambdaError::OutsideWritePrefix(key) => {
+                format!("{}: {}", MSG_OUTSIDE_WRITE_PREFIX, key)
+            }
+            LambdaError::KeyTooDeep { limit, depth } => {
+                format!("{} (max: {}, got: {})", MSG_KEY_TOO_DEEP, limit, depth)
+            }
+            LambdaError::NoFreeKey { attempts } => {
+                format!("{} (attempts: {})", MSG_NO_FREE_KEY, attempts)
+            }
+            LambdaError::ShuttingDown => MSG_SHUTTING_DOWN.to_string(),
+        }
+    }
 
+    fn name(&self) -> &'static str {
+        match self {
+            LambdaError::EmptyTextBody => "EmptyTextBody",
+            LambdaError::TextBodyTooLong => "TextBodyTooLong",
+            LambdaError::InvalidCacheControl => "InvalidCacheControl",
+            LambdaError::UnknownOperation => "UnknownOperation",
+            LambdaError::MalformedEvent(_) => "MalformedEvent",
+            LambdaError::UnknownFields(_) => "UnknownFields",
+            LambdaError::UnsupportedContentType(_) => "UnsupportedContentType",
+            LambdaError::BatchTooLarge { .. } => "BatchTooLarge",
+            LambdaError::ObjectExists => "ObjectExists",
+            LambdaError::PreconditionFailed => "PreconditionFailed",
+            LambdaError::OpNotAllowed(_) => "OpNotAllowed",
+            LambdaError::UpstreamTimeout { .. } => "UpstreamTimeout",
+            LambdaError::MissingDestinationKey => "MissingDestinationKey",
+            LambdaError::InvalidWebsiteRedirect => "InvalidWebsiteRedirect",
+            LambdaError::InsufficientTime { .. } => "InsufficientTime",
+            LambdaError::KeyReused => "KeyReused",
+            LambdaError::ObjectTooLarge { .. } => "ObjectTooLarge",
+            LambdaError::UnknownTransform(_) => "UnknownTransform",
+            LambdaError::InvalidContentLanguage => "InvalidContentLanguage",
+            LambdaError::Unauthorized => "Unauthorized",
+            LambdaError::EventTooLarge { .. } => "EventTooLarge",
+            LambdaError::InvalidExpiry => "InvalidExpiry",
+            LambdaError::InvalidEncoding(_) => "InvalidEncoding",
+            LambdaError::UnsupportedEncryption(_) => "UnsupportedEncryption",
+            LambdaError::MissingKmsKey => "MissingKmsKey",
+            LambdaError::AmbiguousBody => "AmbiguousBody",
+            LambdaError::OutsideWritePrefix(_) => "OutsideWritePrefix",
+            LambdaError::KeyTooDeep { .. } => "KeyTooDeep",
+            LambdaError::NoFreeKey { .. } => "NoFreeKey",
+            LambdaError::ShuttingDown => "ShuttingDown",
+        }
+    }
+
+    // slug of the stable `type` URI used in problem+json responses
+    fn problem_slug(&self) -> &'static str {
+        match self {
+            LambdaError::EmptyTextBody => "empty-text-body",
+            LambdaError::TextBodyTooLong => "text-body-too-long",
+            LambdaError::InvalidCacheControl => "invalid-cache-control",
+            LambdaError::UnknownOperation => "unknown-operation",
+            LambdaError::MalformedEvent(_) => "malformed-event",
+            LambdaError::UnknownFields(_) => "unknown-fields",
+            LambdaError::UnsupportedContentType(_) => "unsupported-content-type",
+            LambdaError::BatchTooLarge { .. } => "batch-too-large",
+            LambdaError::ObjectExists => "object-exists",
+            LambdaError::PreconditionFailed => "precondition-failed",
+            LambdaError::OpNotAllowed(_) => "op-not-allowed",
+            LambdaError::UpstreamTimeout { .. } => "upstream-timeout",
+            LambdaError::MissingDestinationKey => "missing-destination-key",
+            LambdaError::InvalidWebsiteRedirect => "invalid-website-redirect",
+            LambdaError::InsufficientTime { .. } => "insufficient-time",
+            LambdaError::KeyReused => "key-reused",
+            LambdaError::ObjectTooLarge { .. } => "object-too-large",
+            LambdaError::UnknownTransform(_) => "unknown-transform",
+            LambdaError::InvalidContentLanguage => "invalid-content-language",
+            LambdaError::Unauthorized => "unauthorized",
+            LambdaError::EventTooLarge { .. } => "event-too-large",
+            LambdaError::InvalidExpiry => "invalid-expiry",
+            LambdaError::InvalidEncoding(_) => "invalid-encoding",
+            LambdaError::UnsupportedEncryption(_) => "unsupported-encryption",
+            LambdaError::MissingKmsKey => "missing-kms-key",
+            LambdaError::AmbiguousBody => "ambiguous-body",
+            LambdaError::OutsideWritePrefix(_) => "outside-write-prefix",
+            LambdaError::KeyTooDeep { .. } => "key-too-deep",
+            LambdaError::NoFreeKey { .. } => "no-free-key",
+            LambdaError::ShuttingDown => "shutting-down",
+        }
+    }
+
+    fn to_problem_details(&self, request_id: &str) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: format!("{}{}", PROBLEM_TYPE_BASE, self.problem_slug()),
+            title: get_status_title(self.status()).to_string(),
+            status: self.status(),
+            detail: self.detail(),
+            instance: request_id.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LambdaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", get_err_msg(self.status(), &self.detail()))
+    }
+}
+
+impl std::error::Error for LambdaError {}
+
+/// Serializes as `{"error": <variant>, "message": <detail>, "code": <status>}`.
+impl serde::Serialize for LambdaError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("LambdaError", 3)?;
+        state.serialize_field("error", self.name())?;
+        state.serialize_field("message", &self.detail())?;
+        state.serialize_field("code", &self.status())?;
+        state.end()
+    }
+}
+
+/// RFC 7807 problem details
+#[derive(Serialize, Debug, PartialEq)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    problem_type: String,
+    title: String,
+    status: u16,
+    detail: String,
+    instance: String,
+}
+
+// lambda::Error is a boxed std error, which anyhow::Error converts into with `?`;
+// the other way round needs a map_err (https://github.com/dtolnay/anyhow/issues/35)
 #[tokio::main]
-async fn main() -> Result<()> {
-    SimpleLogger::new().with_level(LevelFilter::Debug).init().unwrap();
-    lambda::run(handler_fn(hello))
-        .await
-        // https://github.com/dtolnay/anyhow/issues/35
-        .map_err(|err| anyhow!(err))?;
+async fn main() -> std::result::Result<(), lambda::Error> {
+    // built once and shared by every invocation of this container
+    let config = Arc::new(Config::from_env()?);
+    // request logging is at trace level, it would be filtered out otherwise
+    let level = if config.s3_request_logging {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Debug
+    };
+    if env_flag(LAZY_LOGGER_INIT_KEY) {
+        LazyLogger::new(level).init().unwrap();
+    } else {
+        SimpleLogger::new().with_level(level).init().unwrap();
+    }
+    #[cfg(feature = "sentry-integration")]
+    let _sentry = config.sentry_dsn.as_deref().map(error_tracking::init);
+    let s3 = get_s3_client(&config);
+    if !config.mock_s3 && !config.local {
+        check_s3_connectivity(&s3).await?;
+    }
+    if let Some(threshold_ms) = config.clock_skew_threshold_ms {
+        check_startup_clock_skew(threshold_ms, &config.region).await;
+    }
+    if env_flag(DLQ_REPLAY_KEY) {
+        // this function is subscribed to the DLQ of the hello function
+        lambda::run(handler_fn(move |event, c| {
+            let config = config.clone();
+            let s3 = s3.clone();
+            async move { replay(event, c, &config, &s3).await }
+        }))
+        .await?;
+        return Ok(());
+    }
+    if config.local_replay_enabled {
+        match EventStore::new(DEFAULT_EVENT_LOG_PATH).replay() {
+            Ok(events) => info!(
+                "{} events in local event log {}",
+                events.len(),
+                DEFAULT_EVENT_LOG_PATH
+            ),
+            Err(err) => error!("Failed to read local event log: {}", err),
+        }
+    }
+    let container_mode = env_flag(CONTAINER_MODE_KEY);
+    let shutdown_deadline = Duration::from_millis(config.shutdown_deadline_ms);
+    let handler = handler_fn(move |event, c| {
+        let config = config.clone();
+        let s3 = s3.clone();
+        let invocation = async move {
+            if shutdown::IN_FLIGHT.is_shutting_down() {
+                let err = anyhow!(LambdaError::ShuttingDown);
+                return Err(render_error(err, config.error_format, &c.request_id));
+            }
+            #[cfg(feature = "sentry-integration")]
+            error_tracking::start_session();
+            let result = handle_invocation(event, c, &config, &s3).await;
+            #[cfg(feature = "sentry-integration")]
+            error_tracking::flush();
+            result
+        };
+        shutdown::IN_FLIGHT.track(invocation)
+    });
+    if !container_mode {
+        lambda::run(handler).await?;
+        return Ok(());
+    }
+    if let Some(result) = shutdown::run_until_shutdown(
+        lambda::run(handler),
+        shutdown::terminate_signal(),
+        &shutdown::IN_FLIGHT,
+        shutdown_deadline,
+    )
+    .await
+    {
+        result?;
+    }
     Ok(())
 }
 
-async fn hello(event: CustomEvent, c: Context) -> Result<CustomOutput> {
-    if let None = event.text_body {
-        error!("Empty text body in request {}", c.request_id);
-        return Err(anyhow!(get_err_msg(400, MSG_EMPTY_TEXT_BODY)));
+/// Never fails as a whole: an error would make SQS retry every message of the
+/// batch, so only the messages that failed for a reason that can pass, such as
+/// a 5xx from S3, are listed in the response. Messages that can never replay,
+/// unparseable or rejected with a 4xx, are logged and dropped instead of being
+/// redelivered until the queue gives up on them.
+async fn replay(
+    event: SqsEvent,
+    c: Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<SqsBatchResponse> {
+    let mut response = SqsBatchResponse::default();
+    let mut dropped = 0;
+    for record in &event.records {
+        let message_id = record.message_id.as_deref().unwrap_or_default();
+        // a message that doesn't parse now never will
+        let message = match parse_dlq_message(record) {
+            Ok(message) => message,
+            Err(err) => {
+                error!(
+                    "Dropping message {} that can't be parsed in request {}: {}",
+                    message_id, c.request_id, err
+                );
+                dropped += 1;
+                continue;
+            }
+        };
+        match replay_message(message, &c, config, s3).await {
+            Ok(()) => {}
+            Err(err) if is_permanent_error(&err) => {
+                error!(
+                    "Dropping message {} that can't be replayed in request {}: {}",
+                    message_id, c.request_id, err
+                );
+                dropped += 1;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to replay message {} in request {}: {}",
+                    message_id, c.request_id, err
+                );
+                response
+                    .batch_item_failures
+                    .push(SqsBatchItemFailure::new(record));
+            }
+        }
     }
-    let text = event.text_body.unwrap();
-    if text.len() > 100 {
-        error!("text body is too long (max: 100) in request {}", c.request_id);
-        return Err(anyhow!(get_err_msg(400, MSG_TEXT_BODY_TOO_LONG)));
+    info!(
+        "Replayed {} of {} events, dropped {}.",
+        event.records.len() - response.batch_item_failures.len() - dropped,
+        event.records.len(),
+        dropped
+    );
+    Ok(response)
+}
+
+// a 4xx is about the event itself; errors that are not a LambdaError,
+// such as S3 and transport errors, are worth another try
+fn is_permanent_error(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<LambdaError>() {
+        Some(err) => (400..500).contains(&err.status()) && err.status() != 429,
+        None => false,
     }
-    let s3 = get_s3_client();
-    let bucket_name = env::var(BUCKET_NAME_KEY)?;
-    s3.put_object(PutObjectRequest {
-        bucket: bucket_name.to_string(),
-        key: "test.txt".to_string(),
-        body: Some(text.into_bytes().into()),
-        acl: Some("public-read".to_string()),
-        ..Default::default()
-    }).await?;
-    
-    Ok(CustomOutput {
-        message: format!("Succeeded.")
-    })
 }
 
-fn get_s3_client() -> S3Client {
-    let s3 = match env::var(MOCK_KEY) {
-        Ok(_) => {
-            // Unit Test
-            S3Client::new_with(
-                MockRequestDispatcher::default().with_body(
-                    &MockResponseReader::read_response("mock_data", "s3_test.json")
-                ),
-                MockCredentialsProvider,
-                Default::default(),
+async fn replay_message(
+    message: DlqMessage,
+    c: &Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<()> {
+    info!(
+        "Replaying event of request {} (failed at {} with {}: {}) in request {}",
+        message.request_id,
+        message.failed_at,
+        message.error_code,
+        message.error_message,
+        c.request_id
+    );
+    let max_retries = request_max_retries(message.original_event.max_retries, config);
+    let uploader = get_uploader(s3, config, c).with_max_retries(max_retries);
+    let authorized = match authorize(&message.original_event, c.clone(), config) {
+        Ok(authorized) => authorized,
+        Err(err) => {
+            error!(
+                "Replayed event of request {} is not authorized in request {}",
+                message.request_id, c.request_id
+            );
+            return Err(anyhow!(err));
+        }
+    };
+    hello(message.original_event, authorized, config, s3, &uploader).await?;
+    Ok(())
+}
+
+/// A proxy integration takes the status, headers and body from the function's
+/// result, so for a proxy request both outcomes come back as a proxy response.
+async fn handle_invocation(
+    event: Value,
+    c: Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<Value> {
+    let request_id = c.request_id.clone();
+    if is_proxy_request(&event) {
+        let result = handle_event(event, c, config, s3).await;
+        return Ok(get_proxy_response(result, config.error_format, &request_id));
+    }
+    let output = handle_event(event, c, config, s3)
+        .await
+        .map_err(|err| render_error(err, config.error_format, &request_id))?;
+    Ok(serde_json::to_value(output)?)
+}
+
+fn get_proxy_response(
+    result: Result<CustomOutput>,
+    format: ErrorFormat,
+    request_id: &str,
+) -> Value {
+    // serializing the output never fails, like in render_error
+    let (status, content_type, body) = match result {
+        Ok(output) => (
+            200,
+            "application/json",
+            serde_json::to_string(&output).unwrap(),
+        ),
+        // errors that are not a LambdaError, such as S3 ones, are a plain 500
+        Err(err) => {
+            let (status, content_type) = match err.downcast_ref::<LambdaError>() {
+                Some(err) => (err.status(), format.content_type()),
+                None => (500, "text/plain"),
+            };
+            (
+                status,
+                content_type,
+                render_error(err, format, request_id).to_string(),
             )
-        },
-        Err(_) => {
-            if env::var(LOCAL_KEY).unwrap() != "" {
-                // local
-                return S3Client::new(Region::Custom {
-                    name: "ap-northeast-1".to_owned(),
-                    endpoint: "http://host.docker.internal:8000".to_owned(),
-                })
-            }
-            // cloud
-            return S3Client::new(Region::ApNortheast1)
-        },
+        }
     };
-    s3
+    serde_json::json!({
+        "statusCode": status,
+        "headers": { "Content-Type": content_type },
+        "body": body,
+        "isBase64Encoded": false,
+    })
 }
 
-fn get_err_msg(code: u16, msg: &str) -> String {
-    format!("[{}] {}", code, msg)
+/// Entry point: deserializes the raw event itself so malformed input is
+/// reported as a 400 naming the offending field.
+async fn handle_event(
+    event: Value,
+    c: Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<CustomOutput> {
+    log_cold_start(&mut io::stdout().lock(), IS_COLD_START.take(), &c);
+    if let Err(err) = check_raw_event_size(&event, config.max_raw_event_bytes) {
+        log_rejection(
+            "event_too_large",
+            serde_json::json!({ "limit": config.max_raw_event_bytes }),
+            &c,
+            config,
+        );
+        return Err(anyhow!(err));
+    }
+    let parsed = if is_proxy_request(&event) {
+        parse_proxy_request(event)
+    } else {
+        extract_payload(event, config.event_payload_path.as_deref())
+            .and_then(parse_event)
+            .map(|event| (event, None))
+    };
+    let (event, api_context) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let rule = match err {
+                LambdaError::UnknownFields(_) => "unknown_fields",
+                _ => "malformed_event",
+            };
+            log_rejection(rule, serde_json::json!({}), &c, config);
+            return Err(anyhow!(err));
+        }
+    };
+    if config.local_replay_enabled {
+        // the log is a debugging aid, it must not fail the invocation
+        if let Err(err) = EventStore::new(DEFAULT_EVENT_LOG_PATH).append(&event) {
+            error!("Failed to log event of request {}: {}", c.request_id, err);
+        }
+    }
+    let uploader = get_uploader(s3, config, &c)
+        .with_max_retries(request_max_retries(event.max_retries, config));
+    let authorized = match authorize(&event, c.clone(), config) {
+        Ok(authorized) => authorized.with_api_context(api_context),
+        Err(err) => {
+            log_rejection("unauthorized", serde_json::json!({}), &c, config);
+            return Err(anyhow!(err));
+        }
+    };
+    hello(event, authorized, config, s3, &uploader).await
 }
 
-fn hoge_function() -> String {
-    println!("hoge function executed.");
-    String::from("hogehoge!")
+/// Rejects an event whose JSON form is over `limit` bytes before serde maps it
+/// onto `CustomEvent`. Serialization stops as soon as the limit is passed.
+fn check_raw_event_size(event: &Value, limit: usize) -> std::result::Result<(), LambdaError> {
+    let mut writer = LimitWriter { written: 0, limit };
+    serde_json::to_writer(&mut writer, event).map_err(|_| LambdaError::EventTooLarge { limit })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// counts bytes instead of storing them, and fails once past `limit`
+struct LimitWriter {
+    written: usize,
+    limit: usize,
+}
 
-    fn setup() {
-        env::set_var(MOCK_KEY, "1");
-        env::set_var(BUCKET_NAME_KEY, "test-bucket");
+impl Write for LimitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len();
+        if self.written > self.limit {
+            return Err(io::Error::new(io::ErrorKind::Other, "limit exceeded"));
+        }
+        Ok(buf.len())
     }
 
-    #[test]
-    fn can_get_local_s3_client() {
-        env::set_var(LOCAL_KEY, "local");
-        let _s3 = get_s3_client();
-        assert!(true);
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
+}
 
-    #[test]
-    fn can_get_cloud_s3_client() {
-        env::set_var(LOCAL_KEY, "");
-        let _s3 = get_s3_client();
-        assert!(true);
+/// Takes the object at a dot path such as `detail`, for upstreams that wrap the
+/// event in an envelope the way EventBridge does. Without a path it's a no-op.
+fn extract_payload(event: Value, path: Option<&str>) -> std::result::Result<Value, LambdaError> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(event),
+    };
+    let mut payload = event;
+    for segment in path.split('.') {
+        payload = match payload {
+            Value::Object(mut fields) => fields.remove(segment),
+            _ => None,
+        }
+        .ok_or_else(|| LambdaError::MalformedEvent(format!("no payload at {}", path)))?;
     }
+    Ok(payload)
+}
 
-    #[tokio::test]
-    async fn can_hello_handler_handle_valid_request() {
-        setup();
-        let event = CustomEvent {
-            text_body: Some("Firstname".to_string())
-        };
-        let expected = CustomOutput {
-            message: "Succeeded.".to_string()
-        };
-        assert_eq!(
-            hello(event, Context::default())
-                .await
-                .expect("expected Ok(_) value"),
-            expected
-        )
+fn parse_event(event: Value) -> std::result::Result<CustomEvent, LambdaError> {
+    serde_path_to_error::deserialize(event).map_err(get_parse_error)
+}
+
+/// Parses JSON straight from `reader` instead of buffering the whole input first,
+/// for payloads close to the Lambda input limit.
+fn parse_custom_event_streaming(
+    reader: impl Read,
+) -> std::result::Result<CustomEvent, LambdaError> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let event = serde_path_to_error::deserialize(&mut deserializer).map_err(get_parse_error)?;
+    // reject trailing data, like serde_json::from_reader does
+    deserializer
+        .end()
+        .map_err(|err| LambdaError::MalformedEvent(err.to_string()))?;
+    Ok(event)
+}
+
+fn get_parse_error<E: fmt::Display>(err: serde_path_to_error::Error<E>) -> LambdaError {
+    let message = err.inner().to_string();
+    // serde has no error kind for deny_unknown_fields, so match its message
+    if message.starts_with("unknown field") {
+        LambdaError::UnknownFields(message)
+    } else {
+        LambdaError::MalformedEvent(format!("{}: {}", err.path(), message))
     }
+}
 
-    #[tokio::test]
-    async fn can_hello_handler_handle_empty_text_body() {
-        setup();
-        let event = CustomEvent {
-            text_body: None
-        };
-        let result = hello(event, Context::default()).await;
-        assert!(result.is_err());
-        if let Err(error) = result {
-            assert_eq!(
-                error.to_string(),
-                format!("[400] {}", MSG_EMPTY_TEXT_BODY)
-            )
-        } else {
-            // result must be Err
-            panic!()
+/// Runs the requested operation; with the `sentry-integration` feature a
+/// failed one is also reported to Sentry.
+async fn hello(
+    event: CustomEvent,
+    c: AuthorizedContext,
+    config: &Config,
+    s3: &S3Client,
+    uploader: &dyn Uploader,
+) -> Result<CustomOutput> {
+    let result = dispatch(event, c, config, s3, uploader).await;
+    #[cfg(feature = "sentry-integration")]
+    error_tracking::capture(&result);
+    result
+}
+
+async fn dispatch(
+    mut event: CustomEvent,
+    c: AuthorizedContext,
+    config: &Config,
+    s3: &S3Client,
+    uploader: &dyn Uploader,
+) -> Result<CustomOutput> {
+    let principal = c.principal().clone();
+    let api_context = c.api_context().cloned();
+    let c = c.into_inner();
+    debug!("Request {} authorized as {:?}", c.request_id, principal);
+    if let Some(api_context) = &api_context {
+        debug!(
+            "Request {} came through API Gateway request {}",
+            c.request_id, api_context.apigw_request_id
+        );
+        event.source_ip = Some(api_context.source_ip.clone());
+    }
+    let request_id = c.request_id.clone();
+    let include_timings = event.timings.unwrap_or(false);
+    let op = event.op.as_deref().unwrap_or(OP_PUT);
+    if !is_op_allowed(op, config.allowed_ops.clone()) {
+        error!(
+            "Operation {} is not allowed in request {}",
+            op, c.request_id
+        );
+        return Err(anyhow!(LambdaError::OpNotAllowed(op.to_string())));
+    }
+    // checked before any S3 call so a cut-off invocation leaves nothing half-written
+    if let Err(err) = CircuitBreaker::new(config.circuit_breaker_threshold_ms).check(&c) {
+        error!("{} in request {}", err, c.request_id);
+        return Err(anyhow!(err));
+    }
+    // a put with both bodies is ambiguous, items only win when the deployment says so
+    if event.text_body.is_some() && event.items.is_some() && !config.prefer_items {
+        log_rejection("ambiguous_body", serde_json::json!({}), &c, config);
+        return Err(anyhow!(LambdaError::AmbiguousBody));
+    }
+    // the handlers take the event by value, keep what the audit record needs
+    let audit_event = CustomEvent {
+        op: event.op.clone(),
+        key: event.key.clone(),
+        destination_key: event.destination_key.clone(),
+        prefix: event.prefix.clone(),
+        ..Default::default()
+    };
+    let audit_context = c.clone();
+    let mut output = match event.op.as_deref() {
+        None | Some(OP_PUT) if event.items.is_some() => {
+            put_batch(event, c, config, uploader).await?
         }
+        None | Some(OP_PUT) => put_text(event, c, config, uploader).await?,
+        Some(OP_GET) => get_text(event, c, config, uploader).await?,
+        Some(OP_INVENTORY) => write_inventory(event, c, config, uploader).await?,
+        Some(OP_LIST) => list_keys(event, c, config, s3).await?,
+        Some(OP_MOVE) => move_text(event, c, config, s3).await?,
+        Some(OP_DIAG) => get_diag(),
+        Some(OP_HEALTH) => get_health(event, config, s3).await,
+        Some(OP_STAT) => stat_text(event, c, config, s3).await?,
+        Some(op) => {
+            error!("Unknown operation {} in request {}", op, c.request_id);
+            return Err(anyhow!(LambdaError::UnknownOperation));
+        }
+    };
+    if let Some(metrics) = &output.metrics {
+        info!(
+            "Invocation metrics for request {}: {}",
+            request_id,
+            serde_json::to_string(metrics)?
+        );
+    }
+    info!(
+        "{}",
+        serde_json::json!({
+            "cloudtrail_record": build_cloudtrail_record(
+                &audit_event,
+                &output,
+                &audit_context,
+                api_context.as_ref(),
+                config
+            )
+        })
+    );
+    if include_timings {
+        output.timings = output.metrics.as_ref().map(get_timings);
     }
+    if !config.include_metrics_in_response {
+        output.metrics = None;
+    }
+    Ok(output)
+}
 
-    #[tokio::test]
-    async fn can_hello_handler_handle_text_body_too_long() {
-        setup();
-        let event = CustomEvent {
-            text_body: Some("12345678901234567890123456789012345678901234567890123456789012345678901234567890123456789012345678901".to_owned())
+/// An unset or empty allow-list allows every operation.
+fn is_op_allowed(op: &str, allowed: Option<String>) -> bool {
+    match allowed {
+        Some(allowed) if !allowed.is_empty() => allowed.split(',').any(|name| name.trim() == op),
+        _ => true,
+    }
+}
+
+async fn put_text(
+    event: CustomEvent,
+    c: Context,
+    config: &Config,
+    uploader: &dyn Uploader,
+) -> Result<CustomOutput> {
+    let started_at = Instant::now();
+    // the handler takes the event apart, keep a copy in case it ends up in the DLQ
+    let dead_letter_event = match &config.dlq_bucket {
+        Some(_) => Some(serde_json::to_value(&event)?),
+        None => None,
+    };
+    let (text_body, default_body_used) =
+        apply_default_body(event.text_body, config.default_body.as_deref());
+    let text = match resolve_text_body(text_body, config.allow_empty_upload) {
+        Some(text) => text,
+        None => {
+            log_rejection("empty_text_body", serde_json::json!({}), &c, config);
+            return Err(anyhow!(LambdaError::EmptyTextBody));
+        }
+    };
+    let text = match config.build_pipeline().run(text) {
+        Ok(text) => text,
+        Err(err) => match err.downcast::<LengthExceeded>() {
+            Ok(exceeded) => {
+                log_rejection(
+                    "text_too_long",
+                    serde_json::json!({ "actual_len": exceeded.actual_len, "limit": exceeded.limit }),
+                    &c,
+                    config,
+                );
+                return Err(anyhow!(LambdaError::TextBodyTooLong));
+            }
+            Err(err) => return Err(err),
+        },
+    };
+    let immutable = event.immutable.unwrap_or(false);
+    let requested_cache_control = if immutable {
+        Some(IMMUTABLE_CACHE_CONTROL.to_string())
+    } else {
+        event.cache_control
+    };
+    let cache_control = match resolve_cache_control(
+        requested_cache_control,
+        config.default_cache_control.clone(),
+    ) {
+        Ok(cache_control) => cache_control,
+        Err(err) => {
+            log_rejection("invalid_cache_control", serde_json::json!({}), &c, config);
+            return Err(anyhow!(err));
+        }
+    };
+    let content_type =
+        match resolve_content_type(event.content_type, config.allowed_content_types.clone()) {
+            Ok(content_type) => content_type,
+            Err(err) => {
+                log_rejection(
+                    "unsupported_content_type",
+                    serde_json::json!({}),
+                    &c,
+                    config,
+                );
+                return Err(anyhow!(err));
+            }
         };
-        let result = hello(event, Context::default()).await;
-        assert!(result.is_err());
-        if let Err(error) = result {
-            assert_eq!(
-                error.to_string(),
-                format!("[400] {}", MSG_TEXT_BODY_TOO_LONG)
-            )
-        } else {
-            // result must be Err
-            panic!()
+    let website_redirect = event
+        .website_redirect
+        .filter(|location| !location.is_empty());
+    if let Some(location) = &website_redirect {
+        if !is_valid_website_redirect(location) {
+            log_rejection(
+                "invalid_website_redirect",
+                serde_json::json!({ "actual_len": location.len() }),
+                &c,
+                config,
+            );
+            return Err(anyhow!(LambdaError::InvalidWebsiteRedirect));
+        }
+    }
+    let content_language = event
+        .content_language
+        .filter(|language| !language.is_empty());
+    if let Some(language) = &content_language {
+        if !is_valid_content_language(language) {
+            log_rejection(
+                "invalid_content_language",
+                serde_json::json!({ "actual_len": language.len() }),
+                &c,
+                config,
+            );
+            return Err(anyhow!(LambdaError::InvalidContentLanguage));
+        }
+    }
+    let encryption = match resolve_encryption(event.encryption.as_deref(), event.kms_key_id, config)
+    {
+        Ok(encryption) => encryption,
+        Err(err) => {
+            log_rejection("invalid_encryption", serde_json::json!({}), &c, config);
+            return Err(anyhow!(err));
+        }
+    };
+    if let Some(seconds) = event.expires_in_seconds {
+        if let Err(err) = check_expires_in(seconds) {
+            log_rejection(
+                "invalid_expiry",
+                serde_json::json!({ "expires_in_seconds": seconds }),
+                &c,
+                config,
+            );
+            return Err(anyhow!(err));
         }
     }
+    let validation_us = elapsed_us(started_at);
+    let bucket_name = config.bucket_name.clone();
+    let key = event.key.or(event.filename).unwrap_or_else(|| {
+        generate_key(
+            &config.key_strategy,
+            &c,
+            text.as_bytes(),
+            clock_skew::now(),
+            random_seed(),
+        )
+    });
+    let key = if immutable {
+        add_content_hash_suffix(&key, text.as_bytes())
+    } else {
+        key
+    };
+    let key = resolve_object_key(Some(key), config);
+    if let Err(err) = check_write_prefix(&key, config) {
+        log_rejection("outside_write_prefix", serde_json::json!({}), &c, config);
+        return Err(anyhow!(err));
+    }
+    if let Err(err) = check_key_depth(&key, config.max_key_depth) {
+        log_rejection("key_too_deep", serde_json::json!({}), &c, config);
+        return Err(anyhow!(err));
+    }
+    let key = if event.suffix_on_collision.unwrap_or(false) {
+        match find_free_key(uploader, &bucket_name, &key).await? {
+            Ok(key) => key,
+            Err(err) => {
+                error!("{} for {} in request {}", err, key, c.request_id);
+                return Err(anyhow!(err));
+            }
+        }
+    } else {
+        key
+    };
+    let object = S3Object::new(bucket_name.as_str(), key.as_str());
+    if let Err(err) = check_precondition(
+        uploader,
+        &object,
+        config.put_object_mode,
+        event.if_match.as_deref(),
+    )
+    .await?
+    {
+        error!(
+            "Precondition failed for {} in request {}",
+            key, c.request_id
+        );
+        return Err(anyhow!(err));
+    }
+    let mut index = if config.index_enabled {
+        let index = KeyIndex::load(uploader, &bucket_name).await?;
+        if let Err(err) = index.check(&key) {
+            error!("Key {} reused in request {}", key, c.request_id);
+            return Err(anyhow!(err));
+        }
+        Some(index)
+    } else {
+        None
+    };
+    let mut metadata = get_schema_metadata(config);
+    if config.analyze_text {
+        metadata.extend(text_stats(&text).into_metadata());
+    }
+    let metadata = Some(metadata).filter(|metadata| !metadata.is_empty());
+    let (body, compressed) = compress_body(text.into_bytes(), config.auto_compress_threshold)?;
+    let body_len = body.len() as u64;
+    let params = UploadParams {
+        bucket: bucket_name.clone(),
+        key: key.clone(),
+        body,
+        cache_control,
+        content_type: Some(content_type),
+        content_language,
+        content_encoding: Some("gzip".to_string()).filter(|_| compressed),
+        expires: event.expires_in_seconds.map(compute_expiry_header),
+        website_redirect_location: website_redirect,
+        metadata,
+        tagging: event
+            .source_ip
+            .map(|source_ip| format!("{}={}", SOURCE_IP_TAG, encode_key(&source_ip))),
+        server_side_encryption: encryption.as_ref().map(|e| e.algorithm.clone()),
+        ssekms_key_id: encryption.and_then(|e| e.kms_key_id),
+    };
+    let effective_config = if event.include_effective_config.unwrap_or(false) {
+        Some(EffectiveConfig::new(&params, config))
+    } else {
+        None
+    };
+    let put_started_at = Instant::now();
+    let result = uploader.upload(params).await;
+    let receipt = match result {
+        Ok(receipt) => receipt,
+        Err(err) => match err.downcast::<LambdaError>() {
+            Ok(err) => {
+                error!("{} for {} in request {}", err, key, c.request_id);
+                return Err(anyhow!(err));
+            }
+            Err(err) => {
+                if let (Some(dlq_bucket), Some(event)) = (&config.dlq_bucket, dead_letter_event) {
+                    write_dead_letter(uploader, dlq_bucket, event, &err, &c).await;
+                }
+                return Err(err);
+            }
+        },
+    };
+    let s3_put_us = elapsed_us(put_started_at);
+    TOTAL_BYTES_WRITTEN.add(body_len);
+    // the log-search bin finds invocations by this exact `s3_key="..."` form
+    info!(
+        "Wrote s3_key=\"{}\" s3_bucket=\"{}\" in request {}",
+        key, bucket_name, c.request_id
+    );
+    if let Some(index) = &mut index {
+        index.record(uploader, &object).await?;
+    }
+
+    Ok(CustomOutput::builder()
+        .url(object.url(&config.region))
+        .key(key)
+        .etag(receipt.etag)
+        .encryption(receipt.server_side_encryption, receipt.ssekms_key_id)
+        .uploaded_bytes(body_len)
+        .default_body_used(default_body_used)
+        .compressed(compressed)
+        .effective_config(effective_config)
+        .metrics(InvocationMetrics {
+            validation_us,
+            client_build_us: uploader.client_build_us(),
+            s3_put_us,
+            total_us: elapsed_us(started_at),
+        })
+        .build())
+}
+
+async fn put_batch(
+    mut event: CustomEvent,
+    c: Context,
+    config: &Config,
+    uploader: &dyn Uploader,
+) -> Result<CustomOutput> {
+    let items = event.items.take().unwrap_or_default();
+    let max_batch_items = config.max_batch_items;
+    // reject up front, a half-uploaded batch is harder to recover from than none
+    if let Err(err) = check_batch_size(items.len(), max_batch_items) {
+        // Config::from_vars only sets fan_out_function together with the signing key
+        if let (Some(function_name), Some(signing_key)) =
+            (&config.fan_out_function, &config.internal_signing_key)
+        {
+            let count = items.len();
+            let lambda = LambdaClient::new(Region::default());
+            let invocations = fan_out_batch(
+                items,
+                &event,
+                function_name,
+                max_batch_items,
+                &c.request_id,
+                signing_key,
+                &lambda,
+            )
+            .await?;
+            info!(
+                "Fanned out {} items to {} invocations in request {}",
+                count, invocations, c.request_id
+            );
+            return Ok(CustomOutput::builder()
+                .message(format!(
+                    "Queued {} items in {} invocations.",
+                    count, invocations
+                ))
+                .build());
+        }
+        log_rejection(
+            "batch_too_large",
+            serde_json::json!({ "actual_len": items.len(), "limit": max_batch_items }),
+            &c,
+            config,
+        );
+        return Err(anyhow!(err));
+    }
+    let mut batch = BatchOutput::default();
+    // one failed item doesn't stop the rest, the caller retries only what's in `failed`
+    for (index, item) in items.into_iter().enumerate() {
+        let requested_key = item.key.clone();
+        let result = put_text(
+            CustomEvent {
+                key: item.key,
+                text_body: item.text_body,
+                cache_control: event.cache_control.clone(),
+                immutable: event.immutable,
+                encryption: event.encryption.clone(),
+                kms_key_id: event.kms_key_id.clone(),
+                content_type: event.content_type.clone(),
+                content_language: event.content_language.clone(),
+                expires_in_seconds: event.expires_in_seconds,
+                suffix_on_collision: event.suffix_on_collision,
+                source_ip: event.source_ip.clone(),
+                ..Default::default()
+            },
+            c.clone(),
+            config,
+            uploader,
+        )
+        .await;
+        match result {
+            Ok(output) => batch.succeeded.push(BatchItemResult {
+                index,
+                key: output.key.unwrap_or_default(),
+                etag: output.etag,
+            }),
+            Err(err) => batch
+                .failed
+                .push(get_batch_item_error(index, requested_key, err)),
+        }
+    }
+    Ok(CustomOutput::builder()
+        .message(format!("Uploaded {} items.", batch.succeeded.len()))
+        .batch(batch)
+        .build())
+}
+
+// errors stay typed until they leave the function, so every rejected item keeps
+// its own code whatever the error format
+fn get_batch_item_error(index: usize, key: Option<String>, err: anyhow::Error) -> BatchItemError {
+    match err.downcast::<LambdaError>() {
+        Ok(err) => BatchItemError {
+            index,
+            key,
+            code: err.name().to_string(),
+            message: err.detail(),
+        },
+        Err(err) => BatchItemError {
+            index,
+            key,
+            code: "InternalError".to_string(),
+            message: err.to_string(),
+        },
+    }
+}
+
+/// Keeps the event of a put that failed after its retries under `_dlq/` in
+/// `dlq_bucket`, so it can be replayed. Credentials are left out. A failed
+/// write is only logged, the caller still returns the original error.
+async fn write_dead_letter(
+    uploader: &dyn Uploader,
+    dlq_bucket: &str,
+    mut event: Value,
+    err: &anyhow::Error,
+    c: &Context,
+) {
+    if let Some(event) = event.as_object_mut() {
+        event.remove("apiKey");
+        event.remove("signature");
+        event.remove("internalSignature");
+    }
+    let record = serde_json::json!({
+        "requestId": c.request_id,
+        "event": event,
+        "error": err.to_string(),
+    });
+    let key = format!(
+        "{}{}.json",
+        DEAD_LETTER_PREFIX,
+        format_uuid_v4(random_seed())
+    );
+    let result = uploader
+        .upload(UploadParams {
+            bucket: dlq_bucket.to_string(),
+            key: key.clone(),
+            body: record.to_string().into_bytes(),
+            cache_control: None,
+            content_type: Some("application/json".to_string()),
+            content_language: None,
+            content_encoding: None,
+            expires: None,
+            website_redirect_location: None,
+            metadata: None,
+            tagging: None,
+            server_side_encryption: None,
+            ssekms_key_id: None,
+        })
+        .await;
+    match result {
+        Ok(_) => info!("Wrote dead letter {} for request {}", key, c.request_id),
+        Err(err) => error!(
+            "Failed to write dead letter for request {}: {}",
+            c.request_id, err
+        ),
+    }
+}
+
+/// Logs which validation rule rejected the request as a JSON `warn!` line.
+/// `detail` describes the offending value's shape, never its content.
+fn log_rejection(rule: &str, detail: Value, c: &Context, config: &Config) {
+    if config.log_rejections {
+        warn!("{}", build_rejection_record(rule, detail, c));
+    }
+}
+
+/// Tags every stored object with `SCHEMA_VERSION`, when set, to tell producer
+/// versions apart in later migrations.
+fn get_schema_metadata(config: &Config) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    if let Some(version) = &config.schema_version {
+        metadata.insert(SCHEMA_VERSION_METADATA.to_string(), version.clone());
+    }
+    metadata
+}
+
+/// The first line of every invocation, it also publishes the `ColdStart` metric.
+/// `main` passes stdout, see `write_metric_line`.
+fn log_cold_start<W: Write>(out: &mut W, cold_start: bool, c: &Context) {
+    let timestamp_ms = clock_skew::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let record = build_cold_start_record(cold_start, &c.request_id, timestamp_ms);
+    if let Err(err) = write_metric_line(out, &record) {
+        warn!("Failed to write the ColdStart metric: {}", err);
+    }
+}
+
+fn build_rejection_record(rule: &str, detail: Value, c: &Context) -> Value {
+    let mut record = serde_json::json!({
+        "event": "validation_rejected",
+        "rule": rule,
+        "requestId": c.request_id,
+    });
+    if let (Value::Object(record), Value::Object(detail)) = (&mut record, detail) {
+        record.extend(detail);
+    }
+    record
+}
+
+fn check_batch_size(count: usize, limit: usize) -> std::result::Result<(), LambdaError> {
+    if count > limit {
+        return Err(LambdaError::BatchTooLarge { limit, count });
+    }
+    Ok(())
+}
+
+fn get_timings(metrics: &InvocationMetrics) -> BTreeMap<&'static str, f64> {
+    let ms = |us: u64| us as f64 / 1000.0;
+    let mut timings = BTreeMap::new();
+    timings.insert("validation_ms", ms(metrics.validation_us));
+    timings.insert("client_build_ms", ms(metrics.client_build_us));
+    timings.insert("s3_put_ms", ms(metrics.s3_put_us));
+    timings
+}
+
+fn elapsed_us(started_at: Instant) -> u64 {
+    started_at.elapsed().as_micros() as u64
+}
+
+async fn get_text(
+    event: CustomEvent,
+    c: Context,
+    config: &Config,
+    uploader: &dyn Uploader,
+) -> Result<CustomOutput> {
+    let bucket_name = &config.bucket_name;
+    let key = resolve_object_key(event.key, config);
+    let as_data_uri = event.data_uri.unwrap_or(false);
+    // data URIs are meant for small objects, allowLarge doesn't lift the cap for them
+    let max_get_bytes = config
+        .max_get_bytes
+        .filter(|_| as_data_uri || !event.allow_large.unwrap_or(false));
+    // parsed up front so an unknown name does not cost a download
+    let transform = match event.read_transform.as_deref().map(Transform::parse) {
+        Some(Ok(transform)) => Some(transform),
+        Some(Err(err)) => {
+            error!("Unknown read transform in request {}", c.request_id);
+            return Err(anyhow!(err));
+        }
+        None => None,
+    };
+    let object_text = match apply_deadline(
+        &c,
+        config,
+        "S3",
+        read_text_within(
+            uploader,
+            &S3Object::new(bucket_name.as_str(), key.as_str()),
+            config.decompress_on_read,
+            max_get_bytes,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(object_text)) => object_text,
+        Ok(Err(err)) => match err.downcast::<LambdaError>() {
+            Ok(err) => {
+                error!("{} for {} in request {}", err, key, c.request_id);
+                return Err(anyhow!(err));
+            }
+            Err(err) => return Err(err),
+        },
+        Err(err) => {
+            error!("{} in request {}", err, c.request_id);
+            return Err(anyhow!(err));
+        }
+    };
+    let ObjectText { text, content_type } = object_text;
+    let text = match transform {
+        Some(transform) => apply_transform(transform, &text),
+        None => text,
+    };
+    info!("Read {} in request {}", key, c.request_id);
+    if as_data_uri {
+        let content_type = content_type.unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string());
+        return Ok(CustomOutput::builder()
+            .data_uri(build_data_uri(&content_type, text.as_bytes()))
+            .build());
+    }
+    Ok(CustomOutput::builder().text(text).build())
+}
+
+async fn stat_text(
+    event: CustomEvent,
+    c: Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<CustomOutput> {
+    let key = resolve_object_key(event.key, config);
+    let include_tags = event.include_tags.unwrap_or(false);
+    let stat = match apply_deadline(
+        &c,
+        config,
+        "S3",
+        stat_object(
+            s3,
+            &S3Object::new(config.bucket_name.as_str(), key.as_str()),
+            include_tags,
+        ),
+    )
+    .await
+    {
+        Ok(result) => result?,
+        Err(err) => {
+            error!("{} in request {}", err, c.request_id);
+            return Err(anyhow!(err));
+        }
+    };
+    Ok(CustomOutput::builder().key(key).stat(stat).build())
+}
+
+/// Container-local gauges; cheap, and they reset on every cold start.
+fn get_diag() -> CustomOutput {
+    CustomOutput::builder()
+        .total_bytes_written(TOTAL_BYTES_WRITTEN.get())
+        .build()
+}
+
+/// Unhealthy is still a successful invocation, the report says what failed.
+async fn get_health(event: CustomEvent, config: &Config, s3: &S3Client) -> CustomOutput {
+    let report = health_report(
+        s3,
+        config,
+        event.deep.unwrap_or(false),
+        Duration::from_millis(S3_CONNECTIVITY_TIMEOUT_MS),
+    )
+    .await;
+    CustomOutput::builder().health(report).build()
+}
+
+async fn list_keys(
+    event: CustomEvent,
+    c: Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<CustomOutput> {
+    let page = match apply_deadline(
+        &c,
+        config,
+        "S3",
+        list_key_page(
+            s3,
+            &config.bucket_name,
+            event.prefix.as_deref(),
+            event.max_results.unwrap_or(MAX_LIST_RESULTS),
+            event.continuation_token,
+        ),
+    )
+    .await
+    {
+        Ok(result) => result?,
+        Err(err) => {
+            error!("{} in request {}", err, c.request_id);
+            return Err(anyhow!(err));
+        }
+    };
+    Ok(CustomOutput::builder().page(page).build())
+}
+
+async fn move_text(
+    event: CustomEvent,
+    c: Context,
+    config: &Config,
+    s3: &S3Client,
+) -> Result<CustomOutput> {
+    let destination_key = match event.destination_key {
+        Some(destination_key) => resolve_object_key(Some(destination_key), config),
+        None => {
+            error!("No destination key in request {}", c.request_id);
+            return Err(anyhow!(LambdaError::MissingDestinationKey));
+        }
+    };
+    if let Err(err) = check_key_depth(&destination_key, config.max_key_depth) {
+        log_rejection("key_too_deep", serde_json::json!({}), &c, config);
+        return Err(anyhow!(err));
+    }
+    let source_key = resolve_object_key(event.key, config);
+    // a move deletes the source, so both ends count as writes
+    for key in &[&destination_key, &source_key] {
+        if let Err(err) = check_write_prefix(key, config) {
+            log_rejection("outside_write_prefix", serde_json::json!({}), &c, config);
+            return Err(anyhow!(err));
+        }
+    }
+    let outcome = move_object(s3, &config.bucket_name, &source_key, &destination_key).await?;
+    let (message, warning) = match outcome {
+        MoveOutcome::Moved => {
+            info!(
+                "Moved {} to {} in request {}",
+                source_key, destination_key, c.request_id
+            );
+            ("Succeeded.", None)
+        }
+        MoveOutcome::SourceNotDeleted(err) => {
+            error!(
+                "Copied {} to {} but failed to delete the source in request {}: {}",
+                source_key, destination_key, c.request_id, err
+            );
+            (
+                "Partially succeeded.",
+                Some(format!(
+                    "Copied, but failed to delete {}: {}",
+                    source_key, err
+                )),
+            )
+        }
+    };
+    Ok(CustomOutput::builder()
+        .message(message)
+        .key(destination_key)
+        .source_key(source_key)
+        .warning(warning)
+        .build())
+}
+
+/// The inventory is written like any put: under the resolved key, inside the
+/// write prefix and through the uploader.
+async fn write_inventory(
+    event: CustomEvent,
+    c: Context,
+    config: &Config,
+    uploader: &dyn Uploader,
+) -> Result<CustomOutput> {
+    let bucket_name = &config.bucket_name;
+    let destination_key = resolve_object_key(
+        Some(
+            event
+                .destination_key
+                .unwrap_or_else(|| DEFAULT_INVENTORY_KEY.to_string()),
+        ),
+        config,
+    );
+    if let Err(err) = check_write_prefix(&destination_key, config) {
+        log_rejection("outside_write_prefix", serde_json::json!({}), &c, config);
+        return Err(anyhow!(err));
+    }
+    if let Err(err) = check_key_depth(&destination_key, config.max_key_depth) {
+        log_rejection("key_too_deep", serde_json::json!({}), &c, config);
+        return Err(anyhow!(err));
+    }
+    let objects = match apply_deadline(
+        &c,
+        config,
+        "S3",
+        uploader.list(bucket_name, event.prefix.as_deref()),
+    )
+    .await
+    {
+        Ok(result) => result?,
+        Err(err) => {
+            error!("{} in request {}", err, c.request_id);
+            return Err(anyhow!(err));
+        }
+    };
+    let csv = build_inventory_csv(&objects);
+    let receipt = uploader
+        .upload(UploadParams {
+            bucket: bucket_name.to_string(),
+            key: destination_key.clone(),
+            body: csv.into_bytes(),
+            cache_control: None,
+            content_type: Some("text/csv".to_string()),
+            content_language: None,
+            content_encoding: None,
+            expires: None,
+            website_redirect_location: None,
+            metadata: Some(get_schema_metadata(config)).filter(|metadata| !metadata.is_empty()),
+            tagging: None,
+            server_side_encryption: None,
+            ssekms_key_id: None,
+        })
+        .await?;
+    info!(
+        "Wrote inventory of {} objects to {} in request {}",
+        objects.len(),
+        destination_key,
+        c.request_id
+    );
+    Ok(CustomOutput::builder()
+        .text(destination_key)
+        .etag(receipt.etag)
+        .build())
+}
+
+/// A downloaded object's text and the content type it was stored with.
+#[derive(Debug, PartialEq)]
+struct ObjectText {
+    text: String,
+    content_type: Option<String>,
+}
+
+fn build_data_uri(content_type: &str, body: &[u8]) -> String {
+    format!("data:{};base64,{}", content_type, base64::encode(body))
+}
+
+async fn read_text(
+    uploader: &dyn Uploader,
+    object: &S3Object,
+    decompress: bool,
+) -> Result<ObjectText> {
+    let stored = uploader.fetch(object).await?;
+    let body = decode_body(stored.body, stored.content_encoding.as_deref(), decompress)?;
+    Ok(ObjectText {
+        text: TextBody::try_new(body)?.into_string(),
+        content_type: stored.content_type,
+    })
+}
+
+/// Like `read_text`, but with `max_bytes` the size is checked first so an
+/// oversized object is rejected before any of it is loaded into memory.
+async fn read_text_within(
+    uploader: &dyn Uploader,
+    object: &S3Object,
+    decompress: bool,
+    max_bytes: Option<u64>,
+) -> Result<ObjectText> {
+    if let Some(limit) = max_bytes {
+        let size = uploader.size(object).await?;
+        if size > limit {
+            return Err(LambdaError::ObjectTooLarge { size, limit }.into());
+        }
+    }
+    read_text(uploader, object, decompress).await
+}
+
+/// Gzips a body over `threshold` bytes, keeping the original when gzip doesn't
+/// make it smaller; the flag says whether the compressed body is returned.
+fn compress_body(body: Vec<u8>, threshold: Option<usize>) -> Result<(Vec<u8>, bool)> {
+    match threshold {
+        Some(threshold) if body.len() > threshold => {}
+        _ => return Ok((body, false)),
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&body)?;
+    let compressed = encoder.finish()?;
+    if compressed.len() >= body.len() {
+        return Ok((body, false));
+    }
+    Ok((compressed, true))
+}
+
+/// Transparently gunzips objects stored with `Content-Encoding: gzip`.
+fn decode_body(body: Vec<u8>, content_encoding: Option<&str>, decompress: bool) -> Result<Vec<u8>> {
+    let is_gzip = content_encoding
+        .map(|encoding| encoding.eq_ignore_ascii_case("gzip"))
+        .unwrap_or(false);
+    if !decompress || !is_gzip {
+        return Ok(body);
+    }
+    let mut decoded = Vec::new();
+    GzDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+/// Substitutes `default_body` for a missing or empty body; the flag says whether it did.
+fn apply_default_body(
+    text_body: Option<String>,
+    default_body: Option<&str>,
+) -> (Option<String>, bool) {
+    match (text_body, default_body) {
+        (Some(text), _) if !text.is_empty() => (Some(text), false),
+        (_, Some(default_body)) => (Some(default_body.to_string()), true),
+        (text_body, None) => (text_body, false),
+    }
+}
+
+/// Returns `None` when the request must be rejected for having no body.
+/// With `allow_empty_upload` a missing body becomes a zero-byte marker object.
+fn resolve_text_body(text_body: Option<String>, allow_empty_upload: bool) -> Option<String> {
+    match text_body {
+        Some(text) => Some(text),
+        None if allow_empty_upload => Some(String::new()),
+        None => None,
+    }
+}
+
+/// Trims surrounding whitespace and converts `\r\n` to `\n`.
+/// Borrows the input when nothing has to change.
+fn normalize_text(raw: &str) -> Cow<str> {
+    let trimmed = raw.trim();
+    if trimmed.contains("\r\n") {
+        Cow::Owned(trimmed.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(trimmed)
+    }
+}
+
+/// The request value wins over the deployment default.
+fn resolve_cache_control(
+    requested: Option<String>,
+    default: Option<String>,
+) -> std::result::Result<Option<String>, LambdaError> {
+    let cache_control = match requested.or(default) {
+        Some(value) if !value.is_empty() => value,
+        _ => return Ok(None),
+    };
+    if !is_valid_cache_control(&cache_control) {
+        return Err(LambdaError::InvalidCacheControl);
+    }
+    Ok(Some(cache_control))
+}
+
+/// Without a key every op but put falls back to the static key; a generated
+/// key can't be found again.
+fn resolve_object_key(requested: Option<String>, config: &Config) -> String {
+    let default_key = match &config.key_strategy {
+        KeyStrategy::Static(key) => key.as_str(),
+        _ => DEFAULT_OBJECT_KEY,
+    };
+    // the function name goes after the deployment prefix and keeps its casing too
+    let prefix = match &config.function_namespace {
+        Some(function_name) => Some(format!(
+            "{}{}/",
+            config.key_prefix.as_deref().unwrap_or_default(),
+            function_name
+        )),
+        None => config.key_prefix.clone(),
+    };
+    normalize_key(
+        requested.as_deref().unwrap_or(default_key),
+        config.lowercase_keys,
+        prefix.as_deref(),
+    )
+}
+
+/// With `WRITE_PREFIX` set, only resolved keys under it can be written.
+/// Reads are not restricted.
+fn check_write_prefix(key: &str, config: &Config) -> Result<(), LambdaError> {
+    match &config.write_prefix {
+        Some(prefix) if !key.starts_with(prefix.as_str()) => {
+            Err(LambdaError::OutsideWritePrefix(key.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Counts the `/`-separated segments of the resolved key, prefix included;
+/// empty segments from doubled or trailing slashes don't count.
+fn check_key_depth(key: &str, limit: Option<usize>) -> std::result::Result<(), LambdaError> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+    let depth = key.split('/').filter(|segment| !segment.is_empty()).count();
+    if depth > limit {
+        return Err(LambdaError::KeyTooDeep { limit, depth });
+    }
+    Ok(())
+}
+
+/// `dir/name.txt` becomes `dir/name-<n>.txt`; the suffix goes before the last
+/// extension of the file name, and a dotfile like `.env` counts as having none.
+fn add_collision_suffix(key: &str, n: u32) -> String {
+    let name_start = key.rfind('/').map(|i| i + 1).unwrap_or(0);
+    match key[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}-{}{}", &key[..dot], n, &key[dot..])
+        }
+        _ => format!("{}-{}", key, n),
+    }
+}
+
+/// `key` itself when nothing is stored there, otherwise the first suffixed key
+/// that is free. Best effort, like the put precondition: it asks before
+/// uploading, so two concurrent puts can pick the same key and the later one
+/// overwrites the other.
+async fn find_free_key(
+    uploader: &dyn Uploader,
+    bucket_name: &str,
+    key: &str,
+) -> Result<std::result::Result<String, LambdaError>> {
+    if !uploader.exists(&S3Object::new(bucket_name, key)).await? {
+        return Ok(Ok(key.to_string()));
+    }
+    for n in 1..=MAX_COLLISION_SUFFIX {
+        let candidate = add_collision_suffix(key, n);
+        if !uploader
+            .exists(&S3Object::new(bucket_name, candidate.as_str()))
+            .await?
+        {
+            return Ok(Ok(candidate));
+        }
+    }
+    Ok(Err(LambdaError::NoFreeKey {
+        attempts: MAX_COLLISION_SUFFIX,
+    }))
+}
+
+/// Lowercases before prefixing, so the deployment's prefix keeps its own casing.
+fn normalize_key(key: &str, lowercase: bool, prefix: Option<&str>) -> String {
+    let key = if lowercase {
+        key.to_lowercase()
+    } else {
+        key.to_string()
+    };
+    match prefix {
+        Some(prefix) if !prefix.is_empty() => format!("{}{}", prefix, key),
+        _ => key,
+    }
+}
+
+/// Falls back to plain text, then checks the allow-list when one is configured.
+/// Parameters such as `charset` are ignored when matching.
+fn resolve_content_type(
+    requested: Option<String>,
+    allowed: Option<String>,
+) -> std::result::Result<String, LambdaError> {
+    let content_type = match requested {
+        Some(value) if !value.is_empty() => value,
+        _ => DEFAULT_CONTENT_TYPE.to_string(),
+    };
+    let allowed = match allowed {
+        Some(value) if !value.is_empty() => value,
+        _ => return Ok(content_type),
+    };
+    let media_type = get_media_type(&content_type);
+    if allowed
+        .split(',')
+        .any(|allowed_type| get_media_type(allowed_type) == media_type)
+    {
+        Ok(content_type)
+    } else {
+        Err(LambdaError::UnsupportedContentType(content_type))
+    }
+}
+
+fn get_media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase()
+}
+
+// S3 only accepts a path within the website bucket or an absolute URL
+fn is_valid_website_redirect(location: &str) -> bool {
+    let is_absolute_url = ["http://", "https://"]
+        .iter()
+        .any(|scheme| location.starts_with(scheme) && location.len() > scheme.len());
+    (location.starts_with('/') || is_absolute_url) && !location.chars().any(|c| c.is_control())
+}
+
+// a language and an optional region, like `ja` or `en-US`; full BCP 47 is not needed here
+fn is_valid_content_language(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or("");
+    let is_language =
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic());
+    let is_region = match subtags.next() {
+        None => true,
+        Some(region) => {
+            (region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic()))
+                || (region.len() == 3 && region.chars().all(|c| c.is_ascii_digit()))
+        }
+    };
+    is_language && is_region && subtags.next().is_none()
+}
+
+// every directive must look like `token` or `token=value`
+fn is_valid_cache_control(value: &str) -> bool {
+    let is_token =
+        |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    value.split(',').map(str::trim).all(|directive| {
+        let mut parts = directive.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        match parts.next() {
+            None => is_token(name),
+            Some(value) => {
+                is_token(name) && !value.is_empty() && !value.chars().any(|c| c.is_control())
+            }
+        }
+    })
+}
+
+fn get_s3_client(config: &Config) -> S3Client {
+    #[cfg(feature = "mock-s3")]
+    {
+        use mock::{S3MockResponseBuilder, MOCK_ETAG};
+        use rusoto_mock::MockCredentialsProvider;
+
+        if config.mock_s3 {
+            return S3Client::new_with(
+                S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+                MockCredentialsProvider,
+                Default::default(),
+            );
+        }
+    }
+    CLIENT_CACHE.get_or_build(&config.region, || build_s3_client(config))
+}
+
+fn get_s3_region(local: bool) -> Region {
+    if local {
+        // local
+        // rusoto_s3 always addresses buckets path-style (`endpoint/bucket/key`),
+        // which is what S3-compatible servers such as MinIO expect
+        return Region::Custom {
+            name: "ap-northeast-1".to_owned(),
+            endpoint: "http://host.docker.internal:8000".to_owned(),
+        };
+    }
+    // cloud
+    Region::ApNortheast1
+}
+
+/// Fails the cold start when S3 can't be reached, so a broken deployment shows
+/// up in the init logs instead of as a failed first invocation.
+async fn check_s3_connectivity(s3: &S3Client) -> Result<()> {
+    check_s3_connectivity_within(s3, Duration::from_millis(S3_CONNECTIVITY_TIMEOUT_MS)).await
+}
+
+async fn check_s3_connectivity_within(s3: &S3Client, limit: Duration) -> Result<()> {
+    match timeout(limit, s3.list_buckets()).await {
+        Ok(Ok(_)) => {
+            info!("S3 connectivity verified");
+            Ok(())
+        }
+        Ok(Err(err)) => Err(anyhow!("S3 connectivity check failed: {}", err)),
+        Err(_) => Err(anyhow!(
+            "S3 connectivity check timed out after {}ms",
+            limit.as_millis()
+        )),
+    }
+}
+
+/// Compares the container clock with S3's once per cold start; a failed check
+/// is only logged, it must not keep the function from starting.
+async fn check_startup_clock_skew(threshold_ms: u64, region: &Region) {
+    let dispatcher = match HttpClient::new() {
+        Ok(dispatcher) => dispatcher,
+        Err(err) => {
+            warn!("Failed to check clock skew: {}", err);
+            return;
+        }
+    };
+    match fetch_server_time(&dispatcher, region).await {
+        Ok(server_time) => {
+            check_clock_skew(
+                server_time,
+                clock_skew::now,
+                Duration::from_millis(threshold_ms),
+            );
+        }
+        Err(err) => warn!("Failed to check clock skew: {}", err),
+    }
+}
+
+fn build_s3_client(config: &Config) -> S3Client {
+    let region = config.region.clone();
+    let dispatcher = HttpClient::new().expect("failed to create request dispatcher");
+    let credentials =
+        DefaultCredentialsProvider::new().expect("failed to create credentials provider");
+    let dispatcher = UserAgentDispatcher::new(dispatcher, config.s3_user_agent.clone());
+    if config.s3_request_logging {
+        S3Client::new_with(
+            RequestLoggingDispatcher::new(dispatcher),
+            credentials,
+            region,
+        )
+    } else {
+        S3Client::new_with(dispatcher, credentials, region)
+    }
+}
+
+// only the explicit signals count: ECS, Fargate, CI runners and CONTAINER_MODE
+// have no AWS_EXECUTION_ENV either and still talk to the real S3
+fn is_local_env<F>(get_var: F) -> bool
+where
+    F: Fn(&str) -> Option<String>,
+{
+    get_var(LOCAL_KEY).map(|v| parse_flag(&v)).unwrap_or(false)
+        || get_var(SAM_LOCAL_KEY)
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false)
+        || get_var(LOCALSTACK_HOSTNAME_KEY).is_some()
+}
+
+/// Uploads through the deployment's S3 client, retrying within the invocation deadline.
+fn get_uploader(s3: &S3Client, config: &Config, c: &Context) -> S3Uploader {
+    let deadline = deadline_with_buffer(c, config).map(|remaining| Instant::now() + remaining);
+    S3Uploader::new(s3.clone(), config.max_retries, deadline)
+        .with_retriable_status_codes(config.retriable_status_codes.clone())
+}
+
+/// The retry count a request asked for, or `S3_MAX_RETRIES` when it didn't.
+fn request_max_retries(requested: Option<u32>, config: &Config) -> u32 {
+    requested
+        .map(|retries| retries.min(MAX_REQUEST_RETRIES))
+        .unwrap_or(config.max_retries)
+}
+
+/// Converts the invocation deadline (epoch millis) into an `Instant`.
+/// A context without a deadline, like `Context::default()`, is unbounded.
+fn get_deadline(c: &Context) -> Option<Instant> {
+    if c.deadline == 0 {
+        return None;
+    }
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    Some(Instant::now() + Duration::from_millis(c.deadline.saturating_sub(now_ms)))
+}
+
+/// Time left in the invocation minus `LAMBDA_HANDLER_TIMEOUT_BUFFER_MS`, zero once
+/// inside the buffer. `None` for a context without a deadline.
+fn deadline_with_buffer(c: &Context, config: &Config) -> Option<Duration> {
+    let remaining = get_deadline(c)?.saturating_duration_since(Instant::now());
+    let buffer = Duration::from_millis(config.handler_timeout_buffer_ms);
+    Some(remaining.checked_sub(buffer).unwrap_or_default())
+}
+
+/// Bounds a downstream call by `deadline_with_buffer`.
+/// Calls without a deadline run unbounded.
+async fn apply_deadline<F: Future>(
+    c: &Context,
+    config: &Config,
+    service: &'static str,
+    fut: F,
+) -> std::result::Result<F::Output, LambdaError> {
+    match deadline_with_buffer(c, config) {
+        None => Ok(fut.await),
+        Some(remaining) if remaining > Duration::from_millis(0) => timeout(remaining, fut)
+            .await
+            .map_err(|_| LambdaError::UpstreamTimeout { service }),
+        // not worth starting a call that can't finish
+        Some(_) => Err(LambdaError::UpstreamTimeout { service }),
+    }
+}
+
+/// Runs `op` until it succeeds, the error is not retriable, `max_retries` retries
+/// have been made, or the next backoff would run past `deadline`.
+/// The last error is returned when giving up.
+async fn with_retry<T, E, F, Fut, R>(
+    max_retries: u32,
+    deadline: Option<Instant>,
+    is_retriable: R,
+    mut op: F,
+) -> std::result::Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+    R: Fn(&E) -> bool,
+{
+    let mut retries = 0;
+    loop {
+        let err = match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        if retries >= max_retries || !is_retriable(&err) {
+            return Err(err);
+        }
+        let backoff = Duration::from_millis(RETRY_BASE_DELAY_MS * 2u64.pow(retries));
+        if let Some(deadline) = deadline {
+            if Instant::now() + backoff >= deadline {
+                return Err(err);
+            }
+        }
+        delay_for(backoff).await;
+        retries += 1;
+    }
+}
+
+/// Dispatch failures, 5xx and 429 are always retried; `extra_status_codes` adds
+/// codes that S3-compatible backends use for transient errors.
+fn is_retriable_s3_error<E>(err: &RusotoError<E>, extra_status_codes: &[u16]) -> bool {
+    match err {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(response) => {
+            let status = response.status.as_u16();
+            response.status.is_server_error()
+                || status == 429
+                || extra_status_codes.contains(&status)
+        }
+        _ => false,
+    }
+}
+
+fn get_err_msg(code: u16, msg: &str) -> String {
+    format!("[{}] {}", code, msg)
+}
+
+fn get_status_title(code: u16) -> &'static str {
+    match code {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+/// `0`, `false`, `no`, `off` and the empty string (any case) turn a flag off.
+/// Any other value turns it on, like `LOCAL_FLAG=local` in serverless.yml.
+fn parse_flag(value: &str) -> bool {
+    let value = value.trim();
+    !(value.is_empty()
+        || value == "0"
+        || value.eq_ignore_ascii_case("false")
+        || value.eq_ignore_ascii_case("no")
+        || value.eq_ignore_ascii_case("off"))
+}
+
+/// An unset variable is off.
+fn env_flag(key: &str) -> bool {
+    env::var(key).map(|v| parse_flag(&v)).unwrap_or(false)
+}
+
+/// Handlers return a `LambdaError` as it is, this renders it once it leaves the
+/// function: its message becomes the body API Gateway maps into the response.
+/// Other errors pass through unchanged.
+fn render_error(err: anyhow::Error, format: ErrorFormat, request_id: &str) -> anyhow::Error {
+    let err = match err.downcast::<LambdaError>() {
+        Ok(err) => err,
+        Err(err) => return err,
+    };
+    // serializing plain strings and numbers never fails
+    match format {
+        ErrorFormat::ProblemJson => {
+            let problem = err.to_problem_details(request_id);
+            anyhow!(serde_json::to_string(&problem).unwrap())
+        }
+        ErrorFormat::Json => anyhow!(serde_json::to_string(&err).unwrap()),
+        ErrorFormat::Plain => anyhow!(err),
+    }
+}
+
+/// Only compiles when `T` can be kept in a static or an `Arc` shared by every
+/// invocation of the container, which is also what restoring init state from a
+/// snapshot would need.
+const fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+// everything built at init and reused by later invocations
+const _: () = assert_send_sync::<S3Client>();
+const _: () = assert_send_sync::<client_cache::ClientCache>();
+const _: () = assert_send_sync::<Config>();
+const _: () = assert_send_sync::<diag::ByteCounter>();
+
+#[cfg(test)]
+fn hoge_function() -> String {
+    println!("hoge function executed.");
+    String::from("hogehoge!")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use logger::testing::{capture_logs, get_captured_logs};
+    use mock::testing::SlowDispatcher;
+    use mock::{S3MockResponseBuilder, MOCK_ETAG};
+    use rusoto_mock::{
+        MockCredentialsProvider, MockRequestDispatcher, MultipleMockRequestDispatcher,
+    };
+    use rusoto_s3::PutObjectRequest;
+    use std::io::Write;
+    use uploader::testing::{get_memory_etag, MemoryUploader};
+
+    fn get_config() -> Config {
+        Config {
+            bucket_name: "test-bucket".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn get_mock_s3() -> S3Client {
+        S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+            MockCredentialsProvider,
+            Default::default(),
+        )
+    }
+
+    #[test]
+    fn can_get_local_s3_client() {
+        let config = Config {
+            local: true,
+            region: get_s3_region(true),
+            ..get_config()
+        };
+        let _s3 = get_s3_client(&config);
+    }
+
+    #[test]
+    fn can_parse_flag_values() {
+        for value in &["1", "true", "TRUE", "yes", "local"] {
+            assert!(parse_flag(value), "{}", value);
+        }
+        for value in &["", " ", "0", "false", "False", "no", "off"] {
+            assert!(!parse_flag(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn can_get_cloud_s3_client() {
+        let _s3 = get_s3_client(&get_config());
+    }
+
+    #[tokio::test]
+    async fn can_s3_client_address_bucket_path_style() {
+        let s3 = S3Client::new_with(
+            MockRequestDispatcher::default().with_request_checker(|request| {
+                assert_eq!(request.path, "/test-bucket/test.txt");
+                assert_eq!(request.hostname(), "localhost:8000");
+            }),
+            MockCredentialsProvider,
+            Region::Custom {
+                name: "ap-northeast-1".to_owned(),
+                endpoint: "http://localhost:8000".to_owned(),
+            },
+        );
+        let result = s3
+            .put_object(PutObjectRequest {
+                bucket: "test-bucket".to_string(),
+                key: "test.txt".to_string(),
+                ..Default::default()
+            })
+            .await;
+        assert!(result.is_ok());
+    }
+
+    fn get_error_client(code: &str, message: &str) -> S3Client {
+        S3Client::new_with(
+            S3MockResponseBuilder::put_object_error(code, message),
+            MockCredentialsProvider,
+            Default::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn can_classify_retriable_s3_errors() {
+        let s3 = get_error_client("SlowDown", "Please reduce your request rate.");
+        let err = s3
+            .put_object(PutObjectRequest::default())
+            .await
+            .expect_err("expected Err(_) value");
+        assert!(is_retriable_s3_error(&err, &[]));
+
+        let s3 = get_error_client("AccessDenied", "Access Denied");
+        let err = s3
+            .put_object(PutObjectRequest::default())
+            .await
+            .expect_err("expected Err(_) value");
+        assert!(!is_retriable_s3_error(&err, &[]));
+        assert!(is_retriable_s3_error(&err, &[403]));
+    }
+
+    #[tokio::test]
+    async fn can_retry_until_success() {
+        let mut attempts = 0;
+        let result: std::result::Result<u32, &str> = with_retry(
+            3,
+            None,
+            |_| true,
+            || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 3 {
+                        Err("transient")
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn can_retry_stop_on_non_retriable_error() {
+        let mut attempts = 0;
+        let result: std::result::Result<(), &str> = with_retry(
+            3,
+            None,
+            |_| false,
+            || {
+                attempts += 1;
+                async { Err("fatal") }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("fatal"));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn can_retry_stop_before_deadline() {
+        let started = Instant::now();
+        let deadline = started + Duration::from_millis(300);
+        let mut attempts = 0;
+        let result: std::result::Result<(), &str> = with_retry(
+            10,
+            Some(deadline),
+            |_| true,
+            || {
+                attempts += 1;
+                async {
+                    delay_for(Duration::from_millis(50)).await;
+                    Err("slow failure")
+                }
+            },
+        )
+        .await;
+        assert_eq!(result, Err("slow failure"));
+        assert!(attempts < 11);
+        // the last attempt may start right before the deadline, but no backoff runs past it
+        assert!(Instant::now() < deadline + Duration::from_millis(100));
+    }
+
+    #[test]
+    fn can_resolve_request_max_retries() {
+        let config = get_config();
+        assert_eq!(request_max_retries(None, &config), DEFAULT_MAX_RETRIES);
+        assert_eq!(request_max_retries(Some(0), &config), 0);
+        assert_eq!(request_max_retries(Some(5), &config), 5);
+        assert_eq!(
+            request_max_retries(Some(1000), &config),
+            MAX_REQUEST_RETRIES
+        );
+    }
+
+    #[tokio::test]
+    async fn can_put_text_override_max_retries() {
+        let get_s3 = || {
+            S3Client::new_with(
+                MultipleMockRequestDispatcher::new(vec![
+                    MockRequestDispatcher::with_status(503),
+                    S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+                ]),
+                MockCredentialsProvider,
+                Default::default(),
+            )
+        };
+        let get_event = |max_retries| CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            max_retries,
+            ..Default::default()
+        };
+        let config = get_config();
+
+        let event = get_event(Some(0));
+        let uploader = get_uploader(&get_s3(), &config, &Context::default())
+            .with_max_retries(request_max_retries(event.max_retries, &config));
+        assert!(put_text(event, Context::default(), &config, &uploader)
+            .await
+            .is_err());
+
+        let event = get_event(None);
+        let uploader = get_uploader(&get_s3(), &config, &Context::default())
+            .with_max_retries(request_max_retries(event.max_retries, &config));
+        let output = put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.etag, Some(MOCK_ETAG.to_string()));
+    }
+
+    fn get_context_with_remaining(remaining_ms: u64) -> Context {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        Context {
+            deadline: now_ms + remaining_ms,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_skip_writes_when_circuit_open() {
+        let uploader = MemoryUploader::default();
+        let event: CustomEvent = serde_json::from_value(serde_json::json!({
+            "items": [
+                { "key": "a.txt", "textBody": "Firstname" },
+                { "key": "b.txt", "textBody": "Lastname" },
+            ]
+        }))
+        .unwrap();
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(get_context_with_remaining(100)),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await;
+        assert!(result
+            .expect_err("expected Err(_) value")
+            .to_string()
+            .starts_with(&format!("[503] {}", MSG_INSUFFICIENT_TIME)));
+        assert!(uploader.store.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_check_s3_connectivity() {
+        let s3 = S3Client::new_with(
+            MockRequestDispatcher::default().with_body(
+                "<ListAllMyBucketsResult><Buckets><Bucket><Name>test-bucket</Name>\
+                 <CreationDate>2020-11-16T00:00:00.000Z</CreationDate></Bucket></Buckets>\
+                 </ListAllMyBucketsResult>",
+            ),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        assert!(check_s3_connectivity(&s3).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn can_check_s3_connectivity_time_out() {
+        let s3 = S3Client::new_with(
+            SlowDispatcher::new(MockRequestDispatcher::default(), Duration::from_millis(200)),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let err = check_s3_connectivity_within(&s3, Duration::from_millis(10))
+            .await
+            .expect_err("expected Err(_) value");
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn can_apply_deadline_with_sufficient_time() {
+        let c = get_context_with_remaining(10_000);
+        assert_eq!(
+            apply_deadline(&c, &get_config(), "S3", async { 1 }).await,
+            Ok(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn can_apply_deadline_time_out_slow_call() {
+        let c = get_context_with_remaining(DEFAULT_TIMEOUT_BUFFER_MS + 100);
+        let result =
+            apply_deadline(&c, &get_config(), "S3", delay_for(Duration::from_secs(5))).await;
+        assert_eq!(result, Err(LambdaError::UpstreamTimeout { service: "S3" }));
+    }
+
+    #[tokio::test]
+    async fn can_apply_deadline_fail_without_remaining_time() {
+        let started = Instant::now();
+        // inside the margin, so nothing is left for the call
+        let c = get_context_with_remaining(DEFAULT_TIMEOUT_BUFFER_MS / 2);
+        let result =
+            apply_deadline(&c, &get_config(), "S3", delay_for(Duration::from_secs(5))).await;
+        assert_eq!(result, Err(LambdaError::UpstreamTimeout { service: "S3" }));
+        assert!(started.elapsed() < Duration::from_millis(DEFAULT_TIMEOUT_BUFFER_MS));
+    }
+
+    #[test]
+    fn can_get_deadline_with_buffer() {
+        let config = Config {
+            handler_timeout_buffer_ms: 500,
+            ..get_config()
+        };
+        let remaining = deadline_with_buffer(&get_context_with_remaining(10_000), &config)
+            .expect("expected Some(_) value");
+        assert!(remaining > Duration::from_millis(9_000));
+        assert!(remaining <= Duration::from_millis(9_500));
+        for remaining_ms in &[500, 100, 0] {
+            assert_eq!(
+                deadline_with_buffer(&get_context_with_remaining(*remaining_ms), &config),
+                Some(Duration::from_millis(0))
+            );
+        }
+        assert_eq!(deadline_with_buffer(&Context::default(), &config), None);
+    }
+
+    #[test]
+    fn can_get_no_deadline_from_default_context() {
+        assert_eq!(get_deadline(&Context::default()), None);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_handle_valid_request() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let uploader = MemoryUploader::default();
+        let expected = CustomOutput {
+            message: "Succeeded.".to_string(),
+            key: Some(DEFAULT_OBJECT_KEY.to_string()),
+            etag: Some(get_memory_etag(b"Firstname")),
+            uploaded_bytes: Some(9),
+            url: Some(format!(
+                "https://test-bucket.s3.ap-northeast-1.amazonaws.com/{}",
+                DEFAULT_OBJECT_KEY
+            )),
+            ..Default::default()
+        };
+        assert_eq!(
+            hello(
+                event,
+                AuthorizedContext::anonymous(Context::default()),
+                &get_config(),
+                &get_mock_s3(),
+                &uploader
+            )
+            .await
+            .expect("expected Ok(_) value"),
+            expected
+        );
+        assert_eq!(
+            uploader.get("test-bucket", DEFAULT_OBJECT_KEY),
+            Some(b"Firstname".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_put_text_report_uploaded_bytes() {
+        let event = CustomEvent {
+            text_body: Some("名前".to_string()),
+            ..Default::default()
+        };
+        let uploader = MemoryUploader::default();
+        let output = put_text(event, Context::default(), &get_config(), &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        let stored = uploader
+            .get("test-bucket", DEFAULT_OBJECT_KEY)
+            .expect("expected Some(_) value");
+        assert_eq!(output.uploaded_bytes, Some(6));
+        assert_eq!(output.uploaded_bytes, Some(stored.len() as u64));
+    }
+
+    #[tokio::test]
+    async fn can_put_text_store_immutable_object() {
+        let event = CustomEvent {
+            key: Some("assets/name.txt".to_string()),
+            text_body: Some("Firstname".to_string()),
+            cache_control: Some("no-cache".to_string()),
+            immutable: Some(true),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(request.path, "/test-bucket/assets/name.04176f09.txt");
+                assert_eq!(
+                    request.headers.get("cache-control"),
+                    Some(&vec![IMMUTABLE_CACHE_CONTROL.as_bytes().to_vec()])
+                );
+            }),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let config = get_config();
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        let output = put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("assets/name.04176f09.txt".to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_put_text_encrypt_with_kms_key() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            encryption: Some("aws:kms".to_string()),
+            kms_key_id: Some("alias/hello".to_string()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("x-amz-server-side-encryption"),
+                    Some(&vec![b"aws:kms".to_vec()])
+                );
+                assert_eq!(
+                    request
+                        .headers
+                        .get("x-amz-server-side-encryption-aws-kms-key-id"),
+                    Some(&vec![b"alias/hello".to_vec()])
+                );
+            }),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let config = Config {
+            require_explicit_kms_key: true,
+            ..get_config()
+        };
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        assert!(put_text(event, Context::default(), &config, &uploader)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn can_put_text_report_applied_encryption() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            encryption: Some("aws:kms".to_string()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG)
+                .with_header("x-amz-server-side-encryption", "aws:kms")
+                .with_header(
+                    "x-amz-server-side-encryption-aws-kms-key-id",
+                    "arn:aws:kms:ap-northeast-1:123456789012:key/1234abcd",
+                ),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let config = get_config();
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        let output = put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.encryption, Some("aws:kms".to_string()));
+        assert_eq!(
+            output.kms_key_id,
+            Some("arn:aws:kms:ap-northeast-1:123456789012:key/1234abcd".to_string())
+        );
+
+        // MemoryUploader applies no encryption, so nothing is reported
+        let output = put_text(
+            CustomEvent {
+                text_body: Some("Firstname".to_string()),
+                ..Default::default()
+            },
+            Context::default(),
+            &config,
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.encryption, None);
+        assert_eq!(output.kms_key_id, None);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_kms_without_explicit_key() {
+        let get_event = || CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            encryption: Some("aws:kms".to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            require_explicit_kms_key: true,
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        let err = hello(
+            get_event(),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect_err("expected Err(_) value");
+        assert!(err
+            .to_string()
+            .starts_with(&format!("[400] {}", MSG_MISSING_KMS_KEY)));
+        assert!(uploader.store.lock().unwrap().is_empty());
+
+        // without the flag S3 falls back to the default aws/s3 key
+        let output = hello(
+            get_event(),
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert!(output.key.is_some());
+    }
+
+    #[tokio::test]
+    async fn can_put_text_attach_text_stats() {
+        let event = CustomEvent {
+            text_body: Some("Firstname Lastname\nsecond line".to_string()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                for (name, count) in &[("line", "2"), ("word", "4"), ("char", "30")] {
+                    assert_eq!(
+                        request.headers.get(&format!("x-amz-meta-{}-count", name)),
+                        Some(&vec![count.as_bytes().to_vec()])
+                    );
+                }
+            }),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let config = Config {
+            analyze_text: true,
+            ..get_config()
+        };
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+    }
+
+    #[tokio::test]
+    async fn can_put_text_tag_schema_version() {
+        let get_s3 = |expected: Option<&'static str>| {
+            S3Client::new_with(
+                S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(
+                    move |request| {
+                        assert_eq!(
+                            request.headers.get("x-amz-meta-schema-version"),
+                            expected
+                                .map(|version| vec![version.as_bytes().to_vec()])
+                                .as_ref()
+                        );
+                    },
+                ),
+                MockCredentialsProvider,
+                Default::default(),
+            )
+        };
+        let get_event = || CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            schema_version: Some("2".to_string()),
+            ..get_config()
+        };
+        let s3 = get_s3(Some("2"));
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        put_text(get_event(), Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+
+        let config = get_config();
+        let s3 = get_s3(None);
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        put_text(get_event(), Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+    }
+
+    #[tokio::test]
+    async fn can_put_text_record_phase_metrics() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        // goes through the mock S3 client, an in-memory put can take under a microsecond
+        let config = get_config();
+        let uploader = get_uploader(&get_mock_s3(), &config, &Context::default());
+        let output = put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        let metrics = output.metrics.expect("expected Some(_) value");
+        // validation alone can finish within a microsecond, the signed S3 call cannot
+        assert!(metrics.s3_put_us > 0);
+        assert!(metrics.total_us >= metrics.validation_us + metrics.s3_put_us);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_return_timings() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            timings: Some(true),
+            ..Default::default()
+        };
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        let timings = output.timings.expect("expected Some(_) value");
+        for name in &["validation_ms", "client_build_ms", "s3_put_ms"] {
+            assert!(timings[name] >= 0.0);
+        }
+        assert_eq!(timings.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_omit_timings_by_default() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.timings, None);
+    }
+
+    #[tokio::test]
+    async fn can_list_keys_return_page_metadata() {
+        let event = CustomEvent {
+            op: Some(OP_LIST.to_string()),
+            max_results: Some(1),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            MockRequestDispatcher::default().with_body(
+                "<ListBucketResult><Name>test-bucket</Name><IsTruncated>true</IsTruncated>\
+                 <NextContinuationToken>page-2</NextContinuationToken><MaxKeys>1</MaxKeys>\
+                 <Contents><Key>a.txt</Key></Contents></ListBucketResult>",
+            ),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let output = list_keys(event, Context::default(), &get_config(), &s3)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.keys, Some(vec!["a.txt".to_string()]));
+        assert_eq!(output.next_continuation_token, Some("page-2".to_string()));
+        assert_eq!(output.is_truncated, Some(true));
+        assert_eq!(output.page_size, Some(1));
+        assert_eq!(output.count, Some(1));
+    }
+
+    #[tokio::test]
+    async fn can_list_keys_return_empty_page() {
+        let event = CustomEvent {
+            op: Some(OP_LIST.to_string()),
+            prefix: Some("missing/".to_string()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            MockRequestDispatcher::default().with_body(
+                "<ListBucketResult><Name>test-bucket</Name><IsTruncated>false</IsTruncated>\
+                 <KeyCount>0</KeyCount></ListBucketResult>",
+            ),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let output = list_keys(event, Context::default(), &get_config(), &s3)
+            .await
+            .expect("expected Ok(_) value");
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["message"], "Succeeded.");
+        assert_eq!(json["keys"], serde_json::json!([]));
+        assert_eq!(json["count"], 0);
+        assert_eq!(json["isTruncated"], false);
+        assert_eq!(json.get("nextContinuationToken"), None);
+    }
+
+    #[test]
+    fn can_build_custom_output() {
+        let output = CustomOutput::builder().key("test.txt").etag(None).build();
+        assert_eq!(
+            output,
+            CustomOutput {
+                message: "Succeeded.".to_string(),
+                key: Some("test.txt".to_string()),
+                ..Default::default()
+            }
+        );
+        let output = CustomOutput::builder()
+            .message("Partially succeeded.")
+            .warning(Some("Copied".to_string()))
+            .build();
+        assert_eq!(output.message, "Partially succeeded.");
+        assert_eq!(output.warning, Some("Copied".to_string()));
+    }
+
+    #[test]
+    fn can_serialize_output_with_and_without_etag() {
+        let output = CustomOutput {
+            message: "Succeeded.".to_string(),
+            etag: Some(MOCK_ETAG.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({ "message": "Succeeded.", "etag": MOCK_ETAG })
+        );
+        let output = CustomOutput {
+            message: "Succeeded.".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&output).unwrap(),
+            serde_json::json!({ "message": "Succeeded." })
+        );
+    }
+
+    #[tokio::test]
+    async fn can_put_text_return_etag() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let config = get_config();
+        let uploader = get_uploader(&get_mock_s3(), &config, &Context::default());
+        let output = put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.etag, Some(MOCK_ETAG.to_string()));
+    }
+
+    #[test]
+    fn can_accept_batch_at_limit() {
+        assert_eq!(check_batch_size(0, DEFAULT_MAX_BATCH_ITEMS), Ok(()));
+        assert_eq!(
+            check_batch_size(DEFAULT_MAX_BATCH_ITEMS, DEFAULT_MAX_BATCH_ITEMS),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn can_reject_batch_over_limit() {
+        let err = check_batch_size(DEFAULT_MAX_BATCH_ITEMS + 1, DEFAULT_MAX_BATCH_ITEMS)
+            .expect_err("expected Err(_) value");
+        assert_eq!(err.status(), 400);
+        assert_eq!(
+            err.to_string(),
+            format!("[400] {} (max: 25, got: 26)", MSG_BATCH_TOO_LARGE)
+        );
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_report_health() {
+        let event = CustomEvent {
+            op: Some(OP_HEALTH.to_string()),
+            deep: Some(true),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            MockRequestDispatcher::with_status(500),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &s3,
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        let output = serde_json::to_value(&output).unwrap();
+        assert_eq!(output["status"], "error");
+        assert_eq!(output["checks"][1]["name"], "s3");
+        assert_eq!(output["checks"][1]["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_put_batch() {
+        let event: CustomEvent = serde_json::from_value(serde_json::json!({
+            "items": [
+                { "key": "a.txt", "textBody": "Firstname" },
+                { "key": "b.txt", "textBody": "Lastname" },
+            ]
+        }))
+        .unwrap();
+        let uploader = MemoryUploader::default();
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.message, "Uploaded 2 items.");
+        assert_eq!(
+            uploader.get("test-bucket", "b.txt"),
+            Some(b"Lastname".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_restrict_writes_to_write_prefix() {
+        let config = Config {
+            write_prefix: Some("uploads/".to_string()),
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        let put = |key: &str| CustomEvent {
+            key: Some(key.to_string()),
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let output = hello(
+            put("uploads/a.txt"),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("uploads/a.txt".to_string()));
+
+        let err = hello(
+            put("logs/a.txt"),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect_err("expected Err(_) value");
+        assert!(err
+            .to_string()
+            .starts_with(&format!("[403] {}: logs/a.txt", MSG_OUTSIDE_WRITE_PREFIX)));
+        assert_eq!(uploader.get("test-bucket", "logs/a.txt"), None);
+
+        // reads outside the prefix are still allowed
+        uploader.insert("test-bucket", "logs/a.txt", b"Firstname");
+        let output = hello(
+            CustomEvent {
+                op: Some(OP_GET.to_string()),
+                key: Some("logs/a.txt".to_string()),
+                ..Default::default()
+            },
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.text, Some("Firstname".to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_write_inventory_under_resolved_key() {
+        let config = Config {
+            key_prefix: Some("reports/".to_string()),
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "a.txt", b"Firstname");
+        let output = hello(
+            CustomEvent {
+                op: Some(OP_INVENTORY.to_string()),
+                ..Default::default()
+            },
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.text, Some("reports/inventory.csv".to_string()));
+        let csv = uploader
+            .get("test-bucket", "reports/inventory.csv")
+            .expect("expected Some(_) value");
+        assert_eq!(output.etag, Some(get_memory_etag(&csv)));
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(
+            csv.starts_with("key,size,last_modified,etag\na.txt,9,,"),
+            "{}",
+            csv
+        );
+        assert_eq!(csv.lines().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn can_reject_inventory_outside_write_prefix() {
+        let config = Config {
+            write_prefix: Some("logs/".to_string()),
+            max_key_depth: Some(2),
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        for (destination_key, status) in &[("inventory.csv", 403), ("logs/a/b/c.csv", 400)] {
+            let err = hello(
+                CustomEvent {
+                    op: Some(OP_INVENTORY.to_string()),
+                    destination_key: Some(destination_key.to_string()),
+                    ..Default::default()
+                },
+                AuthorizedContext::anonymous(Context::default()),
+                &config,
+                &get_mock_s3(),
+                &uploader,
+            )
+            .await
+            .expect_err("expected Err(_) value");
+            let err = err
+                .downcast_ref::<LambdaError>()
+                .expect("expected LambdaError value");
+            assert_eq!(err.status(), *status);
+        }
+        assert!(uploader.store.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn can_add_collision_suffix() {
+        assert_eq!(add_collision_suffix("name.txt", 1), "name-1.txt");
+        assert_eq!(add_collision_suffix("logs/a.tar.gz", 2), "logs/a.tar-2.gz");
+        assert_eq!(add_collision_suffix("logs.d/name", 3), "logs.d/name-3");
+        assert_eq!(add_collision_suffix("config/.env", 1), "config/.env-1");
+    }
+
+    #[tokio::test]
+    async fn can_put_text_return_effective_config() {
+        let event = CustomEvent {
+            key: Some("notes/name.md".to_string()),
+            text_body: Some("Firstname".to_string()),
+            content_type: Some("text/markdown".to_string()),
+            cache_control: Some("no-cache".to_string()),
+            encryption: Some("AES256".to_string()),
+            include_effective_config: Some(true),
+            ..Default::default()
+        };
+        let config = Config {
+            api_key: Some("api-secret".to_string()),
+            internal_signing_key: Some("signing-secret".to_string()),
+            key_prefix: Some("uploads/".to_string()),
+            ..get_config()
+        };
+        let output = put_text(
+            event,
+            Context::default(),
+            &config,
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        let effective_config = output.effective_config.expect("expected Some(_) value");
+        assert_eq!(effective_config.bucket, "test-bucket");
+        assert_eq!(effective_config.key, "uploads/notes/name.md");
+        assert_eq!(effective_config.acl, "public-read");
+        assert_eq!(
+            effective_config.content_type.as_deref(),
+            Some("text/markdown")
+        );
+        assert_eq!(effective_config.cache_control.as_deref(), Some("no-cache"));
+        assert_eq!(effective_config.encryption.as_deref(), Some("AES256"));
+        assert_eq!(effective_config.api_key, Some("[REDACTED]"));
+        assert_eq!(effective_config.hmac_secret, None);
+        let json = serde_json::to_string(&effective_config).unwrap();
+        assert!(!json.contains("api-secret"));
+        assert!(!json.contains("signing-secret"));
+
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let output = put_text(
+            event,
+            Context::default(),
+            &config,
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.effective_config, None);
+    }
+
+    #[tokio::test]
+    async fn can_hello_tag_source_ip() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("x-amz-tagging"),
+                    Some(&vec![b"source-ip=2001%3Adb8%3A%3A1".to_vec()])
+                );
+            }),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let config = get_config();
+        let api_context = api_context::ApiGatewayContext {
+            apigw_request_id: "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9".to_string(),
+            source_ip: "2001:db8::1".to_string(),
+            user_agent: None,
+            claims: BTreeMap::new(),
+        };
+        let c =
+            AuthorizedContext::anonymous(Context::default()).with_api_context(Some(api_context));
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        assert!(hello(event, c, &config, &s3, &uploader).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn can_put_text_use_filename_as_key() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            filename: Some("notes.txt".to_string()),
+            ..Default::default()
+        };
+        let output = put_text(
+            event,
+            Context::default(),
+            &get_config(),
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("notes.txt".to_string()));
+
+        let event = CustomEvent {
+            key: Some("a.txt".to_string()),
+            text_body: Some("Firstname".to_string()),
+            filename: Some("notes.txt".to_string()),
+            ..Default::default()
+        };
+        let output = put_text(
+            event,
+            Context::default(),
+            &get_config(),
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("a.txt".to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_put_text_suffix_on_collision() {
+        let get_event = || CustomEvent {
+            key: Some("notes/name.txt".to_string()),
+            text_body: Some("Firstname".to_string()),
+            suffix_on_collision: Some(true),
+            ..Default::default()
+        };
+        let config = get_config();
+        let uploader = MemoryUploader::default();
+        let output = put_text(get_event(), Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("notes/name.txt".to_string()));
+
+        uploader.insert("test-bucket", "notes/name-1.txt", b"Taken");
+        let output = put_text(get_event(), Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("notes/name-2.txt".to_string()));
+        assert_eq!(
+            uploader.get("test-bucket", "notes/name.txt"),
+            Some(b"Firstname".to_vec())
+        );
+        assert_eq!(
+            uploader.get("test-bucket", "notes/name-1.txt"),
+            Some(b"Taken".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_put_text_reject_when_no_free_key() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "name.txt", b"Taken");
+        for n in 1..=MAX_COLLISION_SUFFIX {
+            uploader.insert(
+                "test-bucket",
+                &add_collision_suffix("name.txt", n),
+                b"Taken",
+            );
+        }
+        let event = CustomEvent {
+            key: Some("name.txt".to_string()),
+            text_body: Some("Firstname".to_string()),
+            suffix_on_collision: Some(true),
+            ..Default::default()
+        };
+        let err = put_text(event, Context::default(), &get_config(), &uploader)
+            .await
+            .expect_err("expected Err(_) value");
+        assert!(err.to_string().starts_with(&format!(
+            "[409] {} (attempts: {})",
+            MSG_NO_FREE_KEY, MAX_COLLISION_SUFFIX
+        )));
+        assert_eq!(
+            uploader.get("test-bucket", "name.txt"),
+            Some(b"Taken".to_vec())
+        );
+    }
+
+    #[test]
+    fn can_check_key_depth() {
+        assert_eq!(check_key_depth("a/b/c/d.txt", None), Ok(()));
+        assert_eq!(check_key_depth("a/b/c.txt", Some(3)), Ok(()));
+        assert_eq!(check_key_depth("a//b/c/", Some(3)), Ok(()));
+        assert_eq!(
+            check_key_depth("a/b/c/d.txt", Some(3)),
+            Err(LambdaError::KeyTooDeep { limit: 3, depth: 4 })
+        );
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_key_over_max_depth() {
+        let config = Config {
+            max_key_depth: Some(2),
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        let put = |key: &str| CustomEvent {
+            key: Some(key.to_string()),
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let output = hello(
+            put("logs/a.txt"),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.key, Some("logs/a.txt".to_string()));
+
+        let err = hello(
+            put("logs/2020/a.txt"),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect_err("expected Err(_) value");
+        assert!(err
+            .to_string()
+            .starts_with(&format!("[400] {} (max: 2, got: 3)", MSG_KEY_TOO_DEEP)));
+        assert_eq!(uploader.get("test-bucket", "logs/2020/a.txt"), None);
+    }
+
+    #[test]
+    fn can_check_write_prefix() {
+        let config = get_config();
+        assert_eq!(check_write_prefix("logs/a.txt", &config), Ok(()));
+        let config = Config {
+            write_prefix: Some("uploads/".to_string()),
+            ..config
+        };
+        assert_eq!(check_write_prefix("uploads/a.txt", &config), Ok(()));
+        assert_eq!(
+            check_write_prefix("uploadsa.txt", &config),
+            Err(LambdaError::OutsideWritePrefix("uploadsa.txt".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_text_body_with_items() {
+        let get_event = || -> CustomEvent {
+            serde_json::from_value(serde_json::json!({
+                "textBody": "Firstname",
+                "items": [{ "key": "b.txt", "textBody": "Lastname" }]
+            }))
+            .unwrap()
+        };
+        let uploader = MemoryUploader::default();
+        let err = hello(
+            get_event(),
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect_err("expected Err(_) value");
+        assert!(err
+            .to_string()
+            .starts_with(&format!("[400] {}", MSG_AMBIGUOUS_BODY)));
+        assert!(uploader.store.lock().unwrap().is_empty());
+
+        let config = Config {
+            prefer_items: true,
+            ..get_config()
+        };
+        let output = hello(
+            get_event(),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.message, "Uploaded 1 items.");
+        assert_eq!(uploader.store.lock().unwrap().len(), 1);
+        assert_eq!(
+            uploader.get("test-bucket", "b.txt"),
+            Some(b"Lastname".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_report_mixed_batch() {
+        let event: CustomEvent = serde_json::from_value(serde_json::json!({
+            "items": [
+                { "key": "a.txt", "textBody": "Firstname" },
+                { "key": "b.txt", "textBody": "a".repeat(150) },
+            ]
+        }))
+        .unwrap();
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(
+            serde_json::to_value(&output.batch).unwrap(),
+            serde_json::json!({
+                "succeeded": [{
+                    "index": 0,
+                    "key": "a.txt",
+                    "etag": get_memory_etag(b"Firstname"),
+                }],
+                "failed": [{
+                    "index": 1,
+                    "key": "b.txt",
+                    "code": "TextBodyTooLong",
+                    "message": LambdaError::TextBodyTooLong.detail(),
+                }],
+            })
+        );
+        assert_eq!(output.message, "Uploaded 1 items.");
+    }
+
+    // a multi-threaded runtime so a handler future that is not Send, or a racy
+    // shared store, fails here instead of in production
+    #[tokio::test(threaded_scheduler, core_threads = 4)]
+    async fn can_hello_handler_handle_concurrent_invocations() {
+        let uploader = MemoryUploader::default();
+        let handles: Vec<_> = (0..50)
+            .map(|i| {
+                let uploader = uploader.clone();
+                tokio::spawn(async move {
+                    let event = CustomEvent {
+                        key: Some(format!("concurrent/{}.txt", i)),
+                        text_body: Some(format!("Firstname {}", i)),
+                        ..Default::default()
+                    };
+                    hello(
+                        event,
+                        AuthorizedContext::anonymous(Context::default()),
+                        &get_config(),
+                        &get_mock_s3(),
+                        &uploader,
+                    )
+                    .await
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.await.unwrap().is_ok());
+        }
+        assert_eq!(uploader.store.lock().unwrap().len(), 50);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_handle_empty_text_body() {
+        let event = CustomEvent {
+            text_body: None,
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert!(result.is_err());
+        if let Err(error) = result {
+            assert_eq!(error.to_string(), format!("[400] {}", MSG_EMPTY_TEXT_BODY))
+        } else {
+            // result must be Err
+            panic!()
+        }
+    }
+
+    fn get_rejection_logs(request_id: &str) -> Vec<Value> {
+        get_captured_logs(request_id)
+            .iter()
+            .filter_map(|line| line.strip_prefix("WARN "))
+            .map(|json| serde_json::from_str(json).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn can_log_cold_start() {
+        let get_context = |request_id: &str| Context {
+            request_id: request_id.to_string(),
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        log_cold_start(&mut out, true, &get_context("cold-start-first"));
+        log_cold_start(&mut out, false, &get_context("cold-start-second"));
+        // every line must be the EMF document alone for CloudWatch to extract it
+        let records: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["requestId"], "cold-start-first");
+        assert_eq!(records[0]["cold_start"], true);
+        assert_eq!(records[0]["ColdStart"], 1);
+        assert!(records[0]["_aws"]["CloudWatchMetrics"].is_array());
+        assert_eq!(records[1]["cold_start"], false);
+        assert_eq!(records[1]["ColdStart"], 0);
+    }
+
+    #[tokio::test]
+    async fn can_log_written_s3_key() {
+        capture_logs();
+        let c = Context {
+            request_id: "written-s3-key".to_string(),
+            ..Default::default()
+        };
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        put_text(event, c, &get_config(), &MemoryUploader::default())
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(
+            get_captured_logs("written-s3-key"),
+            vec![format!(
+                "INFO Wrote s3_key=\"{}\" s3_bucket=\"test-bucket\" in request written-s3-key",
+                DEFAULT_OBJECT_KEY
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_log_rejection_of_empty_text_body() {
+        capture_logs();
+        let c = Context {
+            request_id: "rejection-empty-text-body".to_string(),
+            ..Default::default()
+        };
+        let result = hello(
+            CustomEvent::default(),
+            AuthorizedContext::anonymous(c),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(
+            get_rejection_logs("rejection-empty-text-body"),
+            vec![serde_json::json!({
+                "event": "validation_rejected",
+                "rule": "empty_text_body",
+                "requestId": "rejection-empty-text-body",
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn can_log_rejection_of_text_body_too_long() {
+        capture_logs();
+        let c = Context {
+            request_id: "rejection-text-too-long".to_string(),
+            ..Default::default()
+        };
+        let event = CustomEvent {
+            text_body: Some("a".repeat(150)),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(c),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert!(result.is_err());
+        let logs = get_rejection_logs("rejection-text-too-long");
+        assert_eq!(
+            logs,
+            vec![serde_json::json!({
+                "event": "validation_rejected",
+                "rule": "text_too_long",
+                "actual_len": 150,
+                "limit": 100,
+                "requestId": "rejection-text-too-long",
+            })]
+        );
+        // only the shape of the body is logged
+        assert!(!logs[0].to_string().contains("aaaa"));
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_handle_text_body_too_long() {
+        let event = CustomEvent {
+            text_body: Some("12345678901234567890123456789012345678901234567890123456789012345678901234567890123456789012345678901".to_owned()),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert!(result.is_err());
+        if let Err(error) = result {
+            assert_eq!(
+                error.to_string(),
+                format!("[400] {}", MSG_TEXT_BODY_TOO_LONG)
+            )
+        } else {
+            // result must be Err
+            panic!()
+        }
+    }
+
+    fn gzip(text: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn can_read_text_decompress_gzip_object() {
+        let uploader = MemoryUploader::default();
+        uploader.insert_with_headers(
+            "test-bucket",
+            "test.txt",
+            &gzip("Firstname"),
+            None,
+            Some("gzip"),
+        );
+        let text = read_text(&uploader, &S3Object::new("test-bucket", "test.txt"), true)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(text.text, "Firstname");
+    }
+
+    #[tokio::test]
+    async fn can_read_text_return_plain_object() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "test.txt", b"Firstname");
+        let text = read_text(&uploader, &S3Object::new("test-bucket", "test.txt"), true)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(text.text, "Firstname");
+    }
+
+    #[tokio::test]
+    async fn can_read_text_reject_invalid_utf8() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "test.txt", b"ab\x80c");
+        let err = read_text(&uploader, &S3Object::new("test-bucket", "test.txt"), true)
+            .await
+            .expect_err("expected Err(_) value");
+        assert_eq!(
+            err.downcast::<LambdaError>().unwrap(),
+            LambdaError::InvalidEncoding("invalid byte at offset 2".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_read_text_within_size_limit() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "test.txt", b"Firstname");
+        let text = read_text_within(
+            &uploader,
+            &S3Object::new("test-bucket", "test.txt"),
+            true,
+            Some(100),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(text.text, "Firstname");
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_get_data_uri() {
+        let event = CustomEvent {
+            op: Some(OP_GET.to_string()),
+            data_uri: Some(true),
+            ..Default::default()
+        };
+        let uploader = MemoryUploader::default();
+        uploader.insert_with_headers(
+            "test-bucket",
+            DEFAULT_OBJECT_KEY,
+            b"Firstname",
+            Some("text/plain"),
+            None,
+        );
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(
+            output.data_uri,
+            Some("data:text/plain;base64,Rmlyc3RuYW1l".to_string())
+        );
+        assert_eq!(output.text, None);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_cap_data_uri_size() {
+        let event = CustomEvent {
+            op: Some(OP_GET.to_string()),
+            data_uri: Some(true),
+            allow_large: Some(true),
+            ..Default::default()
+        };
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", DEFAULT_OBJECT_KEY, b"Firstname");
+        let config = Config {
+            max_get_bytes: Some(5),
+            ..get_config()
+        };
+        let err = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect_err("expected Err(_) value");
+        assert!(err.to_string().starts_with("[413]"));
+    }
+
+    #[tokio::test]
+    async fn can_read_text_within_reject_large_object() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "test.txt", b"Firstname");
+        let err = read_text_within(
+            &uploader,
+            &S3Object::new("test-bucket", "test.txt"),
+            true,
+            Some(5),
+        )
+        .await
+        .expect_err("expected Err(_) value")
+        .downcast::<LambdaError>()
+        .unwrap();
+        assert_eq!(err, LambdaError::ObjectTooLarge { size: 9, limit: 5 });
+        assert_eq!(err.status(), 413);
+    }
+
+    #[tokio::test]
+    async fn can_transform_read_without_changing_stored_object() {
+        let uploader = MemoryUploader::default();
+        uploader.insert("test-bucket", "test.txt", b"Firstname");
+        let text = read_text(&uploader, &S3Object::new("test-bucket", "test.txt"), true)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(
+            apply_transform(Transform::Uppercase, &text.text),
+            "FIRSTNAME"
+        );
+        assert_eq!(
+            uploader.get("test-bucket", "test.txt"),
+            Some(b"Firstname".to_vec())
+        );
+    }
+
+    #[test]
+    fn can_decode_body_keep_gzip_when_decompression_disabled() {
+        let body = gzip("Firstname");
+        let decoded = decode_body(body.clone(), Some("gzip"), false).unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn can_compress_body_over_threshold() {
+        let body = "Firstname\n".repeat(100).into_bytes();
+        let (compressed, is_compressed) = compress_body(body.clone(), Some(512)).unwrap();
+        assert!(is_compressed);
+        assert!(compressed.len() < body.len());
+        assert_eq!(decode_body(compressed, Some("gzip"), true).unwrap(), body);
+
+        let (kept, is_compressed) = compress_body(body.clone(), None).unwrap();
+        assert!(!is_compressed);
+        assert_eq!(kept, body);
+    }
+
+    #[test]
+    fn can_compress_body_skip_small_and_incompressible() {
+        let (kept, is_compressed) = compress_body(b"Firstname".to_vec(), Some(512)).unwrap();
+        assert!(!is_compressed);
+        assert_eq!(kept, b"Firstname");
+
+        // xorshift output, gzip only adds its header to it
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let body: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let (kept, is_compressed) = compress_body(body.clone(), Some(512)).unwrap();
+        assert!(!is_compressed);
+        assert_eq!(kept, body);
+    }
+
+    #[tokio::test]
+    async fn can_put_text_auto_compress() {
+        let text = "Firstname\n".repeat(100);
+        let event = CustomEvent {
+            text_body: Some(text.clone()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("content-encoding"),
+                    Some(&vec![b"gzip".to_vec()])
+                );
+            }),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let config = Config {
+            auto_compress_threshold: Some(512),
+            ..get_config()
+        };
+        let uploader = get_uploader(&s3, &config, &Context::default());
+        let output = put_text(event, Context::default(), &config, &uploader)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.compressed, Some(true));
+        assert!(output.uploaded_bytes.expect("expected Some(_) value") < text.len() as u64);
+    }
+
+    /// Hands out at most `chunk_size` bytes per read.
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.chunk_size.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn can_parse_custom_event_streaming_from_partial_reads() {
+        let json = br#"{"key": "test.txt", "textBody": "Firstname Lastname"}"#;
+        for chunk_size in &[1, 3, 64] {
+            let reader = ChunkedReader {
+                data: json,
+                chunk_size: *chunk_size,
+            };
+            let event = parse_custom_event_streaming(reader).expect("expected Ok(_) value");
+            assert_eq!(event.key, Some("test.txt".to_string()));
+            assert_eq!(event.text_body, Some("Firstname Lastname".to_string()));
+        }
+    }
+
+    #[test]
+    fn can_parse_custom_event_streaming_report_errors() {
+        let result = parse_custom_event_streaming(&br#"{"textBody": 123}"#[..]);
+        assert_eq!(
+            result.expect_err("expected Err(_) value"),
+            LambdaError::MalformedEvent(
+                "textBody: invalid type: integer `123`, expected a string".to_string()
+            )
+        );
+        let result = parse_custom_event_streaming(&br#"{"key": "a.txt"} {}"#[..]);
+        assert!(matches!(result, Err(LambdaError::MalformedEvent(_))));
+    }
+
+    #[test]
+    fn can_extract_eventbridge_payload() {
+        let event = serde_json::json!({
+            "version": "0",
+            "detail-type": "Text Submitted",
+            "source": "com.example.upstream",
+            "detail": { "key": "a.txt", "textBody": "Firstname" }
+        });
+        let event = extract_payload(event, Some("detail"))
+            .and_then(parse_event)
+            .expect("expected Ok(_) value");
+        assert_eq!(event.key, Some("a.txt".to_string()));
+        assert_eq!(event.text_body, Some("Firstname".to_string()));
+
+        let event = serde_json::json!({ "detail": { "payload": { "textBody": "Firstname" } } });
+        let event = extract_payload(event, Some("detail.payload"))
+            .and_then(parse_event)
+            .expect("expected Ok(_) value");
+        assert_eq!(event.text_body, Some("Firstname".to_string()));
+    }
+
+    #[test]
+    fn can_extract_payload_use_top_level_without_path() {
+        let event = serde_json::json!({ "textBody": "Firstname" });
+        assert_eq!(extract_payload(event.clone(), None), Ok(event));
+    }
+
+    #[test]
+    fn can_extract_payload_reject_missing_path() {
+        let event = serde_json::json!({ "detail": "Firstname" });
+        assert_eq!(
+            extract_payload(event, Some("detail.payload")),
+            Err(LambdaError::MalformedEvent(
+                "no payload at detail.payload".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn can_parse_event_report_field_of_type_mismatch() {
+        let result = parse_event(serde_json::json!({ "textBody": 123 }));
+        assert_eq!(
+            result.expect_err("expected Err(_) value"),
+            LambdaError::MalformedEvent(
+                "textBody: invalid type: integer `123`, expected a string".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn can_parse_event_reject_wrong_case_field() {
+        let result = parse_event(serde_json::json!({ "textbody": "Firstname" }));
+        match result.expect_err("expected Err(_) value") {
+            LambdaError::UnknownFields(detail) => assert!(detail.contains("`textbody`")),
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn can_parse_event_reject_extra_field() {
+        let result = parse_event(serde_json::json!({ "textBody": "Firstname", "extra": 1 }));
+        let err = result.expect_err("expected Err(_) value");
+        assert_eq!(err.status(), 400);
+        assert!(err.to_string().contains("`extra`"));
+    }
+
+    #[test]
+    fn can_convert_anyhow_error_into_lambda_error() {
+        // what lets `main` use `?` on both without a map_err
+        fn propagate(result: Result<()>) -> std::result::Result<(), lambda::Error> {
+            result?;
+            Ok(())
+        }
+        let err = propagate(Err(anyhow!("S3 connectivity check failed")))
+            .expect_err("expected Err(_) value");
+        assert_eq!(err.to_string(), "S3 connectivity check failed");
+        assert!(propagate(Ok(())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn can_handle_event_unwrap_payload_path() {
+        let config = Config {
+            event_payload_path: Some("detail".to_string()),
+            ..get_config()
+        };
+        let output = handle_event(
+            serde_json::json!({ "source": "com.example.upstream", "detail": { "textBody": "Firstname" } }),
+            Context::default(),
+            &config,
+            &get_mock_s3(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.etag, Some(MOCK_ETAG.to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_handle_event_reject_unknown_fields() {
+        let result = handle_event(
+            serde_json::json!({ "textbody": "Firstname" }),
+            Context::default(),
+            &get_config(),
+            &get_mock_s3(),
+        )
+        .await;
+        let message = result.expect_err("expected Err(_) value").to_string();
+        assert!(message.starts_with(&format!("[400] {}", MSG_UNKNOWN_FIELDS)));
+        assert!(message.contains("`textbody`"));
+    }
+
+    #[tokio::test]
+    async fn can_handle_event_reject_malformed_event() {
+        let result = handle_event(
+            serde_json::json!({ "textBody": 123 }),
+            Context::default(),
+            &get_config(),
+            &get_mock_s3(),
+        )
+        .await;
+        let message = result.expect_err("expected Err(_) value").to_string();
+        assert!(message.starts_with(&format!("[400] {}: textBody", MSG_MALFORMED_EVENT)));
+    }
+
+    fn get_proxy_event(body: &str) -> Value {
+        serde_json::json!({
+            "resource": "/",
+            "path": "/",
+            "httpMethod": "POST",
+            "headers": { "Content-Type": "application/json" },
+            "multiValueHeaders": { "Content-Type": ["application/json"] },
+            "queryStringParameters": null,
+            "multiValueQueryStringParameters": null,
+            "pathParameters": null,
+            "stageVariables": null,
+            "requestContext": {
+                "accountId": "123456789012",
+                "resourceId": "us4z18",
+                "stage": "dev",
+                "requestId": "41b45ea3-70b5-11e6-b7bd-69b5aaebc7d9",
+                "identity": {
+                    "sourceIp": "192.168.100.1",
+                    "userAgent": "curl/7.64.1"
+                },
+                "resourcePath": "/",
+                "httpMethod": "POST",
+                "apiId": "wt6mne2s9k"
+            },
+            "body": body,
+            "isBase64Encoded": false
+        })
+    }
+
+    #[tokio::test]
+    async fn can_handle_invocation_return_proxy_response() {
+        let response = handle_invocation(
+            get_proxy_event(r#"{"textBody":"Firstname"}"#),
+            Context::default(),
+            &get_config(),
+            &get_mock_s3(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(response["statusCode"], 200);
+        assert_eq!(response["headers"]["Content-Type"], "application/json");
+        let body: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        assert_eq!(body["etag"], MOCK_ETAG);
+    }
+
+    #[tokio::test]
+    async fn can_handle_invocation_return_problem_json_proxy_response() {
+        let config = Config {
+            error_format: ErrorFormat::ProblemJson,
+            ..get_config()
+        };
+        let response = handle_invocation(
+            get_proxy_event(r#"{"textBody":""}"#),
+            Context::default(),
+            &config,
+            &get_mock_s3(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(response["statusCode"], 400);
+        assert_eq!(
+            response["headers"]["Content-Type"],
+            "application/problem+json"
+        );
+        let problem: Value = serde_json::from_str(response["body"].as_str().unwrap()).unwrap();
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["detail"], MSG_EMPTY_TEXT_BODY);
+    }
+
+    #[tokio::test]
+    async fn can_handle_invocation_render_direct_errors() {
+        let config = Config {
+            error_format: ErrorFormat::Json,
+            ..get_config()
+        };
+        let result = handle_invocation(
+            serde_json::json!({ "textBody": "" }),
+            Context::default(),
+            &config,
+            &get_mock_s3(),
+        )
+        .await;
+        let message = result.expect_err("expected Err(_) value").to_string();
+        assert_eq!(
+            serde_json::from_str::<Value>(&message).unwrap(),
+            serde_json::json!({
+                "error": "EmptyTextBody",
+                "message": MSG_EMPTY_TEXT_BODY,
+                "code": 400,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn can_handle_invocation_keep_batch_item_codes_in_problem_json() {
+        let config = Config {
+            error_format: ErrorFormat::ProblemJson,
+            ..get_config()
+        };
+        let output = handle_invocation(
+            serde_json::json!({ "items": [{ "key": "b.txt", "textBody": "a".repeat(150) }] }),
+            Context::default(),
+            &config,
+            &get_mock_s3(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output["failed"][0]["code"], "TextBodyTooLong");
+        assert_eq!(
+            output["failed"][0]["message"],
+            LambdaError::TextBodyTooLong.detail()
+        );
+    }
+
+    #[tokio::test]
+    async fn can_handle_invocation_keep_direct_errors() {
+        let result = handle_invocation(
+            serde_json::json!({ "textBody": 123 }),
+            Context::default(),
+            &get_config(),
+            &get_mock_s3(),
+        )
+        .await;
+        let message = result.expect_err("expected Err(_) value").to_string();
+        assert!(message.starts_with("[400] "));
+    }
+
+    #[test]
+    fn can_check_raw_event_size() {
+        let event = serde_json::json!({ "textBody": "Firstname" });
+        assert_eq!(check_raw_event_size(&event, 24), Ok(()));
+        assert_eq!(
+            check_raw_event_size(&event, 23),
+            Err(LambdaError::EventTooLarge { limit: 23 })
+        );
+    }
+
+    #[tokio::test]
+    async fn can_handle_event_reject_oversized_event() {
+        let config = Config {
+            max_raw_event_bytes: 1024,
+            ..get_config()
+        };
+        // the unknown field would be reported if the event got as far as serde
+        let result = handle_event(
+            serde_json::json!({ "textBody": "a".repeat(2048), "extra": 1 }),
+            Context::default(),
+            &config,
+            &get_mock_s3(),
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[413] {} (limit 1024 bytes)", MSG_EVENT_TOO_LARGE)
+        );
+    }
+
+    #[tokio::test]
+    async fn can_handle_event_handle_valid_event() {
+        let result = handle_event(
+            serde_json::json!({ "textBody": "Firstname" }),
+            Context::default(),
+            &get_config(),
+            &get_mock_s3(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn can_allow_ops_in_allow_list() {
+        assert!(is_op_allowed(OP_GET, Some("get, inventory".to_string())));
+        assert!(is_op_allowed(
+            OP_INVENTORY,
+            Some("get, inventory".to_string())
+        ));
+        assert!(!is_op_allowed(OP_PUT, Some("get, inventory".to_string())));
+    }
+
+    #[test]
+    fn can_allow_every_op_without_allow_list() {
+        assert!(is_op_allowed(OP_PUT, None));
+        assert!(is_op_allowed(OP_GET, Some("".to_string())));
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_move_without_destination() {
+        let event = CustomEvent {
+            op: Some(OP_MOVE.to_string()),
+            key: Some("a.txt".to_string()),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[400] {}", MSG_MISSING_DESTINATION_KEY)
+        )
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_unknown_operation() {
+        let event = CustomEvent {
+            op: Some("explode".to_string()),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[400] {}", MSG_UNKNOWN_OPERATION)
+        )
+    }
+
+    #[test]
+    fn can_normalize_text_trim_leading_spaces() {
+        assert_eq!(normalize_text("   Firstname \n"), "Firstname");
+    }
+
+    #[test]
+    fn can_normalize_text_convert_windows_newlines() {
+        assert_eq!(normalize_text("first\r\nsecond\r\n"), "first\nsecond");
+    }
+
+    #[test]
+    fn can_normalize_text_borrow_clean_text() {
+        match normalize_text("first\nsecond") {
+            Cow::Borrowed(text) => assert_eq!(text, "first\nsecond"),
+            Cow::Owned(_) => panic!("clean text must not be reallocated"),
+        }
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_check_length_of_normalized_text() {
+        let event = CustomEvent {
+            text_body: Some(format!("  {}  \r\n", "a".repeat(100))),
+            ..Default::default()
+        };
+        assert!(hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default()
+        )
+        .await
+        .is_ok());
+    }
+
+    #[test]
+    fn can_resolve_empty_text_body_to_zero_byte_object_when_allowed() {
+        let text = resolve_text_body(None, true).expect("expected Some(_) value");
+        assert_eq!(text.into_bytes().len(), 0);
+    }
+
+    #[test]
+    fn can_resolve_empty_text_body_reject_by_default() {
+        assert_eq!(resolve_text_body(None, false), None);
+        assert_eq!(
+            resolve_text_body(Some("Firstname".to_string()), false),
+            Some("Firstname".to_string())
+        );
+    }
+
+    #[test]
+    fn can_apply_default_body() {
+        assert_eq!(
+            apply_default_body(None, Some("TODO")),
+            (Some("TODO".to_string()), true)
+        );
+        assert_eq!(
+            apply_default_body(Some(String::new()), Some("TODO")),
+            (Some("TODO".to_string()), true)
+        );
+        assert_eq!(
+            apply_default_body(Some("Firstname".to_string()), Some("TODO")),
+            (Some("Firstname".to_string()), false)
+        );
+        assert_eq!(apply_default_body(None, None), (None, false));
+    }
+
+    #[cfg(feature = "sentry-integration")]
+    #[test]
+    fn can_hello_report_error_to_sentry() {
+        // the captured events are bound to this thread, so no worker threads
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap();
+        let events = sentry::test::with_captured_events(|| {
+            for op in &["unknown", OP_DIAG] {
+                let event = CustomEvent {
+                    op: Some(op.to_string()),
+                    ..Default::default()
+                };
+                let _ = runtime.block_on(hello(
+                    event,
+                    AuthorizedContext::anonymous(Context::default()),
+                    &get_config(),
+                    &get_mock_s3(),
+                    &MemoryUploader::default(),
+                ));
+            }
+        });
+        assert_eq!(events.len(), 1);
+        let message = events[0].exception.values[0].value.as_deref();
+        assert!(message
+            .expect("expected Some(_) value")
+            .contains(MSG_UNKNOWN_OPERATION));
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_count_bytes_written() {
+        let uploader = MemoryUploader::default();
+        let before = TOTAL_BYTES_WRITTEN.get();
+        for text_body in &["Firstname", "Lastname"] {
+            let event = CustomEvent {
+                text_body: Some(text_body.to_string()),
+                ..Default::default()
+            };
+            hello(
+                event,
+                AuthorizedContext::anonymous(Context::default()),
+                &get_config(),
+                &get_mock_s3(),
+                &uploader,
+            )
+            .await
+            .expect("expected Ok(_) value");
+        }
+        // other tests upload concurrently, so the total can only be bounded from below
+        let event = CustomEvent {
+            op: Some(OP_DIAG.to_string()),
+            ..Default::default()
+        };
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        let total = output.total_bytes_written.expect("expected Some(_) value");
+        assert!(total >= before + 17);
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_write_default_body() {
+        let config = Config {
+            default_body: Some("# TODO".to_string()),
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        let output = hello(
+            CustomEvent::default(),
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(output.default_body_used, Some(true));
+        assert_eq!(
+            uploader.get("test-bucket", DEFAULT_OBJECT_KEY),
+            Some(b"# TODO".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_missing_body_without_default() {
+        let uploader = MemoryUploader::default();
+        let result = hello(
+            CustomEvent::default(),
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[400] {}", MSG_EMPTY_TEXT_BODY)
+        );
+        assert!(uploader.store.lock().unwrap().is_empty());
+    }
+
+    fn lambda_env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let mut vars: std::collections::HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        vars.insert(
+            "AWS_EXECUTION_ENV".to_string(),
+            "AWS_Lambda_rust".to_string(),
+        );
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn can_detect_lambda_invocation() {
+        assert!(!is_local_env(lambda_env(&[])));
+        assert!(!is_local_env(lambda_env(&[(LOCAL_KEY, "")])));
+        assert!(!is_local_env(lambda_env(&[(SAM_LOCAL_KEY, "false")])));
+    }
+
+    #[test]
+    fn can_detect_container_without_local_signals() {
+        // neither Lambda nor local, e.g. ECS or a CI runner
+        assert!(!is_local_env(|_: &str| None));
+        assert!(!is_local_env(|key: &str| {
+            if key == "AWS_EXECUTION_ENV" {
+                Some("AWS_ECS_FARGATE".to_string())
+            } else {
+                None
+            }
+        }));
+    }
+
+    #[test]
+    fn can_detect_each_local_condition() {
+        assert!(is_local_env(lambda_env(&[(LOCAL_KEY, "1")])));
+        assert!(is_local_env(lambda_env(&[(SAM_LOCAL_KEY, "true")])));
+        assert!(is_local_env(lambda_env(&[(LOCALSTACK_HOSTNAME_KEY, "")])));
+    }
+
+    #[test]
+    fn can_detect_combined_local_conditions() {
+        assert!(is_local_env(lambda_env(&[
+            (LOCAL_KEY, "1"),
+            (SAM_LOCAL_KEY, "true"),
+        ])));
+        assert!(is_local_env(lambda_env(&[
+            (LOCAL_KEY, ""),
+            (LOCALSTACK_HOSTNAME_KEY, "localstack"),
+        ])));
+        assert!(is_local_env(|key: &str| {
+            if key == SAM_LOCAL_KEY {
+                Some("true".to_string())
+            } else {
+                None
+            }
+        }));
+    }
+
+    #[test]
+    fn can_normalize_key_casing() {
+        assert_eq!(normalize_key("Logs/Test.TXT", true, None), "logs/test.txt");
+        assert_eq!(normalize_key("Logs/Test.TXT", false, None), "Logs/Test.TXT");
+    }
+
+    #[test]
+    fn can_normalize_key_lowercase_before_prefix() {
+        assert_eq!(
+            normalize_key("Test.TXT", true, Some("Uploads/")),
+            "Uploads/test.txt"
+        );
+        assert_eq!(
+            normalize_key("Test.TXT", false, Some("Uploads/")),
+            "Uploads/Test.TXT"
+        );
+        assert_eq!(normalize_key("Test.TXT", true, Some("")), "test.txt");
+    }
+
+    #[test]
+    fn can_namespace_key_by_function() {
+        let config = Config {
+            key_prefix: Some("Uploads/".to_string()),
+            function_namespace: Some("Hello".to_string()),
+            lowercase_keys: true,
+            ..get_config()
+        };
+        assert_eq!(
+            resolve_object_key(Some("Test.TXT".to_string()), &config),
+            "Uploads/Hello/test.txt"
+        );
+        let config = Config {
+            function_namespace: Some("hello".to_string()),
+            ..get_config()
+        };
+        assert_eq!(resolve_object_key(None, &config), "hello/test.txt");
+    }
+
+    #[test]
+    fn can_validate_website_redirect() {
+        assert!(is_valid_website_redirect("/docs/index.html"));
+        assert!(is_valid_website_redirect("https://example.com/docs/"));
+        assert!(!is_valid_website_redirect("docs/index.html"));
+        assert!(!is_valid_website_redirect("https://"));
+        assert!(!is_valid_website_redirect("ftp://example.com/"));
+        assert!(!is_valid_website_redirect("/docs\n"));
+    }
+
+    #[test]
+    fn can_validate_content_language() {
+        assert!(is_valid_content_language("ja"));
+        assert!(is_valid_content_language("en-US"));
+        assert!(is_valid_content_language("es-419"));
+        assert!(!is_valid_content_language("english"));
+        assert!(!is_valid_content_language("en-"));
+        assert!(!is_valid_content_language("en_US"));
+        assert!(!is_valid_content_language("zh-Hant-TW"));
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_forward_content_language() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            content_language: Some("en-US".to_string()),
+            ..Default::default()
+        };
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("content-language"),
+                    Some(&vec![b"en-US".to_vec()])
+                );
+            }),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let uploader = S3Uploader::new(s3.clone(), 0, None);
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &s3,
+            &uploader,
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    /// Fails every upload to `failing_bucket` and stores the rest.
+    struct FailingBucketUploader {
+        inner: MemoryUploader,
+        failing_bucket: &'static str,
+    }
+
+    #[async_trait::async_trait]
+    impl Uploader for FailingBucketUploader {
+        async fn upload(&self, req: UploadParams) -> Result<uploader::UploadReceipt> {
+            if req.bucket == self.failing_bucket {
+                return Err(anyhow!("S3 is down"));
+            }
+            self.inner.upload(req).await
+        }
+
+        async fn etag(&self, object: &S3Object) -> Result<Option<String>> {
+            self.inner.etag(object).await
+        }
+
+        async fn download(&self, object: &S3Object) -> Result<Option<Vec<u8>>> {
+            self.inner.download(object).await
+        }
+
+        async fn fetch(&self, object: &S3Object) -> Result<uploader::StoredObject> {
+            self.inner.fetch(object).await
+        }
+
+        async fn size(&self, object: &S3Object) -> Result<u64> {
+            self.inner.size(object).await
+        }
+
+        async fn list(&self, bucket: &str, prefix: Option<&str>) -> Result<Vec<rusoto_s3::Object>> {
+            self.inner.list(bucket, prefix).await
+        }
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_write_dead_letter() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            api_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            dlq_bucket: Some("dlq-bucket".to_string()),
+            ..get_config()
+        };
+        let uploader = FailingBucketUploader {
+            inner: MemoryUploader::default(),
+            failing_bucket: "test-bucket",
+        };
+        let c = Context {
+            request_id: "dead-letter".to_string(),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(c),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            "S3 is down"
+        );
+        let store = uploader.inner.store.lock().unwrap();
+        assert_eq!(store.len(), 1);
+        let (key, body) = store.iter().next().unwrap();
+        assert!(key.starts_with("dlq-bucket/_dlq/"));
+        assert!(key.ends_with(".json"));
+        let record: Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(record["requestId"], "dead-letter");
+        assert_eq!(record["event"]["textBody"], "Firstname");
+        assert_eq!(record["event"].get("apiKey"), None);
+        assert_eq!(record["error"], "S3 is down");
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_generate_key() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            key_strategy: KeyStrategy::ContentHash,
+            ..get_config()
+        };
+        let uploader = MemoryUploader::default();
+        let output = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &config,
+            &get_mock_s3(),
+            &uploader,
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(
+            output.key,
+            Some("04176f095283bc729f1e3926967e7034.txt".to_string())
+        );
+        assert!(uploader.get("test-bucket", DEFAULT_OBJECT_KEY).is_none());
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_invalid_expiry() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            expires_in_seconds: Some(0),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[400] {}", MSG_INVALID_EXPIRY)
+        )
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_invalid_content_language() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            content_language: Some("english".to_string()),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[400] {}", MSG_INVALID_CONTENT_LANGUAGE)
+        )
+    }
+
+    #[tokio::test]
+    async fn can_hello_handler_reject_invalid_website_redirect() {
+        let event = CustomEvent {
+            text_body: Some("Firstname".to_string()),
+            website_redirect: Some("docs/index.html".to_string()),
+            ..Default::default()
+        };
+        let result = hello(
+            event,
+            AuthorizedContext::anonymous(Context::default()),
+            &get_config(),
+            &get_mock_s3(),
+            &MemoryUploader::default(),
+        )
+        .await;
+        assert_eq!(
+            result.expect_err("expected Err(_) value").to_string(),
+            format!("[400] {}", MSG_INVALID_WEBSITE_REDIRECT)
+        )
+    }
+
+    #[test]
+    fn can_resolve_requested_cache_control() {
+        let result = resolve_cache_control(
+            Some("public, max-age=3600".to_string()),
+            Some("no-cache".to_string()),
+        );
+        assert_eq!(result, Ok(Some("public, max-age=3600".to_string())));
+    }
+
+    #[test]
+    fn can_resolve_default_cache_control() {
+        let result = resolve_cache_control(None, Some("no-cache".to_string()));
+        assert_eq!(result, Ok(Some("no-cache".to_string())));
+    }
+
+    #[test]
+    fn can_resolve_no_cache_control() {
+        assert_eq!(resolve_cache_control(None, None), Ok(None));
+        assert_eq!(resolve_cache_control(None, Some("".to_string())), Ok(None));
+    }
+
+    #[test]
+    fn can_reject_invalid_cache_control() {
+        for value in &["max-age=", "public,,private", "max age=10", "no-cache\n"] {
+            assert_eq!(
+                resolve_cache_control(Some(value.to_string()), None),
+                Err(LambdaError::InvalidCacheControl)
+            );
+        }
+    }
+
+    #[test]
+    fn can_resolve_allowed_content_type() {
+        let result = resolve_content_type(
+            Some("application/json".to_string()),
+            Some("text/plain, application/json".to_string()),
+        );
+        assert_eq!(result, Ok("application/json".to_string()));
+        // the default matches on its media type, not the charset parameter
+        let result = resolve_content_type(None, Some("text/plain".to_string()));
+        assert_eq!(result, Ok(DEFAULT_CONTENT_TYPE.to_string()));
+    }
+
+    #[test]
+    fn can_reject_disallowed_content_type() {
+        let result = resolve_content_type(
+            Some("text/html".to_string()),
+            Some("text/plain,application/json".to_string()),
+        );
+        let err = result.expect_err("expected Err(_) value");
+        assert_eq!(
+            err,
+            LambdaError::UnsupportedContentType("text/html".to_string())
+        );
+        assert_eq!(err.status(), 415);
+        assert_eq!(err.to_string(), "[415] Unsupported content type: text/html");
+    }
+
+    #[test]
+    fn can_allow_any_content_type_without_allow_list() {
+        for allowed in vec![None, Some("".to_string())] {
+            let result = resolve_content_type(Some("image/png".to_string()), allowed);
+            assert_eq!(result, Ok("image/png".to_string()));
+        }
+        assert_eq!(
+            resolve_content_type(None, None),
+            Ok(DEFAULT_CONTENT_TYPE.to_string())
+        );
+    }
+
+    #[test]
+    fn can_lambda_error_serialize_to_structured_json() {
+        let cases = vec![
+            (
+                LambdaError::EmptyTextBody,
+                "EmptyTextBody",
+                MSG_EMPTY_TEXT_BODY.to_string(),
+            ),
+            (
+                LambdaError::TextBodyTooLong,
+                "TextBodyTooLong",
+                MSG_TEXT_BODY_TOO_LONG.to_string(),
+            ),
+            (
+                LambdaError::InvalidCacheControl,
+                "InvalidCacheControl",
+                MSG_INVALID_CACHE_CONTROL.to_string(),
+            ),
+            (
+                LambdaError::UnknownOperation,
+                "UnknownOperation",
+                MSG_UNKNOWN_OPERATION.to_string(),
+            ),
+            (
+                LambdaError::MalformedEvent("textBody: oops".to_string()),
+                "MalformedEvent",
+                format!("{}: textBody: oops", MSG_MALFORMED_EVENT),
+            ),
+            (
+                LambdaError::UnknownFields("unknown field `oops`".to_string()),
+                "UnknownFields",
+                format!("{}: unknown field `oops`", MSG_UNKNOWN_FIELDS),
+            ),
+            (
+                LambdaError::UnsupportedContentType("text/html".to_string()),
+                "UnsupportedContentType",
+                format!("{}: text/html", MSG_UNSUPPORTED_CONTENT_TYPE),
+            ),
+            (
+                LambdaError::BatchTooLarge {
+                    limit: 25,
+                    count: 26,
+                },
+                "BatchTooLarge",
+                format!("{} (max: 25, got: 26)", MSG_BATCH_TOO_LARGE),
+            ),
+            (
+                LambdaError::ObjectExists,
+                "ObjectExists",
+                MSG_OBJECT_EXISTS.to_string(),
+            ),
+            (
+                LambdaError::PreconditionFailed,
+                "PreconditionFailed",
+                MSG_PRECONDITION_FAILED.to_string(),
+            ),
+            (
+                LambdaError::OpNotAllowed("get".to_string()),
+                "OpNotAllowed",
+                format!("{}: get", MSG_OP_NOT_ALLOWED),
+            ),
+            (
+                LambdaError::UpstreamTimeout { service: "S3" },
+                "UpstreamTimeout",
+                format!("{}: S3", MSG_UPSTREAM_TIMEOUT),
+            ),
+            (
+                LambdaError::MissingDestinationKey,
+                "MissingDestinationKey",
+                MSG_MISSING_DESTINATION_KEY.to_string(),
+            ),
+            (
+                LambdaError::InvalidWebsiteRedirect,
+                "InvalidWebsiteRedirect",
+                MSG_INVALID_WEBSITE_REDIRECT.to_string(),
+            ),
+            (
+                LambdaError::InsufficientTime { remaining_ms: 200 },
+                "InsufficientTime",
+                format!("{} (200ms left)", MSG_INSUFFICIENT_TIME),
+            ),
+            (
+                LambdaError::KeyReused,
+                "KeyReused",
+                MSG_KEY_REUSED.to_string(),
+            ),
+            (
+                LambdaError::ObjectTooLarge {
+                    size: 20,
+                    limit: 10,
+                },
+                "ObjectTooLarge",
+                format!("{} (20 bytes, max: 10)", MSG_OBJECT_TOO_LARGE),
+            ),
+            (
+                LambdaError::UnknownTransform("reverse".to_string()),
+                "UnknownTransform",
+                format!("{}: reverse", MSG_UNKNOWN_TRANSFORM),
+            ),
+            (
+                LambdaError::InvalidContentLanguage,
+                "InvalidContentLanguage",
+                MSG_INVALID_CONTENT_LANGUAGE.to_string(),
+            ),
+            (
+                LambdaError::Unauthorized,
+                "Unauthorized",
+                MSG_UNAUTHORIZED.to_string(),
+            ),
+            (
+                LambdaError::EventTooLarge { limit: 1024 },
+                "EventTooLarge",
+                format!("{} (limit 1024 bytes)", MSG_EVENT_TOO_LARGE),
+            ),
+            (
+                LambdaError::InvalidExpiry,
+                "InvalidExpiry",
+                MSG_INVALID_EXPIRY.to_string(),
+            ),
+            (
+                LambdaError::InvalidEncoding("invalid byte at offset 2".to_string()),
+                "InvalidEncoding",
+                format!("{}: invalid byte at offset 2", MSG_INVALID_ENCODING),
+            ),
+            (
+                LambdaError::UnsupportedEncryption("aws:kms:dsse".to_string()),
+                "UnsupportedEncryption",
+                format!("{}: aws:kms:dsse", MSG_UNSUPPORTED_ENCRYPTION),
+            ),
+            (
+                LambdaError::MissingKmsKey,
+                "MissingKmsKey",
+                MSG_MISSING_KMS_KEY.to_string(),
+            ),
+            (
+                LambdaError::AmbiguousBody,
+                "AmbiguousBody",
+                MSG_AMBIGUOUS_BODY.to_string(),
+            ),
+            (
+                LambdaError::OutsideWritePrefix("logs/a.txt".to_string()),
+                "OutsideWritePrefix",
+                format!("{}: logs/a.txt", MSG_OUTSIDE_WRITE_PREFIX),
+            ),
+            (
+                LambdaError::KeyTooDeep { limit: 2, depth: 3 },
+                "KeyTooDeep",
+                format!("{} (max: 2, got: 3)", MSG_KEY_TOO_DEEP),
+            ),
+            (
+                LambdaError::NoFreeKey { attempts: 3 },
+                "NoFreeKey",
+                format!("{} (attempts: 3)", MSG_NO_FREE_KEY),
+            ),
+            (
+                LambdaError::ShuttingDown,
+                "ShuttingDown",
+                MSG_SHUTTING_DOWN.to_string(),
+            ),
+        ];
+        for (err, name, message) in cases {
+            assert_eq!(
+                serde_json::to_value(&err).unwrap(),
+                serde_json::json!({ "error": name, "message": message, "code": err.status() })
+            );
+        }
+    }
+
+    #[test]
+    fn can_empty_text_body_error_convert_to_problem_json() {
+        let problem = LambdaError::EmptyTextBody.to_problem_details("request-1");
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            serde_json::json!({
+                "type": format!("{}empty-text-body", PROBLEM_TYPE_BASE),
+                "title": "Bad Request",
+                "status": 400,
+                "detail": MSG_EMPTY_TEXT_BODY,
+                "instance": "request-1",
+            })
+        )
+    }
+
+    #[test]
+    fn can_text_body_too_long_error_convert_to_problem_json() {
+        let problem = LambdaError::TextBodyTooLong.to_problem_details("request-2");
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            serde_json::json!({
+                "type": format!("{}text-body-too-long", PROBLEM_TYPE_BASE),
+                "title": "Bad Request",
+                "status": 400,
+                "detail": MSG_TEXT_BODY_TOO_LONG,
+                "instance": "request-2",
+            })
+        )
+    }
+
+    fn get_dlq_event(records: &[(&str, &str)]) -> SqsEvent {
+        let records: Vec<Value> = records
+            .iter()
+            .map(|(message_id, body)| {
+                serde_json::json!({
+                    "messageId": message_id,
+                    "body": body,
+                    "attributes": { "SentTimestamp": "1605500000000" },
+                    "messageAttributes": {
+                        "RequestID": { "stringValue": "c6af9ac6", "dataType": "String" },
+                        "ErrorCode": { "stringValue": "200", "dataType": "Number" },
+                        "ErrorMessage": { "stringValue": "Task timed out", "dataType": "String" }
+                    }
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({ "Records": records })).unwrap()
+    }
+
+    fn get_failed_ids(response: &SqsBatchResponse) -> Vec<&str> {
+        response
+            .batch_item_failures
+            .iter()
+            .map(|failure| failure.item_identifier.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn can_replay_handler_resubmit_dlq_events() {
+        let event = get_dlq_event(&[
+            ("message-1", r#"{"textBody":"Firstname"}"#),
+            ("message-2", r#"{"textBody":"Lastname"}"#),
+        ]);
+        let response = replay(event, Context::default(), &get_config(), &get_mock_s3())
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(response, SqsBatchResponse::default());
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({ "batchItemFailures": [] })
+        );
+    }
+
+    // one put per message, so retries would take the next message's response
+    fn get_replay_config() -> Config {
+        Config {
+            max_retries: 0,
+            ..get_config()
+        }
+    }
+
+    fn get_failing_s3() -> S3Client {
+        S3Client::new_with(
+            S3MockResponseBuilder::put_object_error("InternalError", "We encountered an error"),
+            MockCredentialsProvider,
+            Default::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn can_replay_handler_report_failed_messages() {
+        let event = get_dlq_event(&[
+            ("message-1", r#"{"textBody":"Firstname"}"#),
+            ("message-2", r#"{"textBody":"Middlename"}"#),
+            ("message-3", r#"{"textBody":"Lastname"}"#),
+        ]);
+        let s3 = S3Client::new_with(
+            MultipleMockRequestDispatcher::new(vec![
+                S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+                S3MockResponseBuilder::put_object_error("InternalError", "We encountered an error"),
+                S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+            ]),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let response = replay(event, Context::default(), &get_replay_config(), &s3)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(get_failed_ids(&response), vec!["message-2"]);
+    }
+
+    #[tokio::test]
+    async fn can_replay_handler_report_every_message_failed() {
+        let event = get_dlq_event(&[
+            ("message-1", r#"{"textBody":"Firstname"}"#),
+            ("message-2", r#"{"textBody":"Lastname"}"#),
+        ]);
+        let response = replay(
+            event,
+            Context::default(),
+            &get_replay_config(),
+            &get_failing_s3(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        assert_eq!(get_failed_ids(&response), vec!["message-1", "message-2"]);
+    }
+
+    #[tokio::test]
+    async fn can_replay_handler_drop_permanent_failures() {
+        let event = get_dlq_event(&[
+            ("message-1", "{}"),
+            ("message-2", "not json"),
+            (
+                "message-3",
+                &format!(r#"{{"textBody":"{}"}}"#, "a".repeat(101)),
+            ),
+            ("message-4", r#"{"textBody":"Lastname"}"#),
+        ]);
+        let response = replay(
+            event,
+            Context::default(),
+            &get_replay_config(),
+            &get_failing_s3(),
+        )
+        .await
+        .expect("expected Ok(_) value");
+        // only the S3 error can pass on a later delivery
+        assert_eq!(get_failed_ids(&response), vec!["message-4"]);
+    }
+
+    #[test]
+    fn can_classify_permanent_errors_by_type() {
+        assert!(is_permanent_error(&anyhow!(LambdaError::EmptyTextBody)));
+        assert!(!is_permanent_error(&anyhow!(LambdaError::ShuttingDown)));
+        // only a LambdaError is classified, an S3 message that looks like one is retried
+        let err = anyhow!(get_err_msg(404, "Not Found"));
+        assert!(!is_permanent_error(&err));
+        let err = anyhow!("{}", r#"{"code":400,"message":"from S3"}"#);
+        assert!(!is_permanent_error(&err));
+        let err = anyhow!("Request ID: None Body: <Error><Code>InternalError</Code></Error>");
+        assert!(!is_permanent_error(&err));
+    }
 
     #[test]
     fn can_hoge_function_return_correct_string() {
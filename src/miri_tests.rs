@@ -0,0 +1,56 @@
+//! Tests for pure functions only, so they can run under Miri, which can't
+//! interpret tokio or the TLS code behind the S3 client.
+//! Run with `cargo +nightly miri test --features miri miri_tests`.
+
+use std::borrow::Cow;
+
+use crate::{
+    check_batch_size, get_media_type, is_op_allowed, is_valid_cache_control,
+    is_valid_website_redirect, normalize_key, normalize_text, resolve_content_type,
+    resolve_text_body, DEFAULT_CONTENT_TYPE,
+};
+
+#[test]
+fn can_normalize_text() {
+    assert_eq!(normalize_text("  first\r\nsecond \n"), "first\nsecond");
+    match normalize_text("first\nsecond") {
+        Cow::Borrowed(text) => assert_eq!(text, "first\nsecond"),
+        Cow::Owned(_) => panic!("clean text must not be reallocated"),
+    }
+    // multi-byte characters at both ends must not be split by the trim
+    assert_eq!(normalize_text(" \u{3042}\u{3044} "), "\u{3042}\u{3044}");
+}
+
+#[test]
+fn can_resolve_text_body() {
+    assert_eq!(resolve_text_body(None, true), Some(String::new()));
+    assert_eq!(resolve_text_body(None, false), None);
+}
+
+#[test]
+fn can_normalize_key() {
+    assert_eq!(
+        normalize_key("\u{00C4}/Test.TXT", true, Some("Uploads/")),
+        "Uploads/\u{00E4}/test.txt"
+    );
+}
+
+#[test]
+fn can_validate_headers() {
+    assert!(is_valid_cache_control("public, max-age=3600"));
+    assert!(!is_valid_cache_control("max-age="));
+    assert!(is_valid_website_redirect("/docs/index.html"));
+    assert!(!is_valid_website_redirect("docs/index.html"));
+    assert_eq!(get_media_type(" Text/Plain ; charset=utf-8"), "text/plain");
+    assert_eq!(
+        resolve_content_type(None, Some("text/plain".to_string())),
+        Ok(DEFAULT_CONTENT_TYPE.to_string())
+    );
+}
+
+#[test]
+fn can_check_limits() {
+    assert!(is_op_allowed("get", Some("get,list".to_string())));
+    assert!(!is_op_allowed("put", Some("get,list".to_string())));
+    assert!(check_batch_size(26, 25).is_err());
+}
@@ -0,0 +1,169 @@
+use rusoto_mock::MockRequestDispatcher;
+
+// ETag returned by the default mock client
+pub const MOCK_ETAG: &str = "\"9b2cf535f27731c974343645a3985328\"";
+
+/// Builds S3 responses in memory in the shape rusoto_s3 deserializes,
+/// so the mock client does not depend on fixture files.
+pub struct S3MockResponseBuilder;
+
+impl S3MockResponseBuilder {
+    /// PutObject answers with an empty body; the ETag travels in a header.
+    pub fn put_object_success(etag: &str) -> MockRequestDispatcher {
+        MockRequestDispatcher::default().with_header("ETag", etag)
+    }
+}
+
+/// Helpers that only tests need.
+#[cfg(test)]
+pub mod testing {
+    use std::time::Duration;
+
+    use http::{HeaderMap, StatusCode};
+    use rusoto_core::request::{DispatchSignedRequest, DispatchSignedRequestFuture, HttpResponse};
+    use rusoto_core::signature::SignedRequest;
+    use rusoto_core::ByteStream;
+    use rusoto_mock::MockRequestDispatcher;
+
+    use super::S3MockResponseBuilder;
+
+    impl S3MockResponseBuilder {
+        pub fn put_object_error(code: &str, message: &str) -> MockRequestDispatcher {
+            MockRequestDispatcher::with_status(get_error_status(code))
+                .with_body(&get_error_body(code, message))
+        }
+    }
+
+    fn get_error_status(code: &str) -> u16 {
+        match code {
+            "AccessDenied" => 403,
+            "NoSuchBucket" | "NoSuchKey" => 404,
+            "PreconditionFailed" => 412,
+            "InternalError" => 500,
+            "SlowDown" | "ServiceUnavailable" => 503,
+            _ => 400,
+        }
+    }
+
+    fn get_error_body(code: &str, message: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<Error>
+  <Code>{}</Code>
+  <Message>{}</Message>
+  <RequestId>4442587FB7D0A2F9</RequestId>
+  <HostId>mock-host-id</HostId>
+</Error>"#,
+            code, message
+        )
+    }
+
+    /// Answers like `inner`, but only after `delay`, to exercise timeouts.
+    pub struct SlowDispatcher<D> {
+        inner: D,
+        delay: Duration,
+    }
+
+    impl<D> SlowDispatcher<D> {
+        pub fn new(inner: D, delay: Duration) -> SlowDispatcher<D> {
+            SlowDispatcher { inner, delay }
+        }
+    }
+
+    impl<D: DispatchSignedRequest> DispatchSignedRequest for SlowDispatcher<D> {
+        fn dispatch(
+            &self,
+            request: SignedRequest,
+            timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let response = self.inner.dispatch(request, timeout);
+            let delay = self.delay;
+            Box::pin(async move {
+                tokio::time::delay_for(delay).await;
+                response.await
+            })
+        }
+    }
+
+    /// Like `MockRequestDispatcher`, but the body may be arbitrary bytes
+    /// (e.g. gzip) instead of a `&str`.
+    pub struct BinaryBodyDispatcher {
+        body: Vec<u8>,
+        headers: Vec<(&'static str, String)>,
+    }
+
+    impl BinaryBodyDispatcher {
+        pub fn new(body: Vec<u8>) -> BinaryBodyDispatcher {
+            BinaryBodyDispatcher {
+                body,
+                headers: Vec::new(),
+            }
+        }
+
+        pub fn with_header(mut self, key: &'static str, value: &str) -> BinaryBodyDispatcher {
+            self.headers.push((key, value.to_string()));
+            self
+        }
+    }
+
+    impl DispatchSignedRequest for BinaryBodyDispatcher {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            let mut headers = HeaderMap::<String>::default();
+            for (key, value) in &self.headers {
+                headers.insert(*key, value.clone());
+            }
+            let response = HttpResponse {
+                status: StatusCode::OK,
+                body: ByteStream::from(self.body.clone()),
+                headers,
+            };
+            Box::pin(async move { Ok(response) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::RusotoError;
+    use rusoto_mock::MockCredentialsProvider;
+    use rusoto_s3::{PutObjectRequest, S3Client, S3};
+
+    #[tokio::test]
+    async fn can_build_put_object_success_response() {
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let output = s3
+            .put_object(PutObjectRequest::default())
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.e_tag, Some(MOCK_ETAG.to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_build_put_object_error_response() {
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_error("AccessDenied", "Access Denied"),
+            MockCredentialsProvider,
+            Default::default(),
+        );
+        let err = s3
+            .put_object(PutObjectRequest::default())
+            .await
+            .expect_err("expected Err(_) value");
+        match err {
+            RusotoError::Unknown(response) => {
+                assert_eq!(response.status.as_u16(), 403);
+                assert!(response.body_as_str().contains("<Code>AccessDenied</Code>"));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::Result;
+use rusoto_s3::{CopyObjectRequest, DeleteObjectRequest, S3Client, S3};
+
+#[derive(Debug, PartialEq)]
+pub enum MoveOutcome {
+    Moved,
+    /// The destination was written but the source is still there.
+    SourceNotDeleted(String),
+}
+
+/// S3 has no rename, so this copies `source_key` to `destination_key` and then
+/// deletes the source. A failed copy is an error; a failed delete is reported
+/// as `SourceNotDeleted` because the destination already exists by then.
+pub async fn move_object(
+    s3: &S3Client,
+    bucket_name: &str,
+    source_key: &str,
+    destination_key: &str,
+) -> Result<MoveOutcome> {
+    s3.copy_object(CopyObjectRequest {
+        bucket: bucket_name.to_string(),
+        key: destination_key.to_string(),
+        copy_source: format!("{}/{}", bucket_name, encode_copy_source(source_key)),
+        ..Default::default()
+    })
+    .await?;
+    let result = s3
+        .delete_object(DeleteObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: source_key.to_string(),
+            ..Default::default()
+        })
+        .await;
+    match result {
+        Ok(_) => Ok(MoveOutcome::Moved),
+        Err(err) => Ok(MoveOutcome::SourceNotDeleted(err.to_string())),
+    }
+}
+
+// x-amz-copy-source must be URL-encoded, rusoto sends it as is
+fn encode_copy_source(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::S3MockResponseBuilder;
+    use rusoto_core::Region;
+    use rusoto_mock::{
+        MockCredentialsProvider, MockRequestDispatcher, MultipleMockRequestDispatcher,
+    };
+
+    fn copy_success() -> MockRequestDispatcher {
+        MockRequestDispatcher::default()
+            .with_body(
+                r#"<CopyObjectResult><LastModified>2020-11-16T00:00:00.000Z</LastModified><ETag>"9b2cf535f27731c974343645a3985328"</ETag></CopyObjectResult>"#,
+            )
+            .with_request_checker(|request| {
+                assert_eq!(request.path, "/bucket/new/b.txt");
+                assert_eq!(
+                    request.headers.get("x-amz-copy-source"),
+                    Some(&vec![b"bucket/old/a%20b.txt".to_vec()])
+                );
+            })
+    }
+
+    fn get_client(dispatchers: Vec<MockRequestDispatcher>) -> S3Client {
+        S3Client::new_with(
+            MultipleMockRequestDispatcher::new(dispatchers),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        )
+    }
+
+    #[tokio::test]
+    async fn can_move_object() {
+        let delete_success = MockRequestDispatcher::with_status(204)
+            .with_request_checker(|request| assert_eq!(request.method, "DELETE"));
+        let s3 = get_client(vec![copy_success(), delete_success]);
+        let outcome = move_object(&s3, "bucket", "old/a b.txt", "new/b.txt")
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(outcome, MoveOutcome::Moved);
+    }
+
+    #[tokio::test]
+    async fn can_report_source_not_deleted() {
+        let delete_failure =
+            S3MockResponseBuilder::put_object_error("AccessDenied", "Access Denied");
+        let s3 = get_client(vec![copy_success(), delete_failure]);
+        let outcome = move_object(&s3, "bucket", "old/a b.txt", "new/b.txt")
+            .await
+            .expect("expected Ok(_) value");
+        match outcome {
+            MoveOutcome::SourceNotDeleted(_) => {}
+            outcome => panic!("unexpected outcome: {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn can_fail_move_when_copy_fails() {
+        let copy_failure = S3MockResponseBuilder::put_object_error(
+            "NoSuchKey",
+            "The specified key does not exist.",
+        );
+        let s3 = get_client(vec![copy_failure]);
+        assert!(move_object(&s3, "bucket", "old/a b.txt", "new/b.txt")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn can_encode_copy_source() {
+        assert_eq!(encode_copy_source("logs/test.txt"), "logs/test.txt");
+        assert_eq!(encode_copy_source("a b+c.txt"), "a%20b%2Bc.txt");
+    }
+}
@@ -0,0 +1,114 @@
+use futures::executor::block_on;
+use futures::stream;
+use multer::Multipart;
+
+use crate::LambdaError;
+
+/// One field of a `multipart/form-data` body; `filename` is only set for file parts.
+#[derive(Debug, PartialEq)]
+pub struct UploadPart {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Splits a form body on the boundary from `content_type`. The whole body is
+/// already in memory, so the stream multer reads never has to wait.
+pub fn parse_multipart(body: &[u8], content_type: &str) -> Result<Vec<UploadPart>, LambdaError> {
+    let boundary = multer::parse_boundary(content_type).map_err(malformed)?;
+    let body = body.to_vec();
+    let mut multipart = Multipart::new(
+        stream::once(async move { Ok::<_, std::io::Error>(body) }),
+        boundary,
+    );
+    block_on(async {
+        let mut parts = Vec::new();
+        while let Some(field) = multipart.next_field().await.map_err(malformed)? {
+            let name = field.name().unwrap_or_default().to_string();
+            let filename = field.file_name().map(str::to_string);
+            let content_type = field.content_type().map(|mime| mime.to_string());
+            let data = field.bytes().await.map_err(malformed)?.to_vec();
+            parts.push(UploadPart {
+                name,
+                filename,
+                content_type,
+                data,
+            });
+        }
+        Ok::<_, LambdaError>(parts)
+    })
+}
+
+/// Whether a `Content-Type` header is for a form upload.
+pub fn is_multipart(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .map(|media_type| {
+            media_type
+                .trim()
+                .eq_ignore_ascii_case("multipart/form-data")
+        })
+        .unwrap_or(false)
+}
+
+fn malformed(err: multer::Error) -> LambdaError {
+    LambdaError::MalformedEvent(format!("multipart body: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=X-BOUNDARY";
+
+    #[test]
+    fn can_parse_multipart_fields_and_files() {
+        let body = "--X-BOUNDARY\r\n\
+                    Content-Disposition: form-data; name=\"filename\"\r\n\r\n\
+                    notes.txt\r\n\
+                    --X-BOUNDARY\r\n\
+                    Content-Disposition: form-data; name=\"text\"; filename=\"upload.txt\"\r\n\
+                    Content-Type: text/plain\r\n\r\n\
+                    Firstname\r\n\
+                    --X-BOUNDARY--\r\n";
+        let parts = parse_multipart(body.as_bytes(), CONTENT_TYPE).expect("expected Ok(_) value");
+        assert_eq!(
+            parts,
+            vec![
+                UploadPart {
+                    name: "filename".to_string(),
+                    filename: None,
+                    content_type: None,
+                    data: b"notes.txt".to_vec(),
+                },
+                UploadPart {
+                    name: "text".to_string(),
+                    filename: Some("upload.txt".to_string()),
+                    content_type: Some("text/plain".to_string()),
+                    data: b"Firstname".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn can_reject_malformed_multipart() {
+        let body = "--OTHER-BOUNDARY\r\n\
+                    Content-Disposition: form-data; name=\"text\"\r\n\r\n\
+                    Firstname\r\n\
+                    --OTHER-BOUNDARY--\r\n";
+        let result = parse_multipart(body.as_bytes(), CONTENT_TYPE);
+        assert!(matches!(result, Err(LambdaError::MalformedEvent(_))));
+        let result = parse_multipart(body.as_bytes(), "multipart/form-data");
+        assert!(matches!(result, Err(LambdaError::MalformedEvent(_))));
+    }
+
+    #[test]
+    fn can_detect_multipart_content_type() {
+        assert!(is_multipart(CONTENT_TYPE));
+        assert!(is_multipart("Multipart/Form-Data"));
+        assert!(!is_multipart("application/json"));
+    }
+}
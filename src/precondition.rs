@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use rusoto_core::RusotoError;
+
+use crate::s3_object::S3Object;
+use crate::uploader::Uploader;
+use crate::LambdaError;
+
+const MODE_OVERWRITE: &str = "overwrite";
+const MODE_FAIL_IF_EXISTS: &str = "fail_if_exists";
+const MODE_FAIL_IF_CHANGED: &str = "fail_if_changed";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PutObjectMode {
+    Overwrite,
+    FailIfExists,
+    FailIfChanged,
+}
+
+impl PutObjectMode {
+    /// An unset or empty `PUT_OBJECT_MODE` keeps the last-write-wins behavior.
+    pub fn parse(value: Option<&str>) -> Result<PutObjectMode> {
+        match value {
+            None | Some("") | Some(MODE_OVERWRITE) => Ok(PutObjectMode::Overwrite),
+            Some(MODE_FAIL_IF_EXISTS) => Ok(PutObjectMode::FailIfExists),
+            Some(MODE_FAIL_IF_CHANGED) => Ok(PutObjectMode::FailIfChanged),
+            Some(mode) => Err(anyhow!("Unknown PUT_OBJECT_MODE {}", mode)),
+        }
+    }
+}
+
+/// Checks the put precondition for `mode` before uploading. This is best effort,
+/// not a guarantee: rusoto_s3 0.45 can't send `If-None-Match`/`If-Match` on
+/// PutObject, so this asks the uploader first, and a writer that puts between
+/// the check and the upload is overwritten without an error.
+pub async fn check_precondition(
+    uploader: &dyn Uploader,
+    object: &S3Object,
+    mode: PutObjectMode,
+    if_match: Option<&str>,
+) -> Result<std::result::Result<(), LambdaError>> {
+    let result = match mode {
+        PutObjectMode::Overwrite => Ok(()),
+        PutObjectMode::FailIfExists => {
+            if uploader.exists(object).await? {
+                Err(LambdaError::ObjectExists)
+            } else {
+                Ok(())
+            }
+        }
+        PutObjectMode::FailIfChanged => {
+            if uploader.etag(object).await?.as_deref() != if_match {
+                Err(LambdaError::PreconditionFailed)
+            } else {
+                Ok(())
+            }
+        }
+    };
+    Ok(result)
+}
+
+/// S3-compatible servers that do honor conditional headers answer 412.
+pub fn is_precondition_failed<E>(err: &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::Unknown(response) => response.status.as_u16() == 412,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::S3MockResponseBuilder;
+    use crate::uploader::testing::{get_memory_etag, MemoryUploader};
+    use rusoto_core::Region;
+    use rusoto_mock::MockCredentialsProvider;
+    use rusoto_s3::{PutObjectRequest, S3Client, S3};
+
+    fn existing_object() -> MemoryUploader {
+        let uploader = MemoryUploader::default();
+        uploader.insert("bucket", "test.txt", b"Firstname");
+        uploader
+    }
+
+    #[test]
+    fn can_parse_put_object_mode() {
+        assert_eq!(
+            PutObjectMode::parse(None).unwrap(),
+            PutObjectMode::Overwrite
+        );
+        assert_eq!(
+            PutObjectMode::parse(Some("")).unwrap(),
+            PutObjectMode::Overwrite
+        );
+        assert_eq!(
+            PutObjectMode::parse(Some("fail_if_exists")).unwrap(),
+            PutObjectMode::FailIfExists
+        );
+        assert_eq!(
+            PutObjectMode::parse(Some("fail_if_changed")).unwrap(),
+            PutObjectMode::FailIfChanged
+        );
+        assert!(PutObjectMode::parse(Some("append")).is_err());
+    }
+
+    #[tokio::test]
+    async fn can_overwrite_existing_object() {
+        let uploader = existing_object();
+        let result = check_precondition(
+            &uploader,
+            &S3Object::new("bucket", "test.txt"),
+            PutObjectMode::Overwrite,
+            None,
+        );
+        assert_eq!(result.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn can_check_fail_if_exists() {
+        let mode = PutObjectMode::FailIfExists;
+        let missing = MemoryUploader::default();
+        let result = check_precondition(&missing, &S3Object::new("bucket", "test.txt"), mode, None);
+        assert_eq!(result.await.unwrap(), Ok(()));
+        let existing = existing_object();
+        let result =
+            check_precondition(&existing, &S3Object::new("bucket", "test.txt"), mode, None);
+        assert_eq!(result.await.unwrap(), Err(LambdaError::ObjectExists));
+    }
+
+    #[tokio::test]
+    async fn can_check_fail_if_changed() {
+        let mode = PutObjectMode::FailIfChanged;
+        let etag = get_memory_etag(b"Firstname");
+        let existing = existing_object();
+        let result = check_precondition(
+            &existing,
+            &S3Object::new("bucket", "test.txt"),
+            mode,
+            Some(&etag),
+        );
+        assert_eq!(result.await.unwrap(), Ok(()));
+        let result = check_precondition(
+            &existing,
+            &S3Object::new("bucket", "test.txt"),
+            mode,
+            Some("\"stale\""),
+        );
+        assert_eq!(result.await.unwrap(), Err(LambdaError::PreconditionFailed));
+        let missing = MemoryUploader::default();
+        let result = check_precondition(
+            &missing,
+            &S3Object::new("bucket", "test.txt"),
+            mode,
+            Some(&etag),
+        );
+        assert_eq!(result.await.unwrap(), Err(LambdaError::PreconditionFailed));
+    }
+
+    #[tokio::test]
+    async fn can_detect_precondition_failed_put() {
+        let s3 = S3Client::new_with(
+            S3MockResponseBuilder::put_object_error(
+                "PreconditionFailed",
+                "At least one of the pre-conditions you specified did not hold",
+            ),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let err = s3
+            .put_object(PutObjectRequest::default())
+            .await
+            .expect_err("expected Err(_) value");
+        assert!(is_precondition_failed(&err));
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::normalize_text;
+
+/// One step of the text pipeline a put body goes through before upload.
+pub trait Preprocessor: Send + Sync {
+    fn process(&self, text: String) -> Result<String>;
+}
+
+/// Runs its preprocessors in order; the first error stops the chain.
+pub struct PreprocessorChain {
+    processors: Vec<Box<dyn Preprocessor>>,
+}
+
+impl PreprocessorChain {
+    pub fn new(processors: Vec<Box<dyn Preprocessor>>) -> PreprocessorChain {
+        PreprocessorChain { processors }
+    }
+
+    pub fn run(&self, text: String) -> Result<String> {
+        self.processors
+            .iter()
+            .try_fold(text, |text, processor| processor.process(text))
+    }
+}
+
+/// Drops a leading UTF-8 byte order mark some editors prepend.
+pub struct BomStripper;
+
+impl Preprocessor for BomStripper {
+    fn process(&self, text: String) -> Result<String> {
+        match text.strip_prefix('\u{feff}') {
+            Some(stripped) => Ok(stripped.to_string()),
+            None => Ok(text),
+        }
+    }
+}
+
+/// See `normalize_text`.
+pub struct WhitespaceNormalizer;
+
+impl Preprocessor for WhitespaceNormalizer {
+    fn process(&self, text: String) -> Result<String> {
+        Ok(normalize_text(&text).into_owned())
+    }
+}
+
+/// Masks words that look like an email address or a phone number.
+/// A heuristic, not a guarantee that no personal data is stored.
+pub struct PiiRedactor;
+
+impl Preprocessor for PiiRedactor {
+    fn process(&self, text: String) -> Result<String> {
+        let mut redacted = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars() {
+            if c.is_whitespace() {
+                redacted.push_str(redact_word(&word));
+                word.clear();
+                redacted.push(c);
+            } else {
+                word.push(c);
+            }
+        }
+        redacted.push_str(redact_word(&word));
+        Ok(redacted)
+    }
+}
+
+fn redact_word(word: &str) -> &str {
+    let is_email = match word.find('@') {
+        Some(at) => at > 0 && word[at + 1..].contains('.'),
+        None => false,
+    };
+    if is_email {
+        return "[email]";
+    }
+    let digits = word.chars().filter(char::is_ascii_digit).count();
+    let is_phone = digits >= 7
+        && word
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-().".contains(c));
+    if is_phone {
+        return "[phone]";
+    }
+    word
+}
+
+/// Rejects text longer than the limit, in bytes.
+pub struct MaxLengthEnforcer(pub usize);
+
+impl Preprocessor for MaxLengthEnforcer {
+    fn process(&self, text: String) -> Result<String> {
+        if text.len() > self.0 {
+            return Err(LengthExceeded {
+                actual_len: text.len(),
+                limit: self.0,
+            }
+            .into());
+        }
+        Ok(text)
+    }
+}
+
+/// Kept apart from `LambdaError::TextBodyTooLong` so the caller can log the lengths.
+#[derive(Debug, PartialEq)]
+pub struct LengthExceeded {
+    pub actual_len: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for LengthExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes exceeds the limit of {}",
+            self.actual_len, self.limit
+        )
+    }
+}
+
+impl std::error::Error for LengthExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct CallRecorder(Arc<AtomicBool>);
+
+    impl Preprocessor for CallRecorder {
+        fn process(&self, text: String) -> Result<String> {
+            self.0.store(true, Ordering::SeqCst);
+            Ok(text)
+        }
+    }
+
+    #[test]
+    fn can_strip_bom() {
+        let text = BomStripper
+            .process("\u{feff}Firstname".to_string())
+            .unwrap();
+        assert_eq!(text, "Firstname");
+        let text = BomStripper.process("Firstname".to_string()).unwrap();
+        assert_eq!(text, "Firstname");
+    }
+
+    #[test]
+    fn can_normalize_whitespace() {
+        let text = WhitespaceNormalizer
+            .process("  first\r\nsecond \n".to_string())
+            .unwrap();
+        assert_eq!(text, "first\nsecond");
+    }
+
+    #[test]
+    fn can_redact_pii() {
+        let text = PiiRedactor
+            .process("mail me@example.com or call +81-90-1234-5678\nin 2020".to_string())
+            .unwrap();
+        assert_eq!(text, "mail [email] or call [phone]\nin 2020");
+    }
+
+    #[test]
+    fn can_enforce_max_length() {
+        assert_eq!(
+            MaxLengthEnforcer(5).process("12345".to_string()).unwrap(),
+            "12345"
+        );
+        let err = MaxLengthEnforcer(5)
+            .process("123456".to_string())
+            .expect_err("expected Err(_) value");
+        assert_eq!(
+            err.downcast::<LengthExceeded>().unwrap(),
+            LengthExceeded {
+                actual_len: 6,
+                limit: 5
+            }
+        );
+    }
+
+    #[test]
+    fn can_run_chain_in_order() {
+        let chain = PreprocessorChain::new(vec![
+            Box::new(BomStripper),
+            Box::new(WhitespaceNormalizer),
+            Box::new(PiiRedactor),
+            Box::new(MaxLengthEnforcer(20)),
+        ]);
+        let text = chain
+            .run("\u{feff}  me@example.com \r\n".to_string())
+            .unwrap();
+        assert_eq!(text, "[email]");
+    }
+
+    #[test]
+    fn can_abort_chain_on_error() {
+        let called = Arc::new(AtomicBool::new(false));
+        let chain = PreprocessorChain::new(vec![
+            Box::new(MaxLengthEnforcer(3)),
+            Box::new(CallRecorder(called.clone())),
+        ]);
+        assert!(chain.run("Firstname".to_string()).is_err());
+        assert!(!called.load(Ordering::SeqCst));
+    }
+}
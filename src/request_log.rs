@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use log::trace;
+use rusoto_core::request::{DispatchSignedRequest, DispatchSignedRequestFuture};
+use rusoto_core::signature::SignedRequest;
+
+// credentials must never reach the logs, even at trace level
+const REDACTED_HEADERS: &[&str] = &["authorization", "x-amz-security-token"];
+
+/// Logs the method, URL and headers of every request at `TRACE`, for seeing
+/// exactly what goes to S3 without an HTTP proxy. Enabled by `S3_REQUEST_LOGGING=true`.
+pub struct RequestLoggingDispatcher<D> {
+    inner: D,
+}
+
+impl<D> RequestLoggingDispatcher<D> {
+    pub fn new(inner: D) -> RequestLoggingDispatcher<D> {
+        RequestLoggingDispatcher { inner }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for RequestLoggingDispatcher<D> {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        trace!("{}", describe_request(&request));
+        self.inner.dispatch(request, timeout)
+    }
+}
+
+fn describe_request(request: &SignedRequest) -> String {
+    let headers: Vec<String> = request
+        .headers
+        .iter()
+        .map(|(name, values)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                "[redacted]".to_string()
+            } else {
+                values
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect();
+    format!(
+        "S3 request {} {}://{}{} [{}]",
+        request.method(),
+        request.scheme(),
+        request.hostname(),
+        request.path(),
+        headers.join("; ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logger::testing::{capture_logs, get_captured_logs};
+    use crate::mock::{S3MockResponseBuilder, MOCK_ETAG};
+    use rusoto_core::Region;
+    use rusoto_mock::MockCredentialsProvider;
+    use rusoto_s3::{PutObjectRequest, S3Client, S3};
+
+    #[tokio::test]
+    async fn can_log_request_at_trace_level() {
+        capture_logs();
+        let s3 = S3Client::new_with(
+            RequestLoggingDispatcher::new(S3MockResponseBuilder::put_object_success(MOCK_ETAG)),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        s3.put_object(PutObjectRequest {
+            bucket: "request-log-bucket".to_string(),
+            key: "test.txt".to_string(),
+            ..Default::default()
+        })
+        .await
+        .expect("expected Ok(_) value");
+        let logs = get_captured_logs("request-log-bucket");
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].starts_with("TRACE S3 request PUT https://"));
+        assert!(logs[0].contains("/request-log-bucket/test.txt"));
+        assert!(logs[0].contains("authorization: [redacted]"));
+        assert!(!logs[0].contains("AWS4-HMAC-SHA256"));
+    }
+}
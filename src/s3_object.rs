@@ -0,0 +1,85 @@
+use rusoto_core::Region;
+
+/// A bucket and a key that travel together, so helpers taking both can't
+/// have them swapped at the call site.
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Object {
+    pub bucket: String,
+    pub key: String,
+}
+
+impl S3Object {
+    pub fn new(bucket: impl Into<String>, key: impl Into<String>) -> S3Object {
+        S3Object {
+            bucket: bucket.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Where a public-read object can be fetched. A custom endpoint gets a
+    /// path-style URL, which is what the local S3 server answers to; AWS gets
+    /// the virtual-hosted form.
+    pub fn url(&self, region: &Region) -> String {
+        match region {
+            Region::Custom { endpoint, .. } => format!(
+                "{}/{}/{}",
+                endpoint.trim_end_matches('/'),
+                self.bucket,
+                encode_key(&self.key)
+            ),
+            _ => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.bucket,
+                region.name(),
+                encode_key(&self.key)
+            ),
+        }
+    }
+}
+
+// percent-encodes everything but unreserved characters, `/` keeps separating segments
+pub fn encode_key(key: &str) -> String {
+    let mut encoded = String::with_capacity(key.len());
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_get_local_path_style_url() {
+        let region = Region::Custom {
+            name: "ap-northeast-1".to_owned(),
+            endpoint: "http://localhost:8000/".to_owned(),
+        };
+        assert_eq!(
+            S3Object::new("test-bucket", "logs/a.txt").url(&region),
+            "http://localhost:8000/test-bucket/logs/a.txt"
+        );
+    }
+
+    #[test]
+    fn can_get_cloud_virtual_hosted_url() {
+        assert_eq!(
+            S3Object::new("test-bucket", "logs/a.txt").url(&Region::ApNortheast1),
+            "https://test-bucket.s3.ap-northeast-1.amazonaws.com/logs/a.txt"
+        );
+    }
+
+    #[test]
+    fn can_encode_key_in_url() {
+        assert_eq!(
+            S3Object::new("test-bucket", "名前 1+1.txt").url(&Region::UsEast1),
+            "https://test-bucket.s3.us-east-1.amazonaws.com/%E5%90%8D%E5%89%8D%201%2B1.txt"
+        );
+    }
+}
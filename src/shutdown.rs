@@ -0,0 +1,191 @@
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use tokio::time::delay_for;
+
+// how often `drain` looks at the in-flight count
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// the runtime posts a response right after the handler returns and has no hook
+// to tell us it is done, so the last one gets this long to reach the runtime API
+const RESPONSE_POST_GRACE: Duration = Duration::from_millis(500);
+
+/// Invocations still running in this process, waited on before a container exits.
+pub static IN_FLIGHT: InFlight = InFlight::new();
+
+pub struct InFlight {
+    count: AtomicUsize,
+    shutting_down: AtomicBool,
+}
+
+impl InFlight {
+    pub const fn new() -> InFlight {
+        InFlight {
+            count: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    /// Set once SIGTERM arrived; invocations that start after it are turned away.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Counts `future` as in flight until it completes or is dropped.
+    pub async fn track<F: Future>(&self, future: F) -> F::Output {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let _guard = InFlightGuard(self);
+        future.await
+    }
+
+    pub fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Waits for every tracked invocation, giving up after `deadline`.
+    /// Returns false when some were still running at the deadline.
+    pub async fn drain(&self, deadline: Duration) -> bool {
+        let started_at = Instant::now();
+        while self.get() > 0 {
+            if started_at.elapsed() >= deadline {
+                return false;
+            }
+            delay_for(POLL_INTERVAL).await;
+        }
+        true
+    }
+}
+
+struct InFlightGuard<'a>(&'a InFlight);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Resolves on the first SIGTERM, which is how ECS and Kubernetes stop a container.
+pub async fn terminate_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(err) => {
+            // without a handler the default action still kills the process
+            warn!("Failed to install SIGTERM handler: {}", err);
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Runs `runtime` until it returns or `signal` resolves. After the signal
+/// `runtime` is still polled, so the invocations already running finish and
+/// have their responses posted, while new ones are turned away. Returns `None`
+/// when it was stopped by the signal.
+pub async fn run_until_shutdown<R, S>(
+    runtime: R,
+    signal: S,
+    in_flight: &InFlight,
+    deadline: Duration,
+) -> Option<R::Output>
+where
+    R: Future,
+    S: Future,
+{
+    tokio::pin!(runtime);
+    tokio::select! {
+        output = &mut runtime => return Some(output),
+        _ = signal => {}
+    }
+    in_flight.shutting_down.store(true, Ordering::SeqCst);
+    tokio::select! {
+        output = &mut runtime => Some(output),
+        _ = graceful_shutdown(in_flight, deadline) => None,
+    }
+}
+
+/// Waits for the invocations already running, then flushes what is buffered
+/// before the process exits.
+async fn graceful_shutdown(in_flight: &InFlight, deadline: Duration) {
+    let running = in_flight.get();
+    info!(
+        "Shutting down, waiting up to {}ms for {} in-flight invocations",
+        deadline.as_millis(),
+        running
+    );
+    if in_flight.drain(deadline).await {
+        delay_for(RESPONSE_POST_GRACE).await;
+    } else {
+        warn!(
+            "{} invocations still running at the shutdown deadline, their responses are lost",
+            in_flight.get()
+        );
+    }
+    #[cfg(feature = "sentry-integration")]
+    crate::error_tracking::flush();
+    log::logger().flush();
+    // EMF metric lines are written to stdout directly, see diag.rs
+    let _ = std::io::stdout().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // stands in for `lambda::run`: one invocation, its response, then a long poll
+    async fn fake_runtime(in_flight: &InFlight, posted: &AtomicBool) {
+        in_flight.track(delay_for(Duration::from_millis(100))).await;
+        delay_for(Duration::from_millis(10)).await;
+        posted.store(true, Ordering::SeqCst);
+        futures::future::pending::<()>().await;
+    }
+
+    #[tokio::test]
+    async fn can_drain_in_flight_invocations() {
+        let in_flight = InFlight::new();
+        let posted = AtomicBool::new(false);
+        let output = run_until_shutdown(
+            fake_runtime(&in_flight, &posted),
+            delay_for(Duration::from_millis(20)),
+            &in_flight,
+            Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(output, None);
+        assert!(in_flight.is_shutting_down());
+        assert_eq!(in_flight.get(), 0);
+        // the runtime was still polled after the signal, so the response went out
+        assert!(posted.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn can_return_runtime_output_without_signal() {
+        let in_flight = InFlight::new();
+        let output = run_until_shutdown(
+            async { "stopped" },
+            futures::future::pending::<()>(),
+            &in_flight,
+            Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(output, Some("stopped"));
+        assert!(!in_flight.is_shutting_down());
+    }
+
+    #[tokio::test]
+    async fn can_stop_waiting_at_deadline() {
+        let in_flight: &'static InFlight = Box::leak(Box::new(InFlight::new()));
+        let invocation = tokio::spawn(in_flight.track(delay_for(Duration::from_secs(60))));
+        delay_for(Duration::from_millis(10)).await;
+
+        let started_at = Instant::now();
+        assert!(!in_flight.drain(Duration::from_millis(100)).await);
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+        assert_eq!(in_flight.get(), 1);
+        drop(invocation);
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rusoto_s3::{GetObjectTaggingRequest, HeadObjectRequest, S3Client, S3};
+
+use crate::s3_object::S3Object;
+
+// S3 leaves the storage class header out for STANDARD objects
+const DEFAULT_STORAGE_CLASS: &str = "STANDARD";
+
+/// What HeadObject says about an object, plus its tags when asked for.
+#[derive(Debug, PartialEq, Default)]
+pub struct ObjectStat {
+    pub size: i64,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub storage_class: String,
+    pub tags: Option<BTreeMap<String, String>>,
+}
+
+/// With `include_tags` an object without tags gets an empty map, not `None`.
+pub async fn stat_object(
+    s3: &S3Client,
+    object: &S3Object,
+    include_tags: bool,
+) -> Result<ObjectStat> {
+    let output = s3
+        .head_object(HeadObjectRequest {
+            bucket: bucket_name.to_string(),
+            key: key.to_string(),
+            ..Default::default()
+        })
+        .await?;
+    let tags = if include_tags {
+        let tagging = s3
+            .get_object_tagging(GetObjectTaggingRequest {
+                bucket: object.bucket.clone(),
+                key: object.key.clone(),
+                ..Default::default()
+            })
+            .await?;
+        Some(
+            tagging
+                .tag_set
+                .into_iter()
+                .map(|tag| (tag.key, tag.value))
+                .collect(),
+        )
+    } else {
+        None
+    };
+    Ok(ObjectStat {
+        size: output.content_length.unwrap_or_default(),
+        content_type: output.content_type,
+        etag: output.e_tag,
+        last_modified: output.last_modified,
+        storage_class: output
+            .storage_class
+            .unwrap_or_else(|| DEFAULT_STORAGE_CLASS.to_string()),
+        tags,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MOCK_ETAG;
+    use rusoto_core::Region;
+    use rusoto_mock::{
+        MockCredentialsProvider, MockRequestDispatcher, MultipleMockRequestDispatcher,
+    };
+
+    fn head() -> MockRequestDispatcher {
+        MockRequestDispatcher::default()
+            .with_header("Content-Length", "9")
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_header("ETag", MOCK_ETAG)
+    }
+
+    fn tagging(tags: &[(&str, &str)]) -> MockRequestDispatcher {
+        let tag_set: String = tags
+            .iter()
+            .map(|(key, value)| format!("<Tag><Key>{}</Key><Value>{}</Value></Tag>", key, value))
+            .collect();
+        MockRequestDispatcher::default()
+            .with_body(&format!("<Tagging><TagSet>{}</TagSet></Tagging>", tag_set))
+    }
+
+    #[tokio::test]
+    async fn can_stat_object_with_tags() {
+        let s3 = S3Client::new_with(
+            MultipleMockRequestDispatcher::new(vec![
+                head(),
+                tagging(&[("team", "web"), ("env", "dev")]),
+            ]),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let stat = stat_object(&s3, &S3Object::new("bucket", "test.txt"), true)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(stat.size, 9);
+        assert_eq!(stat.etag, Some(MOCK_ETAG.to_string()));
+        assert_eq!(stat.storage_class, DEFAULT_STORAGE_CLASS);
+        let tags = stat.tags.expect("expected Some(_) value");
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags["team"], "web");
+    }
+
+    #[tokio::test]
+    async fn can_stat_object_storage_class() {
+        let s3 = S3Client::new_with(
+            head().with_header("x-amz-storage-class", "GLACIER"),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let stat = stat_object(&s3, &S3Object::new("bucket", "test.txt"), false)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(stat.storage_class, "GLACIER");
+    }
+
+    #[tokio::test]
+    async fn can_stat_object_without_tags() {
+        let s3 = S3Client::new_with(
+            MultipleMockRequestDispatcher::new(vec![head(), tagging(&[])]),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let stat = stat_object(&s3, &S3Object::new("bucket", "test.txt"), true)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(stat.tags, Some(BTreeMap::new()));
+
+        let s3 = S3Client::new_with(head(), MockCredentialsProvider, Region::ApNortheast1);
+        let stat = stat_object(&s3, &S3Object::new("bucket", "test.txt"), false)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(stat.tags, None);
+    }
+}
@@ -0,0 +1,86 @@
+use std::env;
+use std::ffi::OsString;
+use std::sync::{Mutex, MutexGuard};
+
+use once_cell::sync::Lazy;
+
+// the process environment is shared by every test thread
+static ENV_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Sets env vars for the duration of a test and restores the previous values on drop.
+/// Holds a global lock meanwhile, so tests that touch the environment run one at a time.
+pub struct TestGuard {
+    previous: Vec<(&'static str, Option<OsString>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestGuard {
+    pub fn new(vars: &[(&'static str, &str)]) -> TestGuard {
+        // a failed test poisons the lock, that must not fail the tests after it
+        let lock = ENV_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let previous = vars
+            .iter()
+            .map(|(key, value)| {
+                let previous = env::var_os(key);
+                env::set_var(key, value);
+                (*key, previous)
+            })
+            .collect();
+        TestGuard {
+            previous,
+            _lock: lock,
+        }
+    }
+}
+
+impl Drop for TestGuard {
+    fn drop(&mut self) {
+        for (key, previous) in self.previous.drain(..).rev() {
+            match previous {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BUCKET_NAME_KEY;
+    use std::thread;
+    use std::time::Duration;
+
+    fn assert_bucket_name_stays(bucket_name: &'static str) {
+        let _guard = TestGuard::new(&[(BUCKET_NAME_KEY, bucket_name)]);
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(1));
+            assert_eq!(env::var(BUCKET_NAME_KEY).unwrap(), bucket_name);
+        }
+    }
+
+    #[test]
+    fn can_isolate_concurrent_guards() {
+        let handles: Vec<_> = ["bucket-a", "bucket-b"]
+            .iter()
+            .map(|bucket_name| thread::spawn(move || assert_bucket_name_stays(bucket_name)))
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn can_restore_previous_values() {
+        // only this test touches these, so they can be checked without the lock
+        env::set_var("TEST_GUARD_SET", "before");
+        env::remove_var("TEST_GUARD_UNSET");
+        {
+            let _guard = TestGuard::new(&[("TEST_GUARD_SET", "during"), ("TEST_GUARD_UNSET", "1")]);
+            assert_eq!(env::var("TEST_GUARD_SET").unwrap(), "during");
+            assert_eq!(env::var("TEST_GUARD_UNSET").unwrap(), "1");
+        }
+        assert_eq!(env::var("TEST_GUARD_SET").unwrap(), "before");
+        assert!(env::var("TEST_GUARD_UNSET").is_err());
+    }
+}
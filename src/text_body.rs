@@ -0,0 +1,121 @@
+use serde::Serializer;
+use serde_with::SerializeAs;
+
+use crate::LambdaError;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Text built from raw bytes, such as a downloaded object, once they are known
+/// to be UTF-8. Event fields are always UTF-8 already, serde_json checks them.
+///
+/// It has no `Serialize` of its own: a type that holds one picks
+/// `#[serde_as(as = "RedactedText")]` or, for internal audit records only,
+/// `#[serde_as(as = "AuditText")]`, so including it by mistake doesn't compile.
+#[derive(Debug, PartialEq)]
+pub struct TextBody(String);
+
+impl TextBody {
+    /// The error names the offset of the first byte that is not valid UTF-8.
+    pub fn try_new(bytes: Vec<u8>) -> Result<TextBody, LambdaError> {
+        if let Err(err) = std::str::from_utf8(&bytes) {
+            return Err(LambdaError::InvalidEncoding(format!(
+                "invalid byte at offset {}",
+                err.valid_up_to()
+            )));
+        }
+        // checked above, so this can't fail
+        Ok(TextBody(String::from_utf8(bytes).unwrap_or_default()))
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+/// Always `"[REDACTED]"`, for API responses, which may be logged or cached
+/// outside this account. Code that needs the text uses `into_string`.
+// the adapters are only named as types in `serde_as`, never constructed, and
+// nothing in the handler serializes a TextBody yet
+#[allow(dead_code)]
+pub struct RedactedText;
+
+impl SerializeAs<TextBody> for RedactedText {
+    fn serialize_as<S: Serializer>(_: &TextBody, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// The full text, for audit records that stay in this account.
+#[allow(dead_code)]
+pub struct AuditText;
+
+impl SerializeAs<TextBody> for AuditText {
+    fn serialize_as<S: Serializer>(source: &TextBody, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&source.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Serialize;
+    use serde_with::serde_as;
+
+    #[test]
+    fn can_accept_valid_utf8() {
+        let text = TextBody::try_new("Firstname 名前".as_bytes().to_vec());
+        assert_eq!(text.unwrap().into_string(), "Firstname 名前");
+    }
+
+    #[test]
+    fn can_reject_lone_continuation_byte() {
+        assert_eq!(
+            TextBody::try_new(b"ab\x80c".to_vec()),
+            Err(LambdaError::InvalidEncoding(
+                "invalid byte at offset 2".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn can_keep_replacement_characters() {
+        // U+FFFD is valid UTF-8 itself, it only hints that an earlier decode was lossy
+        let text = TextBody::try_new("a\u{fffd}b".as_bytes().to_vec());
+        assert_eq!(text.unwrap().into_string(), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn can_redact_text_body_in_response() {
+        #[serde_as]
+        #[derive(Serialize)]
+        struct Response {
+            #[serde_as(as = "RedactedText")]
+            text: TextBody,
+        }
+        let response = Response {
+            text: TextBody::try_new(b"Firstname".to_vec()).unwrap(),
+        };
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"text":"[REDACTED]"}"#
+        );
+        assert_eq!(response.text.into_string(), "Firstname");
+    }
+
+    #[test]
+    fn can_keep_text_body_in_audit_record() {
+        #[serde_as]
+        #[derive(Serialize)]
+        struct AuditRecord {
+            #[serde_as(as = "AuditText")]
+            text: TextBody,
+        }
+        let record = AuditRecord {
+            text: TextBody::try_new(b"Firstname".to_vec()).unwrap(),
+        };
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"text":"Firstname"}"#
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+/// Counts attached to an object as user metadata when `ANALYZE_TEXT` is set.
+#[derive(Debug, PartialEq)]
+pub struct TextStats {
+    pub lines: usize,
+    pub words: usize,
+    pub chars: usize,
+}
+
+/// Lines as `str::lines` counts them, so a trailing newline doesn't add an empty line.
+/// Words are runs of non-whitespace and chars are Unicode scalar values, not bytes.
+pub fn text_stats(text: &str) -> TextStats {
+    TextStats {
+        lines: text.lines().count(),
+        words: text.split_whitespace().count(),
+        chars: text.chars().count(),
+    }
+}
+
+impl TextStats {
+    /// rusoto adds the `x-amz-meta-` prefix to each key.
+    pub fn into_metadata(self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        metadata.insert("line-count".to_string(), self.lines.to_string());
+        metadata.insert("word-count".to_string(), self.words.to_string());
+        metadata.insert("char-count".to_string(), self.chars.to_string());
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_count_multi_line_text() {
+        assert_eq!(
+            text_stats("Firstname Lastname\nsecond line\n\n名前 です\n"),
+            TextStats {
+                lines: 4,
+                words: 6,
+                chars: 38,
+            }
+        );
+        assert_eq!(
+            text_stats("no trailing\r\nnewline"),
+            TextStats {
+                lines: 2,
+                words: 3,
+                chars: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn can_count_empty_text() {
+        assert_eq!(
+            text_stats(""),
+            TextStats {
+                lines: 0,
+                words: 0,
+                chars: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn can_convert_to_metadata() {
+        let metadata = text_stats("a b\nc").into_metadata();
+        assert_eq!(metadata["line-count"], "2");
+        assert_eq!(metadata["word-count"], "3");
+        assert_eq!(metadata["char-count"], "5");
+    }
+}
@@ -0,0 +1,65 @@
+use crate::LambdaError;
+
+/// A read-time rewrite of an object's text; the stored object is never touched.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Transform {
+    Uppercase,
+    Lowercase,
+    Redact,
+}
+
+impl Transform {
+    pub fn parse(name: &str) -> Result<Transform, LambdaError> {
+        match name {
+            "uppercase" => Ok(Transform::Uppercase),
+            "lowercase" => Ok(Transform::Lowercase),
+            "redact" => Ok(Transform::Redact),
+            _ => Err(LambdaError::UnknownTransform(name.to_string())),
+        }
+    }
+}
+
+/// `Redact` masks everything but whitespace, so the shape of the text survives.
+pub fn apply_transform(transform: Transform, text: &str) -> String {
+    match transform {
+        Transform::Uppercase => text.to_uppercase(),
+        Transform::Lowercase => text.to_lowercase(),
+        Transform::Redact => text
+            .chars()
+            .map(|c| if c.is_whitespace() { c } else { '*' })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_parse_transform() {
+        assert_eq!(Transform::parse("uppercase"), Ok(Transform::Uppercase));
+        assert_eq!(Transform::parse("lowercase"), Ok(Transform::Lowercase));
+        assert_eq!(Transform::parse("redact"), Ok(Transform::Redact));
+        assert_eq!(
+            Transform::parse("reverse"),
+            Err(LambdaError::UnknownTransform("reverse".to_string()))
+        );
+    }
+
+    #[test]
+    fn can_apply_transform() {
+        let text = "Firstname Lastname";
+        assert_eq!(
+            apply_transform(Transform::Uppercase, text),
+            "FIRSTNAME LASTNAME"
+        );
+        assert_eq!(
+            apply_transform(Transform::Lowercase, text),
+            "firstname lastname"
+        );
+        assert_eq!(
+            apply_transform(Transform::Redact, text),
+            "********* ********"
+        );
+    }
+}
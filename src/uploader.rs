@@ -0,0 +1,819 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    GetObjectError, GetObjectRequest, HeadObjectError, HeadObjectRequest, Object, PutObjectError,
+    PutObjectRequest, S3Client, S3,
+};
+use tokio::io::AsyncReadExt;
+
+use crate::inventory::list_all_objects;
+use crate::precondition::is_precondition_failed;
+use crate::s3_object::S3Object;
+use crate::{is_retriable_s3_error, with_retry, LambdaError};
+
+// every object this function writes can be fetched from its URL
+pub const UPLOAD_ACL: &str = "public-read";
+
+pub struct UploadParams {
+    pub bucket: String,
+    pub key: String,
+    pub body: Vec<u8>,
+    pub cache_control: Option<String>,
+    pub content_type: Option<String>,
+    pub content_language: Option<String>,
+    // `gzip` when the body was compressed before upload
+    pub content_encoding: Option<String>,
+    pub expires: Option<String>,
+    pub website_redirect_location: Option<String>,
+    // user metadata, sent as `x-amz-meta-<name>` headers
+    pub metadata: Option<HashMap<String, String>>,
+    // URL-encoded `name=value&...`, sent as `x-amz-tagging`
+    pub tagging: Option<String>,
+    pub server_side_encryption: Option<String>,
+    pub ssekms_key_id: Option<String>,
+}
+
+/// A failed PutObject with the object it was for. `is_retryable` is the default
+/// classification, without the extra `RETRIABLE_STATUS_CODES`.
+#[derive(Debug, PartialEq)]
+pub struct S3UploadError {
+    pub bucket: String,
+    pub key: String,
+    pub cause: String,
+    pub is_retryable: bool,
+}
+
+impl fmt::Display for S3UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PutObject s3://{}/{} failed: {}",
+            self.bucket, self.key, self.cause
+        )
+    }
+}
+
+impl std::error::Error for S3UploadError {}
+
+// PutObject has no modeled errors, so `PutObjectError` has no variants and
+// everything S3 rejects arrives as `Unknown` with the raw response
+impl From<(RusotoError<PutObjectError>, &UploadParams)> for S3UploadError {
+    fn from((err, params): (RusotoError<PutObjectError>, &UploadParams)) -> S3UploadError {
+        let is_retryable = is_retriable_s3_error(&err, &[]);
+        let cause = match &err {
+            RusotoError::Unknown(response) => {
+                format!("{} {}", response.status, response.body_as_str().trim())
+            }
+            err => err.to_string(),
+        };
+        S3UploadError {
+            bucket: params.bucket.clone(),
+            key: params.key.clone(),
+            cause,
+            is_retryable,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UploadReceipt {
+    pub etag: Option<String>,
+    // what S3 says it applied, which may differ from what was asked for
+    pub server_side_encryption: Option<String>,
+    pub ssekms_key_id: Option<String>,
+}
+
+/// A downloaded object with the headers it was stored with.
+#[derive(Debug, Default, PartialEq)]
+pub struct StoredObject {
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+}
+
+/// The storage side of a put, so handlers can be tested without an S3 client.
+/// Failures the caller should see as 4xx are returned as a `LambdaError`.
+#[async_trait]
+pub trait Uploader: Send + Sync {
+    async fn upload(&self, req: UploadParams) -> Result<UploadReceipt>;
+
+    async fn exists(&self, object: &S3Object) -> Result<bool> {
+        Ok(self.etag(object).await?.is_some())
+    }
+
+    /// ETag of the stored object, or `None` when there is no object at `key`.
+    async fn etag(&self, object: &S3Object) -> Result<Option<String>>;
+
+    /// Body of the stored object, or `None` when there is no object at `key`.
+    async fn download(&self, object: &S3Object) -> Result<Option<Vec<u8>>>;
+
+    /// Body and stored headers of the object; unlike `download`, a missing key is an error.
+    async fn fetch(&self, object: &S3Object) -> Result<StoredObject>;
+
+    /// Stored size in bytes, without loading the body.
+    async fn size(&self, object: &S3Object) -> Result<u64>;
+
+    /// Every object under `prefix`, in key order.
+    async fn list(&self, bucket: &str, prefix: Option<&str>) -> Result<Vec<Object>>;
+
+    /// Time spent building the underlying client during this invocation, for timing
+    /// breakdowns; a client built up front in `main` costs nothing here.
+    fn client_build_us(&self) -> u64 {
+        0
+    }
+}
+
+pub struct S3Uploader {
+    s3: S3Client,
+    max_retries: u32,
+    retriable_status_codes: Vec<u16>,
+    deadline: Option<Instant>,
+}
+
+impl S3Uploader {
+    pub fn new(s3: S3Client, max_retries: u32, deadline: Option<Instant>) -> S3Uploader {
+        S3Uploader {
+            s3,
+            max_retries,
+            retriable_status_codes: Vec::new(),
+            deadline,
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> S3Uploader {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Extra HTTP status codes to retry on top of the default set.
+    pub fn with_retriable_status_codes(mut self, codes: Vec<u16>) -> S3Uploader {
+        self.retriable_status_codes = codes;
+        self
+    }
+}
+
+#[async_trait]
+impl Uploader for S3Uploader {
+    async fn upload(&self, req: UploadParams) -> Result<UploadReceipt> {
+        // the request owns a stream, so it is rebuilt for every attempt
+        let result = with_retry(
+            self.max_retries,
+            self.deadline,
+            |err| is_retriable_s3_error(err, &self.retriable_status_codes),
+            || self.s3.put_object(build_put_request(&req)),
+        )
+        .await;
+        match result {
+            Ok(output) => Ok(UploadReceipt {
+                etag: output.e_tag,
+                server_side_encryption: output.server_side_encryption,
+                ssekms_key_id: output.ssekms_key_id,
+            }),
+            Err(err) if is_precondition_failed(&err) => Err(LambdaError::PreconditionFailed.into()),
+            Err(err) => Err(S3UploadError::from((err, &req)).into()),
+        }
+    }
+
+    async fn etag(&self, object: &S3Object) -> Result<Option<String>> {
+        let result = self
+            .s3
+            .head_object(HeadObjectRequest {
+                bucket: object.bucket.clone(),
+                key: object.key.clone(),
+                ..Default::default()
+            })
+            .await;
+        match result {
+            Ok(output) => Ok(Some(output.e_tag.unwrap_or_default())),
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(None),
+            // HEAD responses carry no error body, so a missing key surfaces as a bare 404
+            Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn download(&self, object: &S3Object) -> Result<Option<Vec<u8>>> {
+        let result = self
+            .s3
+            .get_object(GetObjectRequest {
+                bucket: object.bucket.clone(),
+                key: object.key.clone(),
+                ..Default::default()
+            })
+            .await;
+        let output = match result {
+            Ok(output) => output,
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut body = Vec::new();
+        if let Some(stream) = output.body {
+            stream.into_async_read().read_to_end(&mut body).await?;
+        }
+        Ok(Some(body))
+    }
+
+    async fn fetch(&self, object: &S3Object) -> Result<StoredObject> {
+        let output = self
+            .s3
+            .get_object(GetObjectRequest {
+                bucket: object.bucket.clone(),
+                key: object.key.clone(),
+                ..Default::default()
+            })
+            .await?;
+        let mut body = Vec::new();
+        if let Some(stream) = output.body {
+            stream.into_async_read().read_to_end(&mut body).await?;
+        }
+        Ok(StoredObject {
+            body,
+            content_type: output.content_type,
+            content_encoding: output.content_encoding,
+        })
+    }
+
+    async fn size(&self, object: &S3Object) -> Result<u64> {
+        let output = self
+            .s3
+            .head_object(HeadObjectRequest {
+                bucket: object.bucket.clone(),
+                key: object.key.clone(),
+                ..Default::default()
+            })
+            .await?;
+        Ok(output.content_length.unwrap_or(0).max(0) as u64)
+    }
+
+    async fn list(&self, bucket: &str, prefix: Option<&str>) -> Result<Vec<Object>> {
+        list_all_objects(&self.s3, bucket, prefix).await
+    }
+}
+
+/// The PutObject call for `params`; uploads are always publicly readable.
+pub fn build_put_request(params: &UploadParams) -> PutObjectRequest {
+    PutObjectRequest {
+        bucket: params.bucket.clone(),
+        key: params.key.clone(),
+        body: Some(params.body.clone().into()),
+        acl: Some(UPLOAD_ACL.to_string()),
+        cache_control: params.cache_control.clone(),
+        content_type: params.content_type.clone(),
+        content_language: params.content_language.clone(),
+        content_encoding: params.content_encoding.clone(),
+        expires: params.expires.clone(),
+        website_redirect_location: params.website_redirect_location.clone(),
+        metadata: params.metadata.clone(),
+        tagging: params.tagging.clone(),
+        server_side_encryption: params.server_side_encryption.clone(),
+        ssekms_key_id: params.ssekms_key_id.clone(),
+        ..Default::default()
+    }
+}
+
+/// Helpers that only tests need.
+#[cfg(test)]
+pub mod testing {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use rusoto_s3::Object;
+
+    use super::{S3Object, StoredObject, UploadParams, UploadReceipt, Uploader};
+
+    /// Keeps uploads in a map keyed by `bucket/key`.
+    #[derive(Default, Clone)]
+    pub struct MemoryUploader {
+        pub store: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        // content type and encoding of each upload, returned by `fetch`
+        headers: Arc<Mutex<HashMap<String, (Option<String>, Option<String>)>>>,
+    }
+
+    impl MemoryUploader {
+        pub fn get(&self, bucket: &str, key: &str) -> Option<Vec<u8>> {
+            self.store
+                .lock()
+                .unwrap()
+                .get(&get_store_key(bucket, key))
+                .cloned()
+        }
+
+        pub fn insert(&self, bucket: &str, key: &str, body: &[u8]) {
+            self.insert_with_headers(bucket, key, body, None, None);
+        }
+
+        pub fn insert_with_headers(
+            &self,
+            bucket: &str,
+            key: &str,
+            body: &[u8],
+            content_type: Option<&str>,
+            content_encoding: Option<&str>,
+        ) {
+            let store_key = get_store_key(bucket, key);
+            self.headers.lock().unwrap().insert(
+                store_key.clone(),
+                (
+                    content_type.map(str::to_string),
+                    content_encoding.map(str::to_string),
+                ),
+            );
+            self.store.lock().unwrap().insert(store_key, body.to_vec());
+        }
+    }
+
+    fn get_store_key(bucket: &str, key: &str) -> String {
+        format!("{}/{}", bucket, key)
+    }
+
+    // stable per content like a real ETag, the exact value does not matter
+    pub fn get_memory_etag(body: &[u8]) -> String {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        format!("\"{:x}\"", hasher.finish())
+    }
+
+    #[async_trait]
+    impl Uploader for MemoryUploader {
+        async fn upload(&self, req: UploadParams) -> Result<UploadReceipt> {
+            let etag = get_memory_etag(&req.body);
+            self.insert_with_headers(
+                &req.bucket,
+                &req.key,
+                &req.body,
+                req.content_type.as_deref(),
+                req.content_encoding.as_deref(),
+            );
+            Ok(UploadReceipt {
+                etag: Some(etag),
+                ..Default::default()
+            })
+        }
+
+        async fn etag(&self, object: &S3Object) -> Result<Option<String>> {
+            Ok(self
+                .get(&object.bucket, &object.key)
+                .map(|body| get_memory_etag(&body)))
+        }
+
+        async fn download(&self, object: &S3Object) -> Result<Option<Vec<u8>>> {
+            Ok(self.get(&object.bucket, &object.key))
+        }
+
+        async fn fetch(&self, object: &S3Object) -> Result<StoredObject> {
+            let body = self
+                .get(&object.bucket, &object.key)
+                .ok_or_else(|| anyhow!("NoSuchKey: {}", object.key))?;
+            let (content_type, content_encoding) = self
+                .headers
+                .lock()
+                .unwrap()
+                .get(&get_store_key(&object.bucket, &object.key))
+                .cloned()
+                .unwrap_or_default();
+            Ok(StoredObject {
+                body,
+                content_type,
+                content_encoding,
+            })
+        }
+
+        async fn size(&self, object: &S3Object) -> Result<u64> {
+            self.get(&object.bucket, &object.key)
+                .map(|body| body.len() as u64)
+                .ok_or_else(|| anyhow!("NoSuchKey: {}", object.key))
+        }
+
+        async fn list(&self, bucket: &str, prefix: Option<&str>) -> Result<Vec<Object>> {
+            let bucket_prefix = get_store_key(bucket, "");
+            let prefix = prefix.unwrap_or_default();
+            let mut objects: Vec<Object> = self
+                .store
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(store_key, body)| {
+                    let key = store_key.strip_prefix(bucket_prefix.as_str())?;
+                    if !key.starts_with(prefix) {
+                        return None;
+                    }
+                    Some(Object {
+                        key: Some(key.to_string()),
+                        size: Some(body.len() as i64),
+                        e_tag: Some(get_memory_etag(body)),
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            objects.sort_by(|a, b| a.key.cmp(&b.key));
+            Ok(objects)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::MemoryUploader;
+    use super::*;
+    use crate::mock::testing::BinaryBodyDispatcher;
+    use crate::mock::{S3MockResponseBuilder, MOCK_ETAG};
+    use rusoto_core::DispatchSignedRequest;
+    use rusoto_core::Region;
+    use rusoto_mock::{
+        MockCredentialsProvider, MockRequestDispatcher, MultipleMockRequestDispatcher,
+    };
+
+    fn get_s3_uploader<D>(dispatcher: D) -> S3Uploader
+    where
+        D: DispatchSignedRequest + Send + Sync + 'static,
+    {
+        let s3 = S3Client::new_with(dispatcher, MockCredentialsProvider, Region::ApNortheast1);
+        S3Uploader::new(s3, 0, None)
+    }
+
+    fn get_params(key: &str, body: &str) -> UploadParams {
+        UploadParams {
+            bucket: "test-bucket".to_string(),
+            key: key.to_string(),
+            body: body.as_bytes().to_vec(),
+            cache_control: None,
+            content_type: None,
+            content_language: None,
+            content_encoding: None,
+            expires: None,
+            website_redirect_location: None,
+            metadata: None,
+            tagging: None,
+            server_side_encryption: None,
+            ssekms_key_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn can_build_put_request() {
+        let params = UploadParams {
+            cache_control: Some("no-cache".to_string()),
+            content_type: Some("text/plain; charset=utf-8".to_string()),
+            expires: Some("Sun, 06 Nov 1994 08:49:37 GMT".to_string()),
+            ..get_params("test.txt", "Firstname")
+        };
+        let request = build_put_request(&params);
+        assert_eq!(
+            request.expires,
+            Some("Sun, 06 Nov 1994 08:49:37 GMT".to_string())
+        );
+        assert_eq!(request.bucket, "test-bucket");
+        assert_eq!(request.key, "test.txt");
+        assert_eq!(request.acl, Some("public-read".to_string()));
+        assert_eq!(request.cache_control, Some("no-cache".to_string()));
+        assert_eq!(
+            request.content_type,
+            Some("text/plain; charset=utf-8".to_string())
+        );
+        assert_eq!(request.website_redirect_location, None);
+        let mut body = Vec::new();
+        request
+            .body
+            .expect("expected Some(_) value")
+            .into_async_read()
+            .read_to_end(&mut body)
+            .await
+            .unwrap();
+        assert_eq!(body, b"Firstname");
+    }
+
+    #[tokio::test]
+    async fn can_build_put_request_content_encoding() {
+        let request = build_put_request(&get_params("test.txt", "Firstname"));
+        assert_eq!(request.content_encoding, None);
+        let mut body = Vec::new();
+        request
+            .body
+            .expect("expected Some(_) value")
+            .into_async_read()
+            .read_to_end(&mut body)
+            .await
+            .unwrap();
+        assert_eq!(body, b"Firstname");
+
+        let params = UploadParams {
+            content_encoding: Some("gzip".to_string()),
+            ..get_params("test.txt", "Firstname")
+        };
+        assert_eq!(
+            build_put_request(&params).content_encoding,
+            Some("gzip".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_upload() {
+        let uploader = get_s3_uploader(S3MockResponseBuilder::put_object_success(MOCK_ETAG));
+        let receipt = uploader
+            .upload(get_params("test.txt", "Firstname"))
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(receipt.etag, Some(MOCK_ETAG.to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_retry_configured_status_codes() {
+        let get_dispatcher = || {
+            MultipleMockRequestDispatcher::new(vec![
+                MockRequestDispatcher::with_status(425),
+                S3MockResponseBuilder::put_object_success(MOCK_ETAG),
+            ])
+        };
+        let uploader = S3Uploader {
+            max_retries: 1,
+            ..get_s3_uploader(get_dispatcher())
+        };
+        assert!(uploader
+            .upload(get_params("test.txt", "Firstname"))
+            .await
+            .is_err());
+
+        let uploader = S3Uploader {
+            max_retries: 1,
+            ..get_s3_uploader(get_dispatcher())
+        }
+        .with_retriable_status_codes(vec![429, 425]);
+        let receipt = uploader
+            .upload(get_params("test.txt", "Firstname"))
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(receipt.etag, Some(MOCK_ETAG.to_string()));
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_forward_website_redirect() {
+        let uploader = get_s3_uploader(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("x-amz-website-redirect-location"),
+                    Some(&vec![b"/docs/index.html".to_vec()])
+                );
+            }),
+        );
+        let params = UploadParams {
+            website_redirect_location: Some("/docs/index.html".to_string()),
+            ..get_params("index.html", "")
+        };
+        assert!(uploader.upload(params).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_forward_metadata() {
+        let uploader = get_s3_uploader(
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("x-amz-meta-line-count"),
+                    Some(&vec![b"2".to_vec()])
+                );
+            }),
+        );
+        let mut metadata = HashMap::new();
+        metadata.insert("line-count".to_string(), "2".to_string());
+        let params = UploadParams {
+            metadata: Some(metadata),
+            ..get_params("test.txt", "first\nsecond")
+        };
+        assert!(uploader.upload(params).await.is_ok());
+    }
+
+    fn get_unknown_error(status: u16) -> RusotoError<PutObjectError> {
+        RusotoError::Unknown(rusoto_core::request::BufferedHttpResponse {
+            status: http::StatusCode::from_u16(status).unwrap(),
+            body: "<Error><Code>Test</Code></Error>\n".into(),
+            headers: Default::default(),
+        })
+    }
+
+    #[test]
+    fn can_classify_s3_upload_errors() {
+        let cases = vec![
+            (
+                RusotoError::HttpDispatch(rusoto_core::request::HttpDispatchError::new(
+                    "connection reset".to_string(),
+                )),
+                true,
+            ),
+            (get_unknown_error(500), true),
+            (get_unknown_error(503), true),
+            (get_unknown_error(429), true),
+            (get_unknown_error(403), false),
+            (get_unknown_error(404), false),
+            (
+                RusotoError::Credentials(rusoto_core::credential::CredentialsError::new(
+                    "no credentials",
+                )),
+                false,
+            ),
+            (RusotoError::Validation("bad key".to_string()), false),
+            (RusotoError::ParseError("bad xml".to_string()), false),
+            (RusotoError::Blocking, false),
+        ];
+        let params = get_params("test.txt", "Firstname");
+        for (err, is_retryable) in cases {
+            let err = S3UploadError::from((err, &params));
+            assert_eq!(err.is_retryable, is_retryable, "{}", err);
+            assert_eq!(err.bucket, "test-bucket");
+            assert_eq!(err.key, "test.txt");
+        }
+    }
+
+    #[test]
+    fn can_format_s3_upload_error() {
+        let err = S3UploadError::from((get_unknown_error(403), &get_params("test.txt", "")));
+        assert_eq!(
+            err.to_string(),
+            "PutObject s3://test-bucket/test.txt failed: 403 Forbidden <Error><Code>Test</Code></Error>"
+        );
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_wrap_put_object_error() {
+        let uploader = get_s3_uploader(S3MockResponseBuilder::put_object_error(
+            "AccessDenied",
+            "Access Denied",
+        ));
+        let err = uploader
+            .upload(get_params("test.txt", "Firstname"))
+            .await
+            .expect_err("expected Err(_) value");
+        let err = err.downcast::<S3UploadError>().unwrap();
+        assert_eq!(err.key, "test.txt");
+        assert!(!err.is_retryable);
+        assert!(err.cause.contains("AccessDenied"));
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_report_precondition_failed() {
+        let uploader = get_s3_uploader(S3MockResponseBuilder::put_object_error(
+            "PreconditionFailed",
+            "At least one of the pre-conditions you specified did not hold",
+        ));
+        let err = uploader
+            .upload(get_params("test.txt", "Firstname"))
+            .await
+            .expect_err("expected Err(_) value");
+        assert_eq!(
+            err.downcast::<LambdaError>().unwrap(),
+            LambdaError::PreconditionFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_check_existence() {
+        let uploader =
+            get_s3_uploader(MockRequestDispatcher::default().with_header("ETag", MOCK_ETAG));
+        assert!(uploader
+            .exists(&S3Object::new("test-bucket", "test.txt"))
+            .await
+            .unwrap());
+        let uploader = get_s3_uploader(MockRequestDispatcher::with_status(404));
+        assert!(!uploader
+            .exists(&S3Object::new("test-bucket", "test.txt"))
+            .await
+            .unwrap());
+        let uploader = get_s3_uploader(MockRequestDispatcher::with_status(403));
+        assert!(uploader
+            .exists(&S3Object::new("test-bucket", "test.txt"))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_download() {
+        let uploader = get_s3_uploader(MockRequestDispatcher::default().with_body("Firstname"));
+        assert_eq!(
+            uploader
+                .download(&S3Object::new("test-bucket", "test.txt"))
+                .await
+                .unwrap(),
+            Some(b"Firstname".to_vec())
+        );
+        let uploader = get_s3_uploader(S3MockResponseBuilder::put_object_error(
+            "NoSuchKey",
+            "The specified key does not exist.",
+        ));
+        assert_eq!(
+            uploader
+                .download(&S3Object::new("test-bucket", "test.txt"))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_fetch_with_headers() {
+        let uploader = get_s3_uploader(
+            BinaryBodyDispatcher::new(b"Firstname".to_vec())
+                .with_header("Content-Type", "text/plain")
+                .with_header("Content-Encoding", "gzip"),
+        );
+        let object = uploader
+            .fetch(&S3Object::new("test-bucket", "test.txt"))
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(
+            object,
+            StoredObject {
+                body: b"Firstname".to_vec(),
+                content_type: Some("text/plain".to_string()),
+                content_encoding: Some("gzip".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_read_size() {
+        let uploader =
+            get_s3_uploader(MockRequestDispatcher::default().with_header("Content-Length", "9"));
+        assert_eq!(
+            uploader
+                .size(&S3Object::new("test-bucket", "test.txt"))
+                .await
+                .expect("expected Ok(_) value"),
+            9
+        );
+    }
+
+    #[tokio::test]
+    async fn can_s3_uploader_list() {
+        let uploader = get_s3_uploader(MockRequestDispatcher::default().with_body(
+            "<ListBucketResult><Contents><Key>a.txt</Key><Size>9</Size></Contents><IsTruncated>false</IsTruncated></ListBucketResult>",
+        ));
+        let objects = uploader
+            .list("test-bucket", None)
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].key, Some("a.txt".to_string()));
+        assert_eq!(objects[0].size, Some(9));
+    }
+
+    #[tokio::test]
+    async fn can_memory_uploader_fetch_and_list() {
+        let uploader = MemoryUploader::default();
+        uploader
+            .upload(UploadParams {
+                content_type: Some("text/plain".to_string()),
+                ..get_params("logs/b.txt", "Firstname")
+            })
+            .await
+            .expect("expected Ok(_) value");
+        uploader.insert("test-bucket", "logs/a.txt", b"Lastname");
+        uploader.insert("test-bucket", "other.txt", b"");
+        uploader.insert("other-bucket", "logs/c.txt", b"");
+        let object = uploader
+            .fetch(&S3Object::new("test-bucket", "logs/b.txt"))
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(object.body, b"Firstname".to_vec());
+        assert_eq!(object.content_type, Some("text/plain".to_string()));
+        assert!(uploader
+            .fetch(&S3Object::new("test-bucket", "missing.txt"))
+            .await
+            .is_err());
+        let keys: Vec<_> = uploader
+            .list("test-bucket", Some("logs/"))
+            .await
+            .expect("expected Ok(_) value")
+            .into_iter()
+            .map(|object| object.key.unwrap_or_default())
+            .collect();
+        assert_eq!(keys, vec!["logs/a.txt", "logs/b.txt"]);
+    }
+
+    #[tokio::test]
+    async fn can_memory_uploader_store_uploads() {
+        let uploader = MemoryUploader::default();
+        assert!(!uploader
+            .exists(&S3Object::new("test-bucket", "test.txt"))
+            .await
+            .unwrap());
+        uploader
+            .upload(get_params("test.txt", "Firstname"))
+            .await
+            .expect("expected Ok(_) value");
+        assert!(uploader
+            .exists(&S3Object::new("test-bucket", "test.txt"))
+            .await
+            .unwrap());
+        assert_eq!(
+            uploader.get("test-bucket", "test.txt"),
+            Some(b"Firstname".to_vec())
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use rusoto_core::request::{DispatchSignedRequest, DispatchSignedRequestFuture};
+use rusoto_core::signature::SignedRequest;
+
+pub const DEFAULT_USER_AGENT: &str = concat!("lambda-rust-sample/", env!("CARGO_PKG_VERSION"));
+
+/// Sets `User-Agent` on every request so S3 access logs show which function
+/// made the call. rusoto only fills in its own user agent when none is present.
+pub struct UserAgentDispatcher<D> {
+    inner: D,
+    user_agent: String,
+}
+
+impl<D> UserAgentDispatcher<D> {
+    pub fn new(inner: D, user_agent: String) -> UserAgentDispatcher<D> {
+        UserAgentDispatcher { inner, user_agent }
+    }
+}
+
+impl<D: DispatchSignedRequest> DispatchSignedRequest for UserAgentDispatcher<D> {
+    fn dispatch(
+        &self,
+        mut request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        request.headers.remove("user-agent");
+        request.add_header("user-agent", &self.user_agent);
+        self.inner.dispatch(request, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{S3MockResponseBuilder, MOCK_ETAG};
+    use rusoto_core::Region;
+    use rusoto_mock::MockCredentialsProvider;
+    use rusoto_s3::{PutObjectRequest, S3Client, S3};
+
+    #[tokio::test]
+    async fn can_send_custom_user_agent() {
+        let dispatcher =
+            S3MockResponseBuilder::put_object_success(MOCK_ETAG).with_request_checker(|request| {
+                assert_eq!(
+                    request.headers.get("user-agent"),
+                    Some(&vec![b"lambda-rust-sample/test".to_vec()])
+                );
+            });
+        let s3 = S3Client::new_with(
+            UserAgentDispatcher::new(dispatcher, "lambda-rust-sample/test".to_string()),
+            MockCredentialsProvider,
+            Region::ApNortheast1,
+        );
+        let output = s3
+            .put_object(PutObjectRequest::default())
+            .await
+            .expect("expected Ok(_) value");
+        assert_eq!(output.e_tag, Some(MOCK_ETAG.to_string()));
+    }
+
+    #[test]
+    fn can_build_default_user_agent() {
+        assert!(DEFAULT_USER_AGENT.starts_with("lambda-rust-sample/"));
+    }
+}
@@ -0,0 +1,53 @@
+//! Repository tasks, run with `cargo xtask <task>`.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const USAGE: &str = "usage: cargo xtask check-deny";
+
+fn main() {
+    let task = env::args().nth(1);
+    let result = match task.as_deref() {
+        Some("check-deny") => check_deny(&project_root()),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// Runs `cargo deny check` against deny.toml, which needs `cargo install cargo-deny`.
+fn check_deny(root: &Path) -> Result<(), String> {
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(&["deny", "check"])
+        .current_dir(root)
+        .status()
+        .map_err(|err| format!("failed to run cargo deny: {}", err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("cargo deny check failed with {}", status))
+    }
+}
+
+// xtask lives one level below the repository root
+fn project_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask has a parent directory")
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // needs cargo-deny and the registry index, so it only runs in the deny CI job
+    #[test]
+    #[ignore]
+    fn can_pass_deny_policy() {
+        assert_eq!(check_deny(&project_root()), Ok(()));
+    }
+}